@@ -0,0 +1,413 @@
+use crate::ast::{AggregateFunction, Expr, Select, SelectItem};
+use crate::eval::{compare, eval, expr_type, EvalError};
+use crate::record::serialize_row;
+use crate::schema::{Column, DataType, Row, Schema};
+use crate::value::Value;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+/// Works out the aggregates of a SELECT over groups of rows.
+///
+/// Rows are grouped by hashing their GROUP BY values. Each group turns into one row laid out as
+/// `schema`: the columns of the table, holding the values of the first row of the group, followed
+/// by one column per aggregate. Expressions over groups are rewritten to read their aggregates
+/// from those columns, after which they evaluate like any other expression.
+///
+/// Like SQLite, columns that are neither grouped by nor inside an aggregate may still be
+/// selected: they read the values of the first row of each group.
+#[derive(Debug)]
+pub struct Grouping {
+    group_by: Vec<Expr>,
+    /// Every distinct aggregate the query computes.
+    aggregates: Vec<Expr>,
+    /// The schema of the table the rows come from.
+    source: Schema,
+    schema: Schema,
+}
+
+impl Grouping {
+    /// `None` when the query neither groups its rows nor computes any aggregate, in which case
+    /// rows go through one by one.
+    pub fn new(select: &Select, schema: &Schema) -> Result<Option<Grouping>, EvalError> {
+        if let Some(aggregate) = select.selection.as_ref().and_then(Expr::find_aggregate) {
+            return Err(EvalError::MisusedAggregate(aggregate.to_string()));
+        }
+        for expr in select.group_by.iter() {
+            if let Some(aggregate) = expr.find_aggregate() {
+                return Err(EvalError::MisusedAggregate(aggregate.to_string()));
+            }
+            expr_type(expr, schema)?;
+        }
+
+        let mut aggregates = Vec::new();
+        let items = select.projection.iter().filter_map(|item| match item {
+            SelectItem::Wildcard | SelectItem::TableWildcard(_) => None,
+            SelectItem::Expr { expr, .. } => Some(expr),
+        });
+        let ordering = select.order_by.iter().map(|ordering| &ordering.expr);
+        for expr in items.chain(&select.having).chain(ordering) {
+            collect_aggregates(expr, schema, &mut aggregates)?;
+        }
+        if select.group_by.is_empty() && aggregates.is_empty() && select.having.is_none() {
+            return Ok(None);
+        }
+
+        // The columns of the table are only there to be read, they aren't keys of anything.
+        let mut columns: Vec<Column> = schema.columns.iter().map(Column::unconstrained).collect();
+        for (i, aggregate) in aggregates.iter().enumerate() {
+            let data_type = expr_type(aggregate, schema)?.unwrap_or(DataType::Integer);
+            columns.push(Column::new(&aggregate_column(i), data_type));
+        }
+
+        Ok(Some(Grouping {
+            group_by: select.group_by.clone(),
+            aggregates,
+            source: schema.clone(),
+            schema: Schema { columns },
+        }))
+    }
+
+    /// How the rows of each group are laid out.
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Rewrites `expr` to be evaluated against the rows of groups instead of the rows of the table.
+    pub fn rewrite(&self, expr: &Expr) -> Expr {
+        let rewrite = |expr: &Expr| Box::new(self.rewrite(expr));
+        match expr {
+            Expr::Literal(_) | Expr::Column { .. } => expr.clone(),
+            Expr::Unary { op, expr } => Expr::Unary {
+                op: *op,
+                expr: rewrite(expr),
+            },
+            Expr::Binary { left, op, right } => Expr::Binary {
+                left: rewrite(left),
+                op: *op,
+                right: rewrite(right),
+            },
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: rewrite(expr),
+                negated: *negated,
+            },
+            Expr::Aggregate { .. } => match self.aggregates.iter().position(|a| a == expr) {
+                Some(index) => Expr::Column {
+                    table: None,
+                    column: aggregate_column(index),
+                },
+                None => unreachable!("every aggregate of the query was collected up front"),
+            },
+        }
+    }
+
+    /// Groups `rows` and works out the aggregates of each group, giving one row per group in the
+    /// order the groups were first seen. A query without GROUP BY always gives a single row, even
+    /// when there are no rows at all.
+    pub fn group<E: From<EvalError>>(
+        &self,
+        rows: impl IntoIterator<Item = Result<Row, E>>,
+    ) -> Result<Vec<Row>, E> {
+        let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut groups: Vec<Group> = Vec::new();
+        for row in rows {
+            let row = row?;
+            let mut key = Vec::with_capacity(self.group_by.len());
+            for expr in self.group_by.iter() {
+                key.push(eval(expr, &self.source, &row)?);
+            }
+
+            // The key is hashed in its encoded form, which tells every value apart.
+            let slot = *index
+                .entry(serialize_row(&Row::new(key)))
+                .or_insert_with(|| {
+                    groups.push(Group::new(row.clone(), &self.aggregates));
+                    groups.len() - 1
+                });
+            for accumulator in groups[slot].accumulators.iter_mut() {
+                accumulator.add(&self.source, &row)?;
+            }
+        }
+
+        if groups.is_empty() && self.group_by.is_empty() {
+            let nulls = Row::new(vec![Value::Null; self.source.columns.len()]);
+            groups.push(Group::new(nulls, &self.aggregates));
+        }
+        Ok(groups.into_iter().map(Group::finish).collect())
+    }
+}
+
+/// Adds every aggregate within `expr` to `aggregates`, unless it is there already.
+fn collect_aggregates(
+    expr: &Expr,
+    schema: &Schema,
+    aggregates: &mut Vec<Expr>,
+) -> Result<(), EvalError> {
+    match expr {
+        Expr::Literal(_) | Expr::Column { .. } => Ok(()),
+        Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } => {
+            collect_aggregates(expr, schema, aggregates)
+        }
+        Expr::Binary { left, right, .. } => {
+            collect_aggregates(left, schema, aggregates)?;
+            collect_aggregates(right, schema, aggregates)
+        }
+        Expr::Aggregate { .. } => {
+            // Checks the argument, which can't hold another aggregate.
+            expr_type(expr, schema)?;
+            if !aggregates.contains(expr) {
+                aggregates.push(expr.clone());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The name of the column of a group row holding its `index`th aggregate. Names can't start with
+/// `#` in SQL, so it never clashes with a column of the table.
+fn aggregate_column(index: usize) -> String {
+    format!("#{}", index)
+}
+
+struct Group {
+    /// The first row of the group, which columns outside of aggregates are read from.
+    first: Row,
+    accumulators: Vec<Accumulator>,
+}
+
+impl Group {
+    fn new(first: Row, aggregates: &[Expr]) -> Group {
+        Group {
+            first,
+            accumulators: aggregates.iter().map(Accumulator::new).collect(),
+        }
+    }
+
+    fn finish(self) -> Row {
+        let mut values = self.first.values;
+        values.extend(self.accumulators.into_iter().map(Accumulator::finish));
+        Row::new(values)
+    }
+}
+
+/// Works out one aggregate over the rows of a group as they come in. NULLs are left out of
+/// everything but `COUNT(*)`.
+struct Accumulator {
+    function: AggregateFunction,
+    arg: Option<Expr>,
+    /// The values counted so far, when only distinct ones count.
+    seen: Option<HashSet<Vec<u8>>>,
+    count: i64,
+    /// The sum, smallest or largest value so far. NULL until a value is counted.
+    value: Value,
+}
+
+impl Accumulator {
+    fn new(aggregate: &Expr) -> Accumulator {
+        let (function, arg, distinct) = match aggregate {
+            Expr::Aggregate {
+                function,
+                arg,
+                distinct,
+            } => (*function, arg.as_deref().cloned(), *distinct),
+            _ => unreachable!("only aggregates are accumulated"),
+        };
+        Accumulator {
+            function,
+            arg,
+            seen: distinct.then(HashSet::new),
+            count: 0,
+            value: Value::Null,
+        }
+    }
+
+    fn add(&mut self, schema: &Schema, row: &Row) -> Result<(), EvalError> {
+        let value = match &self.arg {
+            Some(arg) => eval(arg, schema, row)?,
+            None => {
+                self.count += 1;
+                return Ok(());
+            }
+        };
+        if value.is_null() {
+            return Ok(());
+        }
+        if let Some(seen) = self.seen.as_mut() {
+            if !seen.insert(serialize_row(&Row::new(vec![value.clone()]))) {
+                return Ok(());
+            }
+        }
+
+        self.count += 1;
+        let current = mem::take(&mut self.value);
+        self.value = match self.function {
+            AggregateFunction::Count => Value::Null,
+            AggregateFunction::Sum | AggregateFunction::Avg => self.sum(current, value)?,
+            AggregateFunction::Min => self.keep(current, value, Ordering::Less)?,
+            AggregateFunction::Max => self.keep(current, value, Ordering::Greater)?,
+        };
+        Ok(())
+    }
+
+    /// Adds `value` to the running sum. Averages are summed as reals so they can't overflow.
+    fn sum(&self, current: Value, value: Value) -> Result<Value, EvalError> {
+        let value = match (self.function, value) {
+            (AggregateFunction::Avg, Value::Integer(i)) => Value::Real(i as f64),
+            (_, value @ (Value::Integer(_) | Value::Real(_))) => value,
+            (_, value) => {
+                return Err(EvalError::TypeMismatch(format!(
+                    "{} can't add up {} values",
+                    self.function.as_str(),
+                    value.type_name()
+                )))
+            }
+        };
+        match (current, value) {
+            (Value::Null, value) => Ok(value),
+            (Value::Integer(sum), Value::Integer(i)) => sum
+                .checked_add(i)
+                .map(Value::Integer)
+                .ok_or(EvalError::Overflow),
+            (Value::Integer(sum), Value::Real(r)) => Ok(Value::Real(sum as f64 + r)),
+            (Value::Real(sum), Value::Integer(i)) => Ok(Value::Real(sum + i as f64)),
+            (Value::Real(sum), Value::Real(r)) => Ok(Value::Real(sum + r)),
+            (current, _) => unreachable!("sums are always numbers, not {:?}", current),
+        }
+    }
+
+    /// Keeps whichever of the two values is `wanted` compared to the other.
+    fn keep(&self, current: Value, value: Value, wanted: Ordering) -> Result<Value, EvalError> {
+        if current.is_null() {
+            return Ok(value);
+        }
+        match compare(&value, &current) {
+            Some(ordering) if ordering == wanted => Ok(value),
+            Some(_) => Ok(current),
+            None => Err(EvalError::TypeMismatch(format!(
+                "{} can't compare {} with {}",
+                self.function.as_str(),
+                value.type_name(),
+                current.type_name()
+            ))),
+        }
+    }
+
+    fn finish(self) -> Value {
+        match (self.function, self.value) {
+            (AggregateFunction::Count, _) => Value::Integer(self.count),
+            (AggregateFunction::Avg, Value::Real(sum)) => Value::Real(sum / self.count as f64),
+            (_, value) => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Statement;
+    use crate::parser::prepare;
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Column::new("team", DataType::Text),
+            Column::new("points", DataType::Integer),
+        ])
+        .unwrap()
+    }
+
+    fn row(team: &str, points: Option<i64>) -> Row {
+        Row::new(vec![
+            Value::Text(team.to_string()),
+            points.map_or(Value::Null, Value::Integer),
+        ])
+    }
+
+    /// Helper method to group rows of the test schema by a query's GROUP BY, returning the
+    /// aggregates of each group.
+    fn aggregates(cmd: &str, rows: Vec<Row>) -> Result<Vec<Vec<Value>>, EvalError> {
+        let select = match prepare(cmd).unwrap() {
+            Statement::Select(select) => select,
+            other => panic!("Expected a select, got {:?}", other),
+        };
+        let grouping = Grouping::new(&select, &schema())?.unwrap();
+        let groups = grouping.group(rows.into_iter().map(Ok::<_, EvalError>))?;
+        Ok(groups
+            .into_iter()
+            .map(|group| group.values[2..].to_vec())
+            .collect())
+    }
+
+    #[test]
+    fn aggregates_over_groups() {
+        let rows = vec![
+            row("a", Some(3)),
+            row("b", None),
+            row("a", Some(3)),
+            row("a", Some(5)),
+            row("b", Some(-1)),
+        ];
+        let groups = aggregates(
+            "select count(*), count(points), count(distinct points), sum(points), \
+             avg(points), min(points), max(points) from t group by team",
+            rows,
+        )
+        .unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                vec![
+                    Value::Integer(3),
+                    Value::Integer(3),
+                    Value::Integer(2),
+                    Value::Integer(11),
+                    Value::Real(11.0 / 3.0),
+                    Value::Integer(3),
+                    Value::Integer(5),
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Integer(1),
+                    Value::Integer(1),
+                    Value::Integer(-1),
+                    Value::Real(-1.0),
+                    Value::Integer(-1),
+                    Value::Integer(-1),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregates_without_rows() {
+        let groups = aggregates("select count(*), sum(points), max(team) from t", Vec::new());
+        assert_eq!(
+            groups,
+            Ok(vec![vec![Value::Integer(0), Value::Null, Value::Null]])
+        );
+        assert_eq!(
+            aggregates("select count(*) from t group by team", Vec::new()),
+            Ok(Vec::new())
+        );
+    }
+
+    #[test]
+    fn aggregate_errors() {
+        let overflow = vec![row("a", Some(i64::MAX)), row("a", Some(1))];
+        assert_eq!(
+            aggregates("select sum(points) from t", overflow),
+            Err(EvalError::Overflow)
+        );
+        assert!(matches!(
+            aggregates("select sum(team) from t", vec![row("a", None)]),
+            Err(EvalError::TypeMismatch(_))
+        ));
+        assert_eq!(
+            aggregates("select count(*) from t where count(*) > 1", Vec::new()),
+            Err(EvalError::MisusedAggregate("COUNT(*)".to_string()))
+        );
+        assert_eq!(
+            aggregates("select sum(max(points)) from t", Vec::new()),
+            Err(EvalError::MisusedAggregate("SUM(MAX(points))".to_string()))
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use crate::schema::Column;
+use crate::value::Value;
+use std::fmt;
+
+/// A parsed SQL statement.
+// Statements are parsed one at a time and handed straight on, so a SELECT being a lot bigger than
+// the others doesn't cost anything worth boxing it for.
+#[allow(clippy::large_enum_variant)]
+#[derive(PartialEq, Debug, Clone)]
+pub enum Statement {
+    Insert(Insert),
+    Select(Select),
+    Update(Update),
+    Delete(Delete),
+    CreateTable(CreateTable),
+    DropTable(DropTable),
+    /// `BEGIN [TRANSACTION]`
+    Begin,
+    /// `COMMIT [TRANSACTION]`
+    Commit,
+    /// `ROLLBACK [TRANSACTION]`
+    Rollback,
+    /// `SAVEPOINT <name>`
+    Savepoint(String),
+    /// `ROLLBACK [TRANSACTION] TO [SAVEPOINT] <name>`
+    RollbackTo(String),
+    /// `RELEASE [SAVEPOINT] <name>`
+    Release(String),
+}
+
+impl Default for Statement {
+    fn default() -> Self {
+        Statement::Select(Select::default())
+    }
+}
+
+/// `INSERT INTO <table> [(<column>, ...)] VALUES (<expr>, ...)[, ...]`
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Insert {
+    pub table: String,
+    /// The columns that each row of values fills in. All columns, in order, when `None`.
+    pub columns: Option<Vec<String>>,
+    pub rows: Vec<Vec<Expr>>,
+}
+
+/// `SELECT <item>, ... FROM <table> [[AS] <alias>] [<join> ...] [WHERE <expr>]
+/// [GROUP BY <expr>, ...] [HAVING <expr>] [ORDER BY <ordering>, ...]
+/// [LIMIT <expr> [OFFSET <expr>]]`
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Select {
+    /// What each row of the result holds, in order.
+    pub projection: Vec<SelectItem>,
+    pub table: String,
+    /// The name the query refers to `table` by. The table's own name when `None`.
+    pub alias: Option<String>,
+    /// The tables combined with `table`, joined left to right.
+    pub joins: Vec<Join>,
+    /// Only rows for which this evaluates to true are returned. Everything is returned when `None`.
+    pub selection: Option<Expr>,
+    /// Rows that agree on every one of these make up a single row of the result.
+    pub group_by: Vec<Expr>,
+    /// Only groups for which this evaluates to true are returned.
+    pub having: Option<Expr>,
+    /// The keys the result is sorted on, most significant first. Rows come back in the order the
+    /// table stores them when empty.
+    pub order_by: Vec<OrderBy>,
+    /// The most rows to return. There is no cap when `None`.
+    pub limit: Option<Expr>,
+    /// How many rows to leave out from the start of the result.
+    pub offset: Option<Expr>,
+}
+
+/// One entry of the column list of a SELECT.
+#[derive(PartialEq, Debug, Clone)]
+pub enum SelectItem {
+    /// `*`, every column of the table in the order they were defined.
+    Wildcard,
+    /// `<table>.*`, every column of one of the tables the query reads.
+    TableWildcard(String),
+    /// `<expr> [[AS] <alias>]`
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+/// `[INNER | LEFT [OUTER] | RIGHT [OUTER] | FULL [OUTER]] JOIN <table> [[AS] <alias>] ON <expr>`
+#[derive(PartialEq, Debug, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: String,
+    pub alias: Option<String>,
+    /// Pairs of rows for which this evaluates to true are combined into one row.
+    pub on: Expr,
+}
+
+/// Which rows of a join are kept even when nothing on the other side matches them. Their
+/// columns from the other side are filled in with NULLs.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum JoinKind {
+    /// Only rows that match.
+    Inner,
+    /// Every row of the left side.
+    Left,
+    /// Every row of the right side.
+    Right,
+    /// Every row of both sides.
+    Full,
+}
+
+/// `<expr> [ASC | DESC] [NULLS FIRST | NULLS LAST]` within ORDER BY.
+#[derive(PartialEq, Debug, Clone)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+    /// Whether NULLs come before every other value. Unless told otherwise NULLs sort as the
+    /// smallest value, so they come first in ascending order and last in descending order.
+    pub nulls_first: bool,
+}
+
+/// `UPDATE <table> SET <column> = <expr>[, ...] [WHERE <expr>]`
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Update {
+    pub table: String,
+    pub assignments: Vec<Assignment>,
+    /// Only rows for which this evaluates to true are changed. Every row is changed when `None`.
+    pub selection: Option<Expr>,
+}
+
+/// `<column> = <expr>` within an UPDATE. The expression sees the row's values from before the update.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Assignment {
+    pub column: String,
+    pub value: Expr,
+}
+
+/// `DELETE FROM <table> [WHERE <expr>]`
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Delete {
+    pub table: String,
+    /// Only rows for which this evaluates to true are removed. Every row is removed when `None`.
+    pub selection: Option<Expr>,
+}
+
+/// `CREATE TABLE <table> (<column> <type> [NOT NULL] [PRIMARY KEY], ...)`
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+/// `DROP TABLE <table>`
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct DropTable {
+    pub name: String,
+}
+
+/// An expression that can be evaluated against a row.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Expr {
+    Literal(Value),
+    /// `[<table>.]<column>`
+    Column {
+        /// The table the column is qualified with, if any.
+        table: Option<String>,
+        column: String,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    /// `<expr> IS [NOT] NULL`
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    /// `COUNT(*)` or `<function>([DISTINCT] <expr>)`, computed over a group of rows.
+    Aggregate {
+        function: AggregateFunction,
+        /// `None` for `COUNT(*)`.
+        arg: Option<Box<Expr>>,
+        distinct: bool,
+    },
+}
+
+impl Expr {
+    /// The first aggregate computed anywhere within the expression, if there is one.
+    pub fn find_aggregate(&self) -> Option<&Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column { .. } => None,
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } => expr.find_aggregate(),
+            Expr::Binary { left, right, .. } => {
+                left.find_aggregate().or_else(|| right.find_aggregate())
+            }
+            Expr::Aggregate { .. } => Some(self),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    /// Looks up an aggregate function by name, ignoring case.
+    pub fn from_name(name: &str) -> Option<AggregateFunction> {
+        let function = match name.to_ascii_uppercase().as_str() {
+            "COUNT" => AggregateFunction::Count,
+            "SUM" => AggregateFunction::Sum,
+            "AVG" => AggregateFunction::Avg,
+            "MIN" => AggregateFunction::Min,
+            "MAX" => AggregateFunction::Max,
+            _ => return None,
+        };
+        Some(function)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "COUNT",
+            AggregateFunction::Sum => "SUM",
+            AggregateFunction::Avg => "AVG",
+            AggregateFunction::Min => "MIN",
+            AggregateFunction::Max => "MAX",
+        }
+    }
+}
+
+/// Writes the expression back out as SQL, e.g to name the result column it produces. Nested
+/// operations are put in parentheses so the text always reads the same way it evaluates.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(Value::Text(text)) => write!(f, "'{}'", text.replace('\'', "''")),
+            Expr::Literal(Value::Boolean(true)) => write!(f, "TRUE"),
+            Expr::Literal(Value::Boolean(false)) => write!(f, "FALSE"),
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Column {
+                table: Some(table),
+                column,
+            } => write!(f, "{}.{}", table, column),
+            Expr::Column {
+                table: None,
+                column,
+            } => write!(f, "{}", column),
+            Expr::Unary { op, expr } => write!(f, "{}{}", op.as_str(), Nested(expr)),
+            Expr::Binary { left, op, right } => {
+                write!(f, "{} {} {}", Nested(left), op.as_str(), Nested(right))
+            }
+            Expr::IsNull { expr, negated } => match negated {
+                true => write!(f, "{} IS NOT NULL", Nested(expr)),
+                false => write!(f, "{} IS NULL", Nested(expr)),
+            },
+            Expr::Aggregate {
+                function,
+                arg,
+                distinct,
+            } => {
+                let distinct = if *distinct { "DISTINCT " } else { "" };
+                match arg {
+                    Some(arg) => write!(f, "{}({}{})", function.as_str(), distinct, arg),
+                    None => write!(f, "{}(*)", function.as_str()),
+                }
+            }
+        }
+    }
+}
+
+/// An expression inside another one, parenthesized unless it is a single term.
+struct Nested<'a>(&'a Expr);
+
+impl fmt::Display for Nested<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expr::Literal(_) | Expr::Column { .. } | Expr::Aggregate { .. } => {
+                write!(f, "{}", self.0)
+            }
+            expr => write!(f, "({})", expr),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum UnaryOp {
+    Not,
+    Minus,
+    Plus,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
+}
+
+impl UnaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOp::Not => "NOT ",
+            UnaryOp::Minus => "-",
+            UnaryOp::Plus => "+",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "OR",
+            BinaryOp::And => "AND",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Concat => "||",
+        }
+    }
+}
@@ -1,205 +1,1556 @@
+use crate::aggregate::Grouping;
+use crate::ast::*;
+use crate::btree::{BTree, Cursor, Key};
+use crate::error::Error;
+use crate::eval::*;
+use crate::join::Joiner;
+use crate::limits::{Limits, Scope, Usage};
+use crate::mvcc::{prune, Snapshot, Transactions, TxnId, Version};
+use crate::pager::{Pager, StorageError, CATALOG_PAGE};
 use crate::parser::*;
-use std::io;
-use std::process::exit;
-
-#[derive(PartialEq, Debug, Default)]
+use crate::query::{Page, Projection, ResultSet, SortOrder, TableScope};
+use crate::record::*;
+use crate::schema::*;
+use crate::sort::{Sorter, DEFAULT_SORT_MEMORY};
+use crate::transaction::TransactionError;
+use crate::value::Value;
+use log::{debug, trace};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::{iter, mem};
+
+/// What a statement that worked did. Failures are reported as an `Error` instead.
+#[derive(PartialEq, Debug)]
 pub enum ExecuteResult {
-    Success(Option<Vec<Row>>),
-    #[default]
-    TableFull,
+    /// Queries return their result set, other statements nothing.
+    Success(Option<ResultSet>),
+    /// The number of rows changed by a statement.
+    RowsAffected(usize),
 }
 
-/// Represents a single SQL table.
+/// Represents a single SQL table. Its rows live in a B+tree ordered by the primary key, each key
+/// holding every version of its row that a snapshot might still need.
 pub struct Table {
-    pub data: Vec<Row>,
+    pub name: String,
+    pub schema: Schema,
+    tree: BTree,
+    limits: Limits,
+    /// How much the table holds. It is only counted once a limit needs it, by reading the whole
+    /// table, and is kept up to date by every statement from then on.
+    usage: Mutex<Option<Usage>>,
+    /// The transaction that created the table. Snapshots that don't see its changes don't see the
+    /// table either. 0 for tables that were already there when the database was opened.
+    created: TxnId,
 }
 
 impl Table {
-    pub fn new() -> Table {
-        Table { data: Vec::new() }
+    fn new(entry: TableEntry) -> Table {
+        Table {
+            name: entry.name,
+            schema: entry.schema,
+            tree: BTree::open(entry.root_page),
+            limits: Limits::default(),
+            usage: Mutex::new(None),
+            created: 0,
+        }
+    }
+
+    fn usage(&self, pager: &Pager) -> Result<Usage, StorageError> {
+        if let Some(usage) = self.counted_usage() {
+            return Ok(usage);
+        }
+
+        let mut records = Vec::new();
+        let mut cursor = self.tree.seek(pager, Bound::Unbounded)?;
+        while let Some((_, chain)) = cursor.next(pager)? {
+            let versions = decode_versions(&chain)?;
+            let current = versions.iter().filter(|version| version.is_current());
+            records.extend(current.map(|version| version.record.len()));
+        }
+        let usage = Usage::of_records(records);
+        self.set_usage(Some(usage));
+        Ok(usage)
+    }
+
+    fn counted_usage(&self) -> Option<Usage> {
+        *self.usage.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_usage(&self, usage: Option<Usage>) {
+        *self.usage.lock().unwrap_or_else(PoisonError::into_inner) = usage;
+    }
+
+    /// Keeps the usage count, if there is one, in step with rows written to or removed from the
+    /// table. A statement that fails part way through leaves it to be counted again.
+    fn record_change<T>(
+        &self,
+        result: Result<T, StorageError>,
+        added: Usage,
+        removed: Usage,
+    ) -> Result<T, StorageError> {
+        let usage = match &result {
+            Ok(_) => self.counted_usage().map(|usage| usage + added - removed),
+            Err(_) => None,
+        };
+        self.set_usage(usage);
+        result
     }
-}
 
-pub fn entrypoint() {
-    let stdin = io::stdin();
-    let mut table = Table::new();
+    /// Reads the rows whose primary key falls in `range` as `snapshot` sees them, in key order,
+    /// one at a time. Tables without a primary key are always read whole.
+    fn scan<'a>(
+        &'a self,
+        pager: &'a Pager,
+        snapshot: &'a Snapshot,
+        range: &KeyRange,
+    ) -> Result<Scan<'a>, StorageError> {
+        let has_key = self.schema.primary_key().is_some();
+        let (start, end) = match has_key {
+            true => (Bound::Included(range.start), Some(range.end)),
+            false => (Bound::Unbounded, None),
+        };
+        let cursor = match has_key && range.is_empty() {
+            true => None,
+            false => Some(self.tree.seek(pager, start)?),
+        };
+        Ok(Scan {
+            table: self,
+            pager,
+            snapshot,
+            cursor,
+            end,
+        })
+    }
 
-    loop {
-        let mut input = String::new();
+    /// Every version of the row under `key`, oldest first. Empty when there is no such row.
+    fn versions(&self, pager: &Pager, key: Key) -> Result<Vec<Version>, StorageError> {
+        match self.tree.get(pager, key)? {
+            Some(chain) => decode_versions(&chain),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Stores the versions of the row under `key`, leaving out those deleted before `horizon`.
+    fn write_versions(
+        &self,
+        pager: &mut Pager,
+        key: Key,
+        mut versions: Vec<Version>,
+        horizon: TxnId,
+    ) -> Result<(), StorageError> {
+        prune(&mut versions, horizon);
+        match versions.is_empty() {
+            true => self.tree.remove(pager, key).map(drop),
+            false => self.tree.insert(pager, key, &encode_versions(&versions)),
+        }
+    }
+
+    /// Writes `row` under `key` as a new version, made by the transaction `writer` belongs to.
+    fn put(
+        &self,
+        pager: &mut Pager,
+        writer: &Writer,
+        key: Key,
+        row: &Row,
+    ) -> Result<(), StorageError> {
+        let mut versions = self.versions(pager, key)?;
+        writer.snapshot.put(&mut versions, serialize_row(row));
+        self.write_versions(pager, key, versions, writer.horizon)
+    }
 
-        println!("Please enter the SQL command: ");
-        stdin.read_line(&mut input).expect("Failed to read line.");
-        input = input.trim().to_string(); // Remove trailing newline.
+    /// Deletes the row under `key` as far as the transaction `writer` belongs to is concerned.
+    /// Snapshots taken before it commits still see the row.
+    fn delete(&self, pager: &mut Pager, writer: &Writer, key: Key) -> Result<(), StorageError> {
+        let mut versions = self.versions(pager, key)?;
+        writer.snapshot.delete(&mut versions);
+        self.write_versions(pager, key, versions, writer.horizon)
+    }
 
-        // Is a command
-        if input.starts_with('.') {
-            if let MetaCommandResult::Unrecognized = execute_command(&input) {
-                println!("Unrecognized command: {}", input);
+    /// Drops every version that no snapshot can see anymore, returning how many went.
+    fn collect_garbage(&self, pager: &mut Pager, horizon: TxnId) -> Result<usize, StorageError> {
+        let mut stale = Vec::new();
+        let mut cursor = self.tree.seek(pager, Bound::Unbounded)?;
+        while let Some((key, chain)) = cursor.next(pager)? {
+            let versions = decode_versions(&chain)?;
+            if versions
+                .iter()
+                .any(|version| version.deleted.is_some_and(|txn| txn < horizon))
+            {
+                stale.push((key, versions));
             }
-            continue; // Skip this iteration of our IO loop.
         }
 
-        let mut statement = Statement::default();
+        let mut collected = 0;
+        for (key, mut versions) in stale {
+            collected += prune(&mut versions, horizon);
+            self.write_versions(pager, key, versions, horizon)?;
+        }
+        Ok(collected)
+    }
+
+    /// The primary key of a row, for tables that have one.
+    fn primary_key_of(&self, row: &Row) -> Option<Key> {
+        let index = self.schema.primary_key()?;
+        match row.values[index] {
+            Value::Integer(key) => Some(key),
+            _ => unreachable!("primary keys are NOT NULL INTEGER columns"),
+        }
+    }
+
+    /// Where the next row of a table without a primary key goes: after every row already there.
+    fn next_row_number(&self, pager: &mut Pager) -> Result<Key, StorageError> {
+        match self.tree.last(pager)? {
+            Some(last) => Ok(last + 1),
+            None => Ok(1),
+        }
+    }
+
+    /// Whether `snapshot` sees a row under `key`.
+    fn contains(
+        &self,
+        pager: &mut Pager,
+        snapshot: &Snapshot,
+        key: Key,
+    ) -> Result<bool, StorageError> {
+        let versions = self.versions(pager, key)?;
+        Ok(snapshot.visible(&versions).is_some())
+    }
+
+    fn duplicate_key(&self, key: Key) -> Error {
+        let column = match self.schema.primary_key() {
+            Some(index) => self.schema.columns[index].name.clone(),
+            None => unreachable!("only tables with a primary key can have duplicate keys"),
+        };
+        Error::Constraint(ConstraintViolation::PrimaryKey {
+            table: self.name.clone(),
+            column,
+            key,
+        })
+    }
+}
+
+impl Clone for Table {
+    fn clone(&self) -> Table {
+        Table {
+            name: self.name.clone(),
+            schema: self.schema.clone(),
+            tree: self.tree,
+            limits: self.limits,
+            usage: Mutex::new(self.counted_usage()),
+            created: self.created,
+        }
+    }
+}
+
+/// The rows of a table that a snapshot sees, read from the tree one at a time.
+struct Scan<'a> {
+    table: &'a Table,
+    pager: &'a Pager,
+    snapshot: &'a Snapshot,
+    /// `None` once there is nothing more to read.
+    cursor: Option<Cursor>,
+    /// The last key to read, for tables with a primary key.
+    end: Option<Key>,
+}
 
-        match prepare_statement(&input, &mut statement) {
-            PrepareResult::Success => {
-                println!("Successfully prepared statement...")
+impl Scan<'_> {
+    fn next_row(&mut self) -> Result<Option<(Key, Row)>, StorageError> {
+        while let Some(cursor) = self.cursor.as_mut() {
+            let (key, chain) = match cursor.next(self.pager)? {
+                Some(entry) if self.end.is_none_or(|end| entry.0 <= end) => entry,
+                _ => {
+                    self.cursor = None;
+                    break;
+                }
+            };
+            let versions = decode_versions(&chain)?;
+            if let Some(version) = self.snapshot.visible(&versions) {
+                return Ok(Some((
+                    key,
+                    decode_row(&version.record, &self.table.schema)?,
+                )));
             }
-            PrepareResult::Unrecognized => {
-                println!("Unrecognized keyword at start of {}", input);
-                continue;
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for Scan<'_> {
+    type Item = Result<(Key, Row), StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.next_row();
+        if row.is_err() {
+            self.cursor = None;
+        }
+        row.transpose()
+    }
+}
+
+/// The primary keys a statement can possibly touch, from `start` to `end` inclusive.
+#[derive(PartialEq, Debug)]
+struct KeyRange {
+    start: i64,
+    end: i64,
+}
+
+impl KeyRange {
+    fn all() -> KeyRange {
+        KeyRange {
+            start: i64::MIN,
+            end: i64::MAX,
+        }
+    }
+
+    /// Works out which keys a WHERE clause can match, from the comparisons against the primary key
+    /// that it requires to be true. Anything it can't make sense of leaves the range as it is,
+    /// since the clause is still checked against every row that is read.
+    fn from_selection(selection: &Option<Expr>, schema: &Schema) -> KeyRange {
+        let mut range = KeyRange::all();
+        if let (Some(selection), Some(index)) = (selection, schema.primary_key()) {
+            range.narrow(selection, &schema.columns[index].name);
+        }
+        range
+    }
+
+    fn narrow(&mut self, expr: &Expr, key: &str) {
+        let (left, op, right) = match expr {
+            Expr::Binary { left, op, right } => (&**left, *op, &**right),
+            _ => return,
+        };
+        if op == BinaryOp::And {
+            self.narrow(left, key);
+            self.narrow(right, key);
+            return;
+        }
+
+        // Put the key on the left, flipping the comparison if it was written the other way round.
+        let (op, other) = match (left, right) {
+            (Expr::Column { column, .. }, other) if column == key => (op, other),
+            (other, Expr::Column { column, .. }) if column == key => match op {
+                BinaryOp::Lt => (BinaryOp::Gt, other),
+                BinaryOp::LtEq => (BinaryOp::GtEq, other),
+                BinaryOp::Gt => (BinaryOp::Lt, other),
+                BinaryOp::GtEq => (BinaryOp::LtEq, other),
+                op => (op, other),
+            },
+            _ => return,
+        };
+        let value = match eval_constant(other) {
+            Ok(Value::Integer(value)) => value,
+            _ => return,
+        };
+
+        match op {
+            BinaryOp::Eq => {
+                self.start = self.start.max(value);
+                self.end = self.end.min(value);
             }
-            PrepareResult::SyntaxError => {
-                println!("Unrecognized syntax for command. Did you follow the proper format?");
-                continue;
+            BinaryOp::GtEq => self.start = self.start.max(value),
+            BinaryOp::LtEq => self.end = self.end.min(value),
+            BinaryOp::Gt => match value.checked_add(1) {
+                Some(value) => self.start = self.start.max(value),
+                None => *self = KeyRange::empty(),
+            },
+            BinaryOp::Lt => match value.checked_sub(1) {
+                Some(value) => self.end = self.end.min(value),
+                None => *self = KeyRange::empty(),
+            },
+            _ => {}
+        }
+    }
+
+    fn empty() -> KeyRange {
+        KeyRange {
+            start: i64::MAX,
+            end: i64::MIN,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start > self.end
+    }
+}
+
+/// The limits on a database as a whole, along with every table that counts towards them.
+struct Quota<'a> {
+    limits: Limits,
+    tables: &'a BTreeMap<String, Table>,
+}
+
+impl Quota<'_> {
+    /// Checks that `table` has room for `added` once `removed` is gone, both under its own limits
+    /// and under those of the whole database.
+    fn check(
+        &self,
+        table: &Table,
+        pager: &mut Pager,
+        added: Usage,
+        removed: Usage,
+    ) -> Result<(), Error> {
+        let storage_error = Error::Storage;
+        if !table.limits.is_unlimited() {
+            let usage = table.usage(pager).map_err(storage_error)?;
+            let checked = table
+                .limits
+                .check(usage + added - removed, &table.name, Scope::Table);
+            checked.map_err(Error::TableFull)?;
+        }
+
+        if !self.limits.is_unlimited() {
+            let mut usage = Usage::default();
+            for other in self.tables.values() {
+                usage = usage + other.usage(pager).map_err(storage_error)?;
             }
+            let checked = self
+                .limits
+                .check(usage + added - removed, &table.name, Scope::Database);
+            checked.map_err(Error::TableFull)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a statement that changes rows writes them: through the snapshot of the transaction it runs
+/// in, clearing away versions deleted before `horizon` as it goes.
+struct Writer {
+    snapshot: Snapshot,
+    horizon: TxnId,
+}
+
+impl Writer {
+    /// The transaction the writer belongs to.
+    fn txn(&self) -> TxnId {
+        self.snapshot
+            .id()
+            .expect("writers always have a transaction of their own")
+    }
+}
+
+/// A dropped table that snapshots taken before the drop committed may still read. Its pages are
+/// only freed once none of them are left.
+#[derive(Clone)]
+struct Retired {
+    table: Table,
+    dropped: TxnId,
+}
+
+/// A set of named tables, stored in a single database file.
+/// Statements find the table they work on through this catalog.
+pub struct Database {
+    pager: Pager,
+    tables: BTreeMap<String, Table>,
+    /// Tables that have been dropped but whose pages may still be read.
+    retired: Vec<Retired>,
+    /// Whether tables were created or dropped since the catalog was last written out.
+    catalog_dirty: bool,
+    limits: Limits,
+    /// Where the open transaction can be rolled back to: its start, then each savepoint inside it.
+    savepoints: Vec<Savepoint>,
+    /// Behind a lock of its own, so that readers sharing the database can take snapshots.
+    txns: Mutex<Transactions>,
+    /// The snapshot of the transaction that is writing, if there is one. A statement that changes
+    /// rows outside of a transaction writes in one of its own, which ends when it commits.
+    writer: Option<Snapshot>,
+    /// How much memory a query may sort in before it spills to temporary files.
+    sort_memory: usize,
+}
+
+/// How the tables stood at the start of a transaction or at a savepoint. The pager keeps track of
+/// their rows.
+struct Savepoint {
+    /// `None` for the start of the transaction.
+    name: Option<String>,
+    tables: BTreeMap<String, Table>,
+    retired: Vec<Retired>,
+    catalog_dirty: bool,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new()
+    }
+}
+
+impl Database {
+    /// A database that only lives in memory, nothing is ever written to disk.
+    pub fn new() -> Database {
+        Database {
+            pager: Pager::memory(),
+            tables: BTreeMap::new(),
+            retired: Vec::new(),
+            catalog_dirty: false,
+            limits: Limits::default(),
+            savepoints: Vec::new(),
+            txns: Mutex::new(Transactions::new(1)),
+            writer: None,
+            sort_memory: DEFAULT_SORT_MEMORY,
         }
+    }
+
+    /// Opens the database file at `path`, creating it if it doesn't exist yet.
+    /// Only the catalog is read up front; pages holding rows are read as statements need them.
+    pub fn open(path: impl AsRef<Path>) -> Result<Database, StorageError> {
+        let mut pager = Pager::open(path)?;
+        let tables = load_tables(&mut pager)?;
+        let txns = Transactions::new(pager.next_txn());
+
+        Ok(Database {
+            pager,
+            tables,
+            retired: Vec::new(),
+            catalog_dirty: false,
+            limits: Limits::default(),
+            savepoints: Vec::new(),
+            txns: Mutex::new(txns),
+            writer: None,
+            sort_memory: DEFAULT_SORT_MEMORY,
+        })
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.get(name)
+    }
+
+    /// The names of every table in the database, in alphabetical order.
+    pub fn table_names(&self) -> Vec<&str> {
+        self.tables.keys().map(String::as_str).collect()
+    }
 
-        match execute_statement(statement, &mut table) {
-            ExecuteResult::Success(_) => {
-                println!("Successfully executed...")
+    /// Caps how much the database may hold across all of its tables. Limits aren't stored in the
+    /// database file, so they have to be set again each time it is opened.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Sets how many bytes of rows a query may hold in memory while sorting them. Past that,
+    /// sorted runs are written out to temporary files and merged back together at the end.
+    pub fn set_sort_memory(&mut self, bytes: usize) {
+        self.sort_memory = bytes;
+    }
+
+    /// Sets how many pages of the database file to keep in memory. Past that, pages are read from
+    /// the file again as they are needed.
+    pub fn set_cache_pages(&mut self, pages: usize) {
+        self.pager.set_cache_pages(pages);
+    }
+
+    /// Caps how much the table `name` may hold, on top of the limits on the whole database.
+    pub fn set_table_limits(&mut self, name: &str, limits: Limits) -> Result<(), SchemaError> {
+        match self.tables.get_mut(name) {
+            Some(table) => {
+                table.limits = limits;
+                Ok(())
             }
-            ExecuteResult::TableFull => {
-                println!("Table is full...")
+            None => Err(SchemaError::UnknownTable(name.to_string())),
+        }
+    }
+
+    /// Looks up the table a statement refers to, along with the pager holding its rows and the
+    /// limits it has to stay within.
+    fn resolve(&mut self, name: &str) -> Result<(&Table, &mut Pager, Quota<'_>), Error> {
+        let quota = Quota {
+            limits: self.limits,
+            tables: &self.tables,
+        };
+        match self.tables.get(name) {
+            Some(table) => Ok((table, &mut self.pager, quota)),
+            None => Err(Error::Schema(SchemaError::UnknownTable(name.to_string()))),
+        }
+    }
+
+    /// Looks up every table a query reads, in the order it joins them, along with the pager
+    /// holding their rows. Tables are the ones `snapshot` sees, which leaves out tables created
+    /// by a transaction that hasn't committed and keeps the ones it dropped.
+    fn resolve_query(
+        &self,
+        select: &Select,
+        snapshot: &Snapshot,
+    ) -> Result<(Vec<&Table>, &Pager), Error> {
+        let names = iter::once(&select.table).chain(select.joins.iter().map(|join| &join.table));
+        let mut tables = Vec::with_capacity(select.joins.len() + 1);
+        for name in names {
+            let current = self
+                .tables
+                .get(name)
+                .filter(|table| snapshot.sees(table.created));
+            let retired = || {
+                self.retired
+                    .iter()
+                    .find(|retired| {
+                        retired.table.name == *name
+                            && snapshot.sees(retired.table.created)
+                            && !snapshot.sees(retired.dropped)
+                    })
+                    .map(|retired| &retired.table)
+            };
+            match current.or_else(retired) {
+                Some(table) => tables.push(table),
+                None => return Err(Error::Schema(SchemaError::UnknownTable(name.clone()))),
             }
         }
+        Ok((tables, &self.pager))
+    }
+
+    /// A snapshot of everything committed so far. Reading through it with `execute_read` gives
+    /// the same rows for as long as it is kept, whatever is written in the meantime, so readers
+    /// can hold on to one while a transaction goes on writing.
+    pub fn snapshot(&self) -> Snapshot {
+        self.transactions().snapshot()
+    }
+
+    /// The snapshot a statement that only reads sees: the open transaction's, so that it sees its
+    /// own changes, or else a fresh one.
+    fn reader(&mut self) -> Snapshot {
+        match &self.writer {
+            Some(snapshot) => snapshot.clone(),
+            None => self.transactions().snapshot(),
+        }
+    }
+
+    /// What a statement that changes rows writes through, starting a transaction for it if none
+    /// is writing yet.
+    fn writer(&mut self) -> Writer {
+        let snapshot = match &self.writer {
+            Some(snapshot) => snapshot.clone(),
+            None => self.start(),
+        };
+        Writer {
+            snapshot,
+            horizon: self.transactions().horizon(),
+        }
+    }
+
+    fn start(&mut self) -> Snapshot {
+        let mut txns = self.transactions();
+        let snapshot = txns.start();
+        let next_txn = txns.next_id();
+        drop(txns);
+        self.pager.set_next_txn(next_txn);
+        self.writer = Some(snapshot.clone());
+        snapshot
     }
+
+    fn transactions(&self) -> MutexGuard<'_, Transactions> {
+        self.txns.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Ends the transaction that was writing, if any, so that its versions count as final.
+    fn finish(&mut self) {
+        if let Some(snapshot) = self.writer.take() {
+            self.transactions().finish(&snapshot);
+        }
+    }
+
+    /// Clears away row versions that no snapshot in use can see anymore, returning how many went.
+    /// Outside of a transaction this commits, just like a statement would.
+    pub fn collect_garbage(&mut self) -> Result<usize, StorageError> {
+        let horizon = self.transactions().horizon();
+        let mut collected = 0;
+        for table in self.tables.values() {
+            collected += table.collect_garbage(&mut self.pager, horizon)?;
+        }
+        if !self.in_transaction() {
+            self.commit()?;
+        }
+        Ok(collected)
+    }
+
+    /// Parses and runs a single SQL statement, just like `execute_statement` does.
+    pub fn execute(&mut self, sql: &str) -> Result<ExecuteResult, Error> {
+        execute_statement(prepare(sql)?, self)
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.pager.in_transaction()
+    }
+
+    /// Starts a transaction. Statements run after this are only written to disk together, by
+    /// `commit`, and `rollback` undoes all of them.
+    pub fn begin(&mut self) -> Result<(), TransactionError> {
+        if self.in_transaction() {
+            return Err(TransactionError::AlreadyActive);
+        }
+        self.pager.begin();
+        self.start();
+        self.savepoints.clear();
+        self.mark(None);
+        Ok(())
+    }
+
+    /// Marks a point inside the open transaction that `rollback_to` can go back to. Savepoints
+    /// nest, and may share a name, in which case the latest one is meant.
+    pub fn savepoint(&mut self, name: &str) -> Result<(), TransactionError> {
+        if !self.in_transaction() {
+            return Err(TransactionError::NotActive);
+        }
+        self.pager.savepoint();
+        self.mark(Some(name.to_string()));
+        Ok(())
+    }
+
+    fn mark(&mut self, name: Option<String>) {
+        self.savepoints.push(Savepoint {
+            name,
+            tables: self.tables.clone(),
+            retired: self.retired.clone(),
+            catalog_dirty: self.catalog_dirty,
+        });
+    }
+
+    /// How deep in the stack of savepoints the latest one called `name` is.
+    fn find_savepoint(&self, name: &str) -> Result<usize, TransactionError> {
+        if !self.in_transaction() {
+            return Err(TransactionError::NotActive);
+        }
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name.as_deref() == Some(name))
+            .ok_or_else(|| TransactionError::UnknownSavepoint(name.to_string()))
+    }
+
+    /// Throws away every change made since the savepoint `name`, and the savepoints made after it.
+    /// The savepoint itself stays, and the transaction carries on.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), TransactionError> {
+        let depth = self.find_savepoint(name)?;
+        self.pager.rollback_to(depth);
+        self.savepoints.truncate(depth + 1);
+        self.restore(depth);
+        Ok(())
+    }
+
+    /// Forgets the savepoint `name` and the ones made after it. Their changes are kept, and are
+    /// undone by rolling back to an earlier savepoint or the whole transaction.
+    pub fn release(&mut self, name: &str) -> Result<(), TransactionError> {
+        let depth = self.find_savepoint(name)?;
+        self.pager.release(depth);
+        self.savepoints.truncate(depth);
+        Ok(())
+    }
+
+    /// Throws away every change made since the open transaction began, putting every table back
+    /// the way it was, and ends the transaction.
+    pub fn rollback(&mut self) {
+        if !self.savepoints.is_empty() {
+            self.restore(0);
+        }
+        self.pager.rollback();
+        self.savepoints.clear();
+        self.finish();
+    }
+
+    fn restore(&mut self, depth: usize) {
+        let savepoint = &self.savepoints[depth];
+        self.tables = savepoint.tables.clone();
+        self.retired = savepoint.retired.clone();
+        self.catalog_dirty = savepoint.catalog_dirty;
+    }
+
+    /// Writes every change made since the last commit to disk, ending the open transaction if
+    /// there is one.
+    pub fn commit(&mut self) -> Result<(), StorageError> {
+        if self.catalog_dirty {
+            let entries: Vec<TableEntry> = self
+                .tables
+                .values()
+                .map(|table| TableEntry {
+                    name: table.name.clone(),
+                    schema: table.schema.clone(),
+                    root_page: table.tree.root(),
+                })
+                .collect();
+            self.pager
+                .write_chain(CATALOG_PAGE, &encode_catalog(&entries))?;
+            self.catalog_dirty = false;
+        }
+        self.free_retired()?;
+
+        self.savepoints.clear();
+        let flushed = self.pager.flush();
+        self.finish();
+        flushed
+    }
+
+    /// Frees the pages of dropped tables that every snapshot in use knows are gone.
+    fn free_retired(&mut self) -> Result<(), StorageError> {
+        let horizon = self.transactions().horizon();
+        let (gone, kept) = mem::take(&mut self.retired)
+            .into_iter()
+            .partition(|retired| retired.dropped < horizon);
+        self.retired = kept;
+        for retired in gone {
+            retired.table.tree.destroy(&mut self.pager)?;
+        }
+        Ok(())
+    }
+
+    /// Shuts the database down: a transaction that was never committed is thrown away, and
+    /// everything else is written to the database file. Dropping the database does the same, but
+    /// can't report anything that goes wrong.
+    pub fn close(mut self) -> Result<(), StorageError> {
+        self.rollback();
+        self.checkpoint()
+    }
+
+    /// Commits, then folds the write-ahead log back into the database file.
+    pub fn checkpoint(&mut self) -> Result<(), StorageError> {
+        self.commit()?;
+        self.pager.checkpoint()
+    }
+}
+
+/// Reads the catalog, setting up every table it lists.
+fn load_tables(pager: &mut Pager) -> Result<BTreeMap<String, Table>, StorageError> {
+    let entries = decode_catalog(&pager.read_chain(CATALOG_PAGE)?)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.name.clone(), Table::new(entry)))
+        .collect())
 }
 
-/// Used to execute non-sql CLI commands, e.g exit.
+/// Used to execute non-sql CLI commands, e.g exit. Nothing here ends the process; `.exit` is
+/// only reported back, for the REPL to act on.
 pub fn execute_command(cmd: &str) -> MetaCommandResult {
     if cmd == ".exit" {
-        exit(0);
+        MetaCommandResult::Exit
     } else {
         MetaCommandResult::Unrecognized
     }
 }
 
-pub fn execute_statement(statement: Statement, tb: &mut Table) -> ExecuteResult {
-    match statement.cmd {
-        StatementType::Insert => {
-            println!("Performing an insert...");
-            execute_insert(statement, tb)
+/// Runs a statement against the database. Outside of a transaction each statement commits on its
+/// own, so whatever it changed is on disk by the time this returns. A statement that fails there
+/// is rolled back instead, so it either happens as a whole or not at all.
+pub fn execute_statement(statement: Statement, db: &mut Database) -> Result<ExecuteResult, Error> {
+    let writes = matches!(
+        statement,
+        Statement::Insert(_)
+            | Statement::Update(_)
+            | Statement::Delete(_)
+            | Statement::CreateTable(_)
+            | Statement::DropTable(_)
+    );
+    // Writes are made one row at a time, so one that fails part way through has to be able to
+    // undo the rows it already wrote.
+    let implicit = writes && !db.in_transaction();
+    if implicit {
+        db.begin()?;
+    }
+
+    let result = match statement {
+        Statement::Insert(insert) => {
+            debug!("inserting into {}", insert.table);
+            let writer = db.writer();
+            db.resolve(&insert.table).and_then(|(table, pager, quota)| {
+                execute_insert(insert, table, pager, &writer, quota)
+            })
+        }
+        Statement::Select(select) => {
+            debug!("selecting from {}", select.table);
+            let snapshot = db.reader();
+            let sort_memory = db.sort_memory;
+            db.resolve_query(&select, &snapshot)
+                .and_then(|(tables, pager)| {
+                    execute_select(select, &tables, pager, &snapshot, sort_memory)
+                })
+        }
+        Statement::Update(update) => {
+            debug!("updating {}", update.table);
+            let writer = db.writer();
+            db.resolve(&update.table).and_then(|(table, pager, quota)| {
+                execute_update(update, table, pager, &writer, quota)
+            })
+        }
+        Statement::Delete(delete) => {
+            debug!("deleting from {}", delete.table);
+            let writer = db.writer();
+            db.resolve(&delete.table)
+                .and_then(|(table, pager, _)| execute_delete(delete, table, pager, &writer))
+        }
+        Statement::CreateTable(create) => {
+            debug!("creating table {}", create.name);
+            execute_create_table(create, db)
+        }
+        Statement::DropTable(drop) => {
+            debug!("dropping table {}", drop.name);
+            execute_drop_table(drop, db)
+        }
+        Statement::Begin => {
+            debug!("beginning a transaction");
+            db.begin()?;
+            Ok(ExecuteResult::Success(None))
+        }
+        Statement::Commit => {
+            debug!("committing a transaction");
+            match db.in_transaction() {
+                true => {
+                    db.commit()?;
+                    Ok(ExecuteResult::Success(None))
+                }
+                false => Err(Error::Transaction(TransactionError::NotActive)),
+            }
+        }
+        Statement::Rollback => {
+            debug!("rolling back a transaction");
+            match db.in_transaction() {
+                true => {
+                    db.rollback();
+                    Ok(ExecuteResult::Success(None))
+                }
+                false => Err(Error::Transaction(TransactionError::NotActive)),
+            }
+        }
+        Statement::Savepoint(name) => {
+            debug!("setting savepoint {}", name);
+            db.savepoint(&name)?;
+            Ok(ExecuteResult::Success(None))
+        }
+        Statement::RollbackTo(name) => {
+            debug!("rolling back to savepoint {}", name);
+            db.rollback_to(&name)?;
+            Ok(ExecuteResult::Success(None))
+        }
+        Statement::Release(name) => {
+            debug!("releasing savepoint {}", name);
+            db.release(&name)?;
+            Ok(ExecuteResult::Success(None))
+        }
+    };
+
+    if implicit && result.is_err() {
+        db.rollback();
+        return result;
+    }
+    if db.in_transaction() && !implicit {
+        return result;
+    }
+    db.commit()?;
+    result
+}
+
+fn execute_create_table(create: CreateTable, db: &mut Database) -> Result<ExecuteResult, Error> {
+    if db.tables.contains_key(&create.name) {
+        return Err(Error::Schema(SchemaError::TableExists(create.name)));
+    }
+
+    let schema = Schema::new(create.columns)?;
+    let tree = BTree::create(&mut db.pager)?;
+
+    let mut table = Table::new(TableEntry {
+        name: create.name.clone(),
+        schema,
+        root_page: tree.root(),
+    });
+    table.set_usage(Some(Usage::default()));
+    table.created = db.writer().txn();
+    db.tables.insert(create.name, table);
+    db.catalog_dirty = true;
+    Ok(ExecuteResult::Success(None))
+}
+
+fn execute_drop_table(drop: DropTable, db: &mut Database) -> Result<ExecuteResult, Error> {
+    let table = match db.tables.remove(&drop.name) {
+        Some(table) => table,
+        None => return Err(Error::Schema(SchemaError::UnknownTable(drop.name))),
+    };
+    let dropped = db.writer().txn();
+    db.catalog_dirty = true;
+
+    // Snapshots taken before the drop commits still read the table, so its pages are only freed
+    // once they are all gone.
+    db.retired.push(Retired { table, dropped });
+    Ok(ExecuteResult::Success(None))
+}
+
+fn execute_insert(
+    insert: Insert,
+    table: &Table,
+    pager: &mut Pager,
+    writer: &Writer,
+    quota: Quota,
+) -> Result<ExecuteResult, Error> {
+    // Work out which column each value of a row goes into.
+    let positions = match &insert.columns {
+        None => (0..table.schema.columns.len()).collect(),
+        Some(names) => {
+            let mut positions = Vec::with_capacity(names.len());
+            for name in names.iter() {
+                let index = match table.schema.index_of(name) {
+                    Some(index) => index,
+                    None => return Err(Error::Eval(EvalError::UnknownColumn(name.clone()))),
+                };
+                if positions.contains(&index) {
+                    return Err(Error::Schema(SchemaError::DuplicateColumn(name.clone())));
+                }
+                positions.push(index);
+            }
+            positions
+        }
+    };
+
+    // Validate every row before inserting any, so a bad row doesn't leave the insert half done.
+    let mut rows = Vec::with_capacity(insert.rows.len());
+    for exprs in insert.rows.iter() {
+        if exprs.len() != positions.len() {
+            return Err(Error::Schema(SchemaError::ColumnCount {
+                expected: positions.len(),
+                found: exprs.len(),
+            }));
         }
-        StatementType::Select => {
-            println!("Performing a select...");
-            execute_select(statement, tb)
+
+        // Columns that aren't given a value are NULL.
+        let mut values = vec![Value::Null; table.schema.columns.len()];
+        for (index, expr) in positions.iter().zip(exprs) {
+            values[*index] = eval_constant(expr)?;
         }
+        rows.push(table.schema.check_row(values)?);
+    }
+
+    // Work out where each row goes, making sure no two rows end up with the same primary key.
+    let mut keys = Vec::with_capacity(rows.len());
+    let mut next_row = None;
+    for row in rows.iter() {
+        let key = match table.primary_key_of(row) {
+            Some(key) => {
+                let taken = table.contains(pager, &writer.snapshot, key)? || keys.contains(&key);
+                if taken {
+                    return Err(table.duplicate_key(key));
+                }
+                key
+            }
+            None => {
+                let key = match next_row {
+                    Some(key) => key,
+                    None => table.next_row_number(pager)?,
+                };
+                next_row = Some(key + 1);
+                key
+            }
+        };
+        keys.push(key);
     }
+
+    let added = Usage::of_records(rows.iter().map(|row| serialize_row(row).len()));
+    quota.check(table, pager, added, Usage::default())?;
+
+    let inserted = keys
+        .into_iter()
+        .zip(rows.iter())
+        .try_for_each(|(key, row)| table.put(pager, writer, key, row));
+    table.record_change(inserted, added, Usage::default())?;
+    Ok(ExecuteResult::Success(None))
+}
+
+/// Reads the rows of `table` that `selection` picks out, as `snapshot` sees them, in key order.
+/// Only rows whose primary key could match are read at all.
+fn matching_rows(
+    table: &Table,
+    pager: &Pager,
+    snapshot: &Snapshot,
+    selection: &Option<Expr>,
+) -> Result<Vec<(Key, Row)>, Error> {
+    matches(table, pager, snapshot, selection)?.collect()
 }
 
-fn execute_insert(statement: Statement, table: &mut Table) -> ExecuteResult {
-    table
-        .data
-        .push(statement.row_instance.expect("Insert is missing row data."));
+/// Like `matching_rows`, but reads the rows one at a time as they are asked for.
+fn matches<'a>(
+    table: &'a Table,
+    pager: &'a Pager,
+    snapshot: &'a Snapshot,
+    selection: &'a Option<Expr>,
+) -> Result<impl Iterator<Item = Result<(Key, Row), Error>> + 'a, Error> {
+    let range = KeyRange::from_selection(selection, &table.schema);
+    let rows = table.scan(pager, snapshot, &range)?;
+    Ok(rows.filter_map(move |entry| {
+        let (key, row) = match entry {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(err.into())),
+        };
+        // A statement without a WHERE clause works on all data in the table.
+        let selection = match selection {
+            Some(selection) => selection,
+            None => return Some(Ok((key, row))),
+        };
+        match eval_predicate(selection, &table.schema, &row) {
+            Ok(true) => Some(Ok((key, row))),
+            Ok(false) => None,
+            Err(err) => Some(Err(err.into())),
+        }
+    }))
+}
 
-    ExecuteResult::Success(None)
+/// Runs a SELECT against `snapshot`, leaving aside whatever was written after it was taken. Any
+/// number of readers can each keep their own snapshot while statements go on changing the tables.
+pub fn execute_read(
+    select: Select,
+    snapshot: &Snapshot,
+    db: &Database,
+) -> Result<ExecuteResult, Error> {
+    let sort_memory = db.sort_memory;
+    db.resolve_query(&select, snapshot)
+        .and_then(|(tables, pager)| execute_select(select, &tables, pager, snapshot, sort_memory))
 }
 
-fn execute_select(statement: Statement, table: &mut Table) -> ExecuteResult {
-    // Select didn't specify an instance. Return all data in table.
-    if statement.row_instance.is_none() {
-        for row in table.data.iter() {
-            println!("Found data: {:?}", row);
+/// Runs a SELECT over `tables`: the one it reads from followed by the ones it joins, in order.
+fn execute_select(
+    select: Select,
+    tables: &[&Table],
+    pager: &Pager,
+    snapshot: &Snapshot,
+    sort_memory: usize,
+) -> Result<ExecuteResult, Error> {
+    let aliases = iter::once(&select.alias).chain(select.joins.iter().map(|join| &join.alias));
+    let names: Vec<String> = tables
+        .iter()
+        .zip(aliases)
+        .map(|(table, alias)| alias.clone().unwrap_or_else(|| table.name.clone()))
+        .collect();
+    let schemas = tables.iter().map(|table| &table.schema);
+    let scope = TableScope::new(names.iter().map(String::as_str).zip(schemas).collect())?;
+    let select = scope.resolve(select)?;
+
+    let table = tables[0];
+    if select.joins.is_empty() {
+        // Rows come straight off the table, in key order and with the WHERE clause narrowing
+        // down which keys are read at all.
+        return query(&select, &table.schema, true, sort_memory, || {
+            let rows = matches(table, pager, snapshot, &select.selection)?;
+            Ok(rows.map(|entry| entry.map(|(_, row)| row)))
+        });
+    }
+
+    let mut schema = scope.joined_schema(0);
+    let mut joiners = Vec::with_capacity(select.joins.len());
+    for (i, join) in select.joins.iter().enumerate() {
+        let joiner = Joiner::new(
+            join.kind,
+            join.on.clone(),
+            &schema,
+            &scope.joined_schema(i + 1),
+        )?;
+        debug!("joining {} with {:?}", join.table, joiner.strategy());
+        schema = joiner.schema().clone();
+        joiners.push(joiner);
+    }
+    if let Some(selection) = &select.selection {
+        if let Some(aggregate) = selection.find_aggregate() {
+            return Err(Error::Eval(EvalError::MisusedAggregate(
+                aggregate.to_string(),
+            )));
         }
-        return ExecuteResult::Success(Some(table.data.iter().cloned().collect()));
+        expr_type(selection, &schema)?;
     }
 
-    // Select cmd specified an instance of data.
-    let target_id = statement.row_instance.as_ref().map(|row| row.id);
+    query(&select, &schema, false, sort_memory, || {
+        let mut rows = read_all(table, pager, snapshot)?;
+        for (joiner, table) in joiners.iter().zip(&tables[1..]) {
+            rows = joiner.join(rows, read_all(table, pager, snapshot)?)?;
+        }
+        let (selection, schema) = (select.selection.as_ref(), &schema);
+        Ok(rows.into_iter().filter_map(move |row| {
+            let selection = match selection {
+                Some(selection) => selection,
+                None => return Some(Ok(row)),
+            };
+            match eval_predicate(selection, schema, &row) {
+                Ok(true) => Some(Ok(row)),
+                Ok(false) => None,
+                Err(err) => Some(Err(err.into())),
+            }
+        }))
+    })
+}
+
+/// Every row of `table`, as `snapshot` sees them, in key order.
+fn read_all(table: &Table, pager: &Pager, snapshot: &Snapshot) -> Result<Vec<Row>, Error> {
+    matches(table, pager, snapshot, &None)?
+        .map(|entry| entry.map(|(_, row)| row))
+        .collect()
+}
 
-    if let Some(id) = target_id {
-        for row in table.data.iter() {
-            if row.id == id {
-                println!("Found data: {:?}", row);
+/// Groups, sorts, projects and pages the rows `read` comes up with, laid out as `schema`. `keyed`
+/// says whether they come in order of the primary key of `schema`. Everything the query refers to
+/// is checked before any row is read.
+fn query<I>(
+    select: &Select,
+    schema: &Schema,
+    keyed: bool,
+    sort_memory: usize,
+    read: impl FnOnce() -> Result<I, Error>,
+) -> Result<ExecuteResult, Error>
+where
+    I: Iterator<Item = Result<Row, Error>>,
+{
+    let grouping = Grouping::new(select, schema)?;
+    let mut projection = Projection::new(&select.projection, schema)?;
+    let page = Page::new(&select.limit, &select.offset)?;
+    let rows = read()?;
+
+    let mut result = ResultSet::default();
+    match grouping {
+        None => {
+            let order = SortOrder::new(&select.order_by, &projection, schema)?;
+            let sorted = keyed && order.follows_key(schema);
+            result.rows = arrange(rows, schema, &projection, &order, sorted, page, sort_memory)?;
+        }
+        Some(grouping) => {
+            let schema = grouping.schema();
+            projection.rewrite(|expr| grouping.rewrite(expr));
+            let order_by: Vec<OrderBy> = select
+                .order_by
+                .iter()
+                .map(|ordering| OrderBy {
+                    expr: grouping.rewrite(&ordering.expr),
+                    ..ordering.clone()
+                })
+                .collect();
+            let order = SortOrder::new(&order_by, &projection, schema)?;
+            let having = select
+                .having
+                .as_ref()
+                .map(|having| grouping.rewrite(having));
+            if let Some(having) = &having {
+                expr_type(having, schema)?;
             }
+
+            let groups = grouping.group(rows)?.into_iter().filter_map(|group| {
+                let having = match &having {
+                    Some(having) => having,
+                    None => return Some(Ok(group)),
+                };
+                match eval_predicate(having, schema, &group) {
+                    Ok(true) => Some(Ok(group)),
+                    Ok(false) => None,
+                    Err(err) => Some(Err(err.into())),
+                }
+            });
+            result.rows = arrange(
+                groups,
+                schema,
+                &projection,
+                &order,
+                false,
+                page,
+                sort_memory,
+            )?;
         }
+    }
+    trace!("found {} rows in {}", result.rows.len(), select.table);
+    result.columns = projection.columns;
+    Ok(ExecuteResult::Success(Some(result)))
+}
 
-        return ExecuteResult::Success(Some(
-            table
-                .data
-                .iter()
-                .filter(|row| row.id == id)
-                .cloned()
-                .collect(),
-        ));
+/// Turns `rows`, laid out as `schema`, into the rows of a result: sorted in `order` unless they
+/// are `sorted` already, projected and cut down to `page`.
+fn arrange(
+    rows: impl Iterator<Item = Result<Row, Error>>,
+    schema: &Schema,
+    projection: &Projection,
+    order: &SortOrder,
+    sorted: bool,
+    page: Page,
+    sort_memory: usize,
+) -> Result<Vec<Row>, Error> {
+    if order.is_empty() || sorted {
+        // Rows are in order already, so reading stops as soon as the page is full. With a WHERE
+        // clause on the key, e.g `id > 40 ORDER BY id LIMIT 20`, only the rows on the page are
+        // ever read.
+        return page.fill(rows, |row| Ok(projection.apply(schema, &row)?));
     }
 
-    ExecuteResult::Success(None)
+    // Rows are handed to the sorter as they are read, so that a sort too big for memory never
+    // has to hold every row at once.
+    let mut sorter = Sorter::new(order.directions.clone(), sort_memory);
+    if let Some(end) = page.end() {
+        sorter.keep_first(end);
+    }
+    for row in rows {
+        let row = row?;
+        let projected = projection.apply(schema, &row)?;
+        let keys = order.keys(schema, &row, &projected)?;
+        sorter.push(keys, projected)?;
+    }
+    let sorted = sorter.finish()?.map(|row| row.map_err(Error::Storage));
+    page.fill(sorted, Ok)
+}
+
+fn execute_update(
+    update: Update,
+    table: &Table,
+    pager: &mut Pager,
+    writer: &Writer,
+    quota: Quota,
+) -> Result<ExecuteResult, Error> {
+    let mut targets = Vec::with_capacity(update.assignments.len());
+    for assignment in update.assignments.iter() {
+        match table.schema.index_of(&assignment.column) {
+            Some(index) => targets.push(index),
+            None => {
+                return Err(Error::Eval(EvalError::UnknownColumn(
+                    assignment.column.clone(),
+                )))
+            }
+        }
+    }
+
+    let rows = matching_rows(table, pager, &writer.snapshot, &update.selection)?;
+
+    // Work out every change up front so that an error part way through leaves the table untouched.
+    let mut changes = Vec::with_capacity(rows.len());
+    for (key, row) in rows.iter() {
+        let mut updated = row.clone();
+        for (assignment, target) in update.assignments.iter().zip(targets.iter()) {
+            let value = eval(&assignment.value, &table.schema, row)?;
+            updated.values[*target] = table.schema.columns[*target].coerce(value)?;
+        }
+        changes.push((*key, updated));
+    }
+
+    // Rows whose primary key changed have to move within the tree. Check that none of them lands
+    // on a key that is already taken, by a row that is staying put or by another row that moves.
+    let moves = |(key, row): &(Key, Row)| match table.primary_key_of(row) {
+        Some(new_key) if new_key != *key => Some((*key, new_key)),
+        _ => None,
+    };
+    let leaving: BTreeSet<Key> = changes
+        .iter()
+        .filter_map(moves)
+        .map(|(key, _)| key)
+        .collect();
+    let mut arriving = BTreeSet::new();
+    for (_, new_key) in changes.iter().filter_map(moves) {
+        let taken =
+            table.contains(pager, &writer.snapshot, new_key)? && !leaving.contains(&new_key);
+        if taken || !arriving.insert(new_key) {
+            return Err(table.duplicate_key(new_key));
+        }
+    }
+
+    // Rows can grow, so an update may need room just like an insert does.
+    let removed = Usage::of_records(rows.iter().map(|(_, row)| serialize_row(row).len()));
+    let added = Usage::of_records(changes.iter().map(|(_, row)| serialize_row(row).len()));
+    quota.check(table, pager, added, removed)?;
+
+    let count = changes.len();
+    let applied = apply_updates(table, pager, writer, changes);
+    table.record_change(applied, added, removed)?;
+    Ok(ExecuteResult::RowsAffected(count))
+}
+
+/// Writes updated rows back to the tree. Rows whose primary key changed have to move, so they are
+/// all taken out before any are put back in, letting rows swap keys with each other.
+fn apply_updates(
+    table: &Table,
+    pager: &mut Pager,
+    writer: &Writer,
+    changes: Vec<(Key, Row)>,
+) -> Result<(), StorageError> {
+    let mut moved = Vec::new();
+    for (key, row) in changes {
+        match table.primary_key_of(&row) {
+            Some(new_key) if new_key != key => {
+                table.delete(pager, writer, key)?;
+                moved.push((new_key, row));
+            }
+            _ => table.put(pager, writer, key, &row)?,
+        }
+    }
+
+    for (key, row) in moved.iter() {
+        table.put(pager, writer, *key, row)?;
+    }
+    Ok(())
+}
+
+fn execute_delete(
+    delete: Delete,
+    table: &Table,
+    pager: &mut Pager,
+    writer: &Writer,
+) -> Result<ExecuteResult, Error> {
+    // Decide which rows go before removing any, so an error part way through deletes nothing.
+    let rows = matching_rows(table, pager, &writer.snapshot, &delete.selection)?;
+
+    let removed = Usage::of_records(rows.iter().map(|(_, row)| serialize_row(row).len()));
+    let deleted = rows
+        .iter()
+        .try_for_each(|(key, _)| table.delete(pager, writer, *key));
+    table.record_change(deleted, Usage::default(), removed)?;
+    Ok(ExecuteResult::RowsAffected(rows.len()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::value::Value;
+    use std::{env, fs};
+
+    /// Helper method to quickly run SQL commands against a database.
+    fn do_sql_cmd(db: &mut Database, cmd: &str) {
+        execute_statement(prepare(cmd).unwrap(), db).unwrap();
+    }
+
+    /// Helper method to create a database holding the users table most tests work with.
+    fn users_db() -> Database {
+        let mut db = Database::new();
+        do_sql_cmd(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        );
+        db
+    }
+
+    /// Helper method to get at the rows of the users table.
+    fn users_rows(db: &mut Database) -> Vec<Row> {
+        let statement = prepare("select * from users").unwrap();
+        match execute_statement(statement, db) {
+            Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => rows,
+            other => panic!("Expected Success with rows, got {:?}", other),
+        }
+    }
+
+    /// Helper method to build a row of the users table.
+    fn user(id: i64, username: &str, email: &str) -> Row {
+        Row::new(vec![
+            Value::Integer(id),
+            Value::Text(username.to_string()),
+            Value::Text(email.to_string()),
+        ])
+    }
+
+    // Testing that a statement whose writes fail part way through leaves nothing of itself
+    // behind, neither in memory nor on disk.
+    #[test]
+    fn failed_statement_is_rolled_back() {
+        let path = env::temp_dir().join(format!(
+            "sql_engine_backend_{}_failed_write.db",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        {
+            let mut db = Database::open(&path).unwrap();
+            do_sql_cmd(
+                &mut db,
+                "create table users (id integer primary key, username text, email text)",
+            );
+            do_sql_cmd(&mut db, "insert into users values (1, 'a', 'a@x.com')");
+
+            let values: Vec<String> = (2..200)
+                .map(|id| format!("({}, 'user{}', 'user{}@x.com')", id, id, id))
+                .collect();
+            let insert = format!("insert into users values {}", values.join(", "));
+            db.pager.fail_writes_after(Some(20));
+            assert!(matches!(
+                execute_statement(prepare(&insert).unwrap(), &mut db),
+                Err(Error::Storage(StorageError::Io(_)))
+            ));
+            db.pager.fail_writes_after(None);
+            assert_eq!(users_rows(&mut db), vec![user(1, "a", "a@x.com")]);
+
+            do_sql_cmd(&mut db, "insert into users values (2, 'b', 'b@x.com')");
+        }
+
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(
+            users_rows(&mut db),
+            vec![user(1, "a", "a@x.com"), user(2, "b", "b@x.com")]
+        );
+        drop(db);
+        let _ = fs::remove_file(&path);
+    }
+
+    // Testing that a dropped table's pages are only freed once no snapshot can read it anymore.
+    #[test]
+    fn dropped_table_waits_for_snapshots() {
+        let mut db = users_db();
+        for id in 1..200 {
+            let cmd = format!("insert into users values ({}, 'user', 'user@x.com')", id);
+            do_sql_cmd(&mut db, &cmd);
+        }
+        let pages = db.pager.page_count();
+        let snapshot = db.snapshot();
+        do_sql_cmd(&mut db, "drop table users");
+        db.collect_garbage().unwrap();
+        assert_eq!(db.retired.len(), 1);
+
+        let select = match prepare("select * from users").unwrap() {
+            Statement::Select(select) => select,
+            other => panic!("Expected a select, got {:?}", other),
+        };
+        match execute_read(select, &snapshot, &db) {
+            Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => assert_eq!(rows.len(), 199),
+            other => panic!("Expected Success with rows, got {:?}", other),
+        }
 
-    /// Helper method to quickly run SQL commands and mutate a table.
-    fn do_sql_cmd(tb: &mut Table, cmd: &str) {
-        let mut statement = Statement::default();
-        prepare_statement(cmd, &mut statement);
-        execute_statement(statement, tb);
+        drop(snapshot);
+        db.collect_garbage().unwrap();
+        assert!(db.retired.is_empty());
+        do_sql_cmd(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        );
+        for id in 1..200 {
+            let cmd = format!("insert into users values ({}, 'user', 'user@x.com')", id);
+            do_sql_cmd(&mut db, &cmd);
+        }
+        assert_eq!(db.pager.page_count(), pages);
     }
 
     // Testing whether insert command errors.
     #[test]
     fn execute_statement_insert() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
 
         assert_eq!(
-            table.data,
-            vec![Row {
-                id: 13,
-                username: "rosh".to_string(),
-                email: "kakapio@gmail.com".to_string()
-            }]
+            users_rows(&mut db),
+            vec![user(13, "rosh", "kakapio@gmail.com")]
         );
     }
 
     // Making sure our test doesn't allow everything to pass.
     #[test]
     fn execute_statement_insert_fail() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
 
         assert_ne!(
-            table.data,
-            vec![Row {
-                id: 13,
-                username: "alfred".to_string(),
-                email: "alfredddd1@gmail.com".to_string()
-            }]
+            users_rows(&mut db),
+            vec![user(13, "alfred", "alfredddd1@gmail.com")]
         );
     }
 
     // Making sure we can insert multiple things and get them back out.
     #[test]
     fn execute_multiple_insert() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-        do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
         do_sql_cmd(
-            &mut table,
-            "insert 1699 sniper_penut penutterbutter@yahoo.com",
+            &mut db,
+            "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+        );
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com')",
         );
 
         assert_eq!(
-            table.data,
+            users_rows(&mut db),
             vec![
-                Row {
-                    id: 13,
-                    username: "rosh".to_string(),
-                    email: "kakapio@gmail.com".to_string()
-                },
-                Row {
-                    id: 42,
-                    username: "stefan".to_string(),
-                    email: "stefp@sigma.com".to_string()
-                },
-                Row {
-                    id: 1699,
-                    username: "sniper_penut".to_string(),
-                    email: "penutterbutter@yahoo.com".to_string()
-                }
+                user(13, "rosh", "kakapio@gmail.com"),
+                user(42, "stefan", "stefp@sigma.com"),
+                user(1699, "sniper_penut", "penutterbutter@yahoo.com")
             ]
         );
     }
@@ -207,12 +1558,11 @@ mod tests {
     // Testing select with no rows in table
     #[test]
     fn execute_select_empty_table() {
-        let mut table = Table::new();
-        let mut statement = Statement::default();
-        prepare_statement("select", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let mut db = users_db();
+        let statement = prepare("select * from users").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 0);
         } else {
             panic!("Expected Success with empty vector");
@@ -222,14 +1572,16 @@ mod tests {
     // Testing select with specific ID that doesn't exist
     #[test]
     fn execute_select_nonexistent_id() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
 
-        let mut statement = Statement::default();
-        prepare_statement("select 42", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users where id = 42").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 0);
         } else {
             panic!("Expected Success with empty vector");
@@ -239,19 +1591,27 @@ mod tests {
     // Testing select with specific ID that exists
     #[test]
     fn execute_select_existing_id() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-        do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+        );
 
-        let mut statement = Statement::default();
-        prepare_statement("select 42", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users where id = 42").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].id, 42);
-            assert_eq!(rows[0].username, "stefan");
-            assert_eq!(rows[0].email, "stefp@sigma.com");
+            assert_eq!(rows[0].values[0], Value::Integer(42));
+            assert_eq!(rows[0].values[1], Value::Text("stefan".to_string()));
+            assert_eq!(
+                rows[0].values[2],
+                Value::Text("stefp@sigma.com".to_string())
+            );
         } else {
             panic!("Expected Success with one row");
         }
@@ -260,19 +1620,24 @@ mod tests {
     // Testing select all with multiple rows
     #[test]
     fn execute_select_all_multiple_rows() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-        do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
         do_sql_cmd(
-            &mut table,
-            "insert 1699 sniper_penut penutterbutter@yahoo.com",
+            &mut db,
+            "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+        );
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com')",
         );
 
-        let mut statement = Statement::default();
-        prepare_statement("select", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 3);
         } else {
             panic!("Expected Success with three rows");
@@ -282,73 +1647,74 @@ mod tests {
     // Testing insert with duplicate ID
     #[test]
     fn execute_insert_duplicate_id() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-        do_sql_cmd(&mut table, "insert 13 stefan stefp@sigma.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
+        let statement =
+            prepare("insert into users values (13, 'stefan', 'stefp@sigma.com')").unwrap();
 
-        // The second insert should be added as a new row
+        // The second insert is rejected and the first row is left alone
         assert_eq!(
-            table.data,
-            vec![
-                Row {
-                    id: 13,
-                    username: "rosh".to_string(),
-                    email: "kakapio@gmail.com".to_string()
-                },
-                Row {
-                    id: 13,
-                    username: "stefan".to_string(),
-                    email: "stefp@sigma.com".to_string()
-                }
-            ]
+            execute_statement(statement, &mut db),
+            Err(Error::Constraint(ConstraintViolation::PrimaryKey {
+                table: "users".to_string(),
+                column: "id".to_string(),
+                key: 13
+            }))
+        );
+        assert_eq!(
+            users_rows(&mut db),
+            vec![user(13, "rosh", "kakapio@gmail.com")]
         );
     }
 
     // Testing insert with zero ID
     #[test]
     fn execute_insert_zero_id() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 0 rosh kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (0, 'rosh', 'kakapio@gmail.com')",
+        );
 
         assert_eq!(
-            table.data,
-            vec![Row {
-                id: 0,
-                username: "rosh".to_string(),
-                email: "kakapio@gmail.com".to_string()
-            }]
+            users_rows(&mut db),
+            vec![user(0, "rosh", "kakapio@gmail.com")]
         );
     }
 
     // Testing insert with maximum u32 ID
     #[test]
     fn execute_insert_max_id() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 4294967295 rosh kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (4294967295, 'rosh', 'kakapio@gmail.com')",
+        );
 
         assert_eq!(
-            table.data,
-            vec![Row {
-                id: 4294967295,
-                username: "rosh".to_string(),
-                email: "kakapio@gmail.com".to_string()
-            }]
+            users_rows(&mut db),
+            vec![user(4294967295, "rosh", "kakapio@gmail.com")]
         );
     }
 
     // Testing select with zero ID
     #[test]
     fn execute_select_zero_id() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 0 rosh kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (0, 'rosh', 'kakapio@gmail.com')",
+        );
 
-        let mut statement = Statement::default();
-        prepare_statement("select 0", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users where id = 0").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].id, 0);
+            assert_eq!(rows[0].values[0], Value::Integer(0));
         } else {
             panic!("Expected Success with one row");
         }
@@ -357,16 +1723,18 @@ mod tests {
     // Testing select with maximum u32 ID
     #[test]
     fn execute_select_max_id() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 4294967295 rosh kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (4294967295, 'rosh', 'kakapio@gmail.com')",
+        );
 
-        let mut statement = Statement::default();
-        prepare_statement("select 4294967295", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users where id = 4294967295").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].id, 4294967295);
+            assert_eq!(rows[0].values[0], Value::Integer(4294967295));
         } else {
             panic!("Expected Success with one row");
         }
@@ -375,102 +1743,99 @@ mod tests {
     // Testing insert with special characters in username
     #[test]
     fn execute_insert_special_chars_username() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh!@#$ kakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh!@#$', 'kakapio@gmail.com')",
+        );
 
         assert_eq!(
-            table.data,
-            vec![Row {
-                id: 13,
-                username: "rosh!@#$".to_string(),
-                email: "kakapio@gmail.com".to_string()
-            }]
+            users_rows(&mut db),
+            vec![user(13, "rosh!@#$", "kakapio@gmail.com")]
         );
     }
 
     // Testing insert with special characters in email
     #[test]
     fn execute_insert_special_chars_email() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio+special@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio+special@gmail.com')",
+        );
 
         assert_eq!(
-            table.data,
-            vec![Row {
-                id: 13,
-                username: "rosh".to_string(),
-                email: "kakapio+special@gmail.com".to_string()
-            }]
+            users_rows(&mut db),
+            vec![user(13, "rosh", "kakapio+special@gmail.com")]
         );
     }
 
     // Testing insert with very long username
     #[test]
     fn execute_insert_long_username() {
-        let mut table = Table::new();
+        let mut db = users_db();
         let long_username = "a".repeat(1000);
         do_sql_cmd(
-            &mut table,
-            &format!("insert 13 {} kakapio@gmail.com", long_username),
+            &mut db,
+            &format!(
+                "insert into users values (13, '{}', 'kakapio@gmail.com')",
+                long_username
+            ),
         );
 
         assert_eq!(
-            table.data,
-            vec![Row {
-                id: 13,
-                username: long_username,
-                email: "kakapio@gmail.com".to_string()
-            }]
+            users_rows(&mut db),
+            vec![user(13, &long_username, "kakapio@gmail.com")]
         );
     }
 
     // Testing insert with very long email
     #[test]
     fn execute_insert_long_email() {
-        let mut table = Table::new();
+        let mut db = users_db();
         let long_email = format!("{}@gmail.com", "a".repeat(1000));
-        do_sql_cmd(&mut table, &format!("insert 13 rosh {}", long_email));
-
-        assert_eq!(
-            table.data,
-            vec![Row {
-                id: 13,
-                username: "rosh".to_string(),
-                email: long_email,
-            }]
+        do_sql_cmd(
+            &mut db,
+            &format!("insert into users values (13, 'rosh', '{}')", long_email),
         );
+
+        assert_eq!(users_rows(&mut db), vec![user(13, "rosh", &long_email)]);
     }
 
     // Testing multiple selects on the same table
     #[test]
     fn execute_multiple_selects() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-        do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+        let mut db = users_db();
         do_sql_cmd(
-            &mut table,
-            "insert 1699 sniper_penut penutterbutter@yahoo.com",
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+        );
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+        );
+        do_sql_cmd(
+            &mut db,
+            "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com')",
         );
 
         // Select all
-        let mut statement = Statement::default();
-        prepare_statement("select", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 3);
         } else {
             panic!("Expected Success with three rows");
         }
 
         // Select specific ID
-        let mut statement = Statement::default();
-        prepare_statement("select 42", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users where id = 42").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].id, 42);
+            assert_eq!(rows[0].values[0], Value::Integer(42));
         } else {
             panic!("Expected Success with one row");
         }
@@ -479,33 +1844,38 @@ mod tests {
     // Testing insert and select with whitespace
     #[test]
     fn execute_insert_select_whitespace() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "  insert  13  rosh  kakapio@gmail.com  ");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "  insert  into  users  values  ( 13 ,  'rosh' ,  'kakapio@gmail.com' )  ",
+        );
 
-        let mut statement = Statement::default();
-        prepare_statement("  select  13  ", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("  select  *  from  users  where  id  =  13  ").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
-            assert_eq!(rows.len(), 0);
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].values[0], Value::Integer(13));
         } else {
-            panic!("Expected Success with empty vector");
+            panic!("Expected Success with one row");
         }
     }
 
     // Testing insert and select with tab characters
     #[test]
     fn execute_insert_select_tab() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert\t13\trosh\tkakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert\tinto\tusers\tvalues\t(13,\t'rosh',\t'kakapio@gmail.com')",
+        );
 
-        let mut statement = Statement::default();
-        prepare_statement("select\t13", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select\t*\tfrom\tusers\twhere\tid\t=\t13").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].id, 13);
+            assert_eq!(rows[0].values[0], Value::Integer(13));
         } else {
             panic!("Expected Success with one row");
         }
@@ -514,18 +1884,65 @@ mod tests {
     // Testing insert and select with newline characters
     #[test]
     fn execute_insert_select_newline() {
-        let mut table = Table::new();
-        do_sql_cmd(&mut table, "insert\n13\nrosh\nkakapio@gmail.com");
+        let mut db = users_db();
+        do_sql_cmd(
+            &mut db,
+            "insert\ninto\nusers\nvalues\n(13,\n'rosh',\n'kakapio@gmail.com')",
+        );
 
-        let mut statement = Statement::default();
-        prepare_statement("select\n13", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select\n*\nfrom\nusers\nwhere\nid\n=\n13").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
             assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].id, 13);
+            assert_eq!(rows[0].values[0], Value::Integer(13));
         } else {
             panic!("Expected Success with one row");
         }
     }
+
+    /// Helper method to work out the key range of a WHERE clause on a table keyed by `id`.
+    fn range_of(clause: &str) -> KeyRange {
+        let statement = prepare(&format!("select * from t where {}", clause)).unwrap();
+        let selection = match statement {
+            Statement::Select(select) => select.selection,
+            other => panic!("Expected a select, got {:?}", other),
+        };
+
+        let mut id = Column::new("id", DataType::Integer);
+        id.primary_key = true;
+        let schema = Schema::new(vec![id, Column::new("name", DataType::Text)]).unwrap();
+        KeyRange::from_selection(&selection, &schema)
+    }
+
+    // Testing which primary keys a WHERE clause narrows a scan down to.
+    #[test]
+    fn key_range_from_where() {
+        assert_eq!(range_of("id = 42"), KeyRange { start: 42, end: 42 });
+        assert_eq!(range_of("42 = id"), KeyRange { start: 42, end: 42 });
+        assert_eq!(
+            range_of("id > 10 and id <= 20"),
+            KeyRange { start: 11, end: 20 }
+        );
+        assert_eq!(
+            range_of("10 < id and name = 'rosh'"),
+            KeyRange {
+                start: 11,
+                end: i64::MAX
+            }
+        );
+        assert_eq!(
+            range_of("id >= 2 * 5 and id < -1 + 1"),
+            KeyRange { start: 10, end: -1 }
+        );
+        assert!(range_of("id > 10 and id < 5").is_empty());
+        assert!(range_of("id > 9223372036854775807").is_empty());
+
+        // Anything that isn't a plain comparison against the key has to look at every row.
+        assert_eq!(range_of("id = 1 or id = 2"), KeyRange::all());
+        assert_eq!(range_of("not id = 1"), KeyRange::all());
+        assert_eq!(range_of("id + 1 = 2"), KeyRange::all());
+        assert_eq!(range_of("id = 2.5"), KeyRange::all());
+        assert_eq!(range_of("name = 'id'"), KeyRange::all());
+    }
 }
@@ -0,0 +1,642 @@
+use crate::pager::{Page, Pager, StorageError, PAGE_SIZE};
+use std::ops::Bound;
+
+/// Where a row sits in a table's tree: its primary key, or for tables without one, its row number.
+pub type Key = i64;
+
+/* Every node takes up one page, starting with a header:
+ *   byte 0     node type, LEAF or INTERNAL
+ *   bytes 1-2  number of cells
+ *   bytes 3-6  leaves: the next leaf to the right, 0 for the last one
+ *              internal nodes: the rightmost child
+ *
+ * Leaf cells hold a key, the record's length and then either the record itself or, when it is
+ * bigger than MAX_INLINE, the first page of an overflow chain holding it.
+ * Internal cells hold a child and the key separating it from the child after it: every key in
+ * that child is smaller than the separator, every key after it is the same or bigger.
+ */
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+const HEADER_SIZE: usize = 7;
+const KEY_SIZE: usize = 8;
+const LEAF_CELL_HEADER_SIZE: usize = KEY_SIZE + 4;
+const INTERNAL_CELL_SIZE: usize = 4 + KEY_SIZE;
+
+/// Records bigger than this go to overflow pages, which keeps cells small enough that a full leaf
+/// can always be split into two halves that each fit a page.
+const MAX_INLINE: usize = 1024;
+
+#[derive(Debug, Clone)]
+enum Payload {
+    Inline(Vec<u8>),
+    Overflow { length: u32, page: u32 },
+}
+
+impl Payload {
+    fn cell_size(&self) -> usize {
+        match self {
+            Payload::Inline(record) => LEAF_CELL_HEADER_SIZE + record.len(),
+            Payload::Overflow { .. } => LEAF_CELL_HEADER_SIZE + 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        entries: Vec<(Key, Payload)>,
+        next: u32,
+    },
+    /// Holds one more child than keys.
+    Internal { keys: Vec<Key>, children: Vec<u32> },
+}
+
+impl Node {
+    fn read(page: &Page, number: u32) -> Result<Node, StorageError> {
+        let count = u16::from_le_bytes([page[1], page[2]]) as usize;
+        let link = read_u32(page, 3);
+        let mut offset = HEADER_SIZE;
+
+        match page[0] {
+            LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    check_fits(number, offset + LEAF_CELL_HEADER_SIZE)?;
+                    let key = read_key(page, offset);
+                    let length = read_u32(page, offset + KEY_SIZE);
+                    offset += LEAF_CELL_HEADER_SIZE;
+
+                    let payload = if length as usize > MAX_INLINE {
+                        check_fits(number, offset + 4)?;
+                        let overflow = read_u32(page, offset);
+                        offset += 4;
+                        Payload::Overflow {
+                            length,
+                            page: overflow,
+                        }
+                    } else {
+                        check_fits(number, offset + length as usize)?;
+                        let record = page[offset..offset + length as usize].to_vec();
+                        offset += length as usize;
+                        Payload::Inline(record)
+                    };
+                    entries.push((key, payload));
+                }
+                Ok(Node::Leaf {
+                    entries,
+                    next: link,
+                })
+            }
+            INTERNAL => {
+                check_fits(number, HEADER_SIZE + count * INTERNAL_CELL_SIZE)?;
+                let mut keys = Vec::with_capacity(count);
+                let mut children = Vec::with_capacity(count + 1);
+                for _ in 0..count {
+                    children.push(read_u32(page, offset));
+                    keys.push(read_key(page, offset + 4));
+                    offset += INTERNAL_CELL_SIZE;
+                }
+                children.push(link);
+                Ok(Node::Internal { keys, children })
+            }
+            other => Err(StorageError::Corrupt(format!(
+                "page {} has unknown node type {}",
+                number, other
+            ))),
+        }
+    }
+
+    fn write(&self, page: &mut Page) {
+        page.fill(0);
+        let mut offset = HEADER_SIZE;
+
+        match self {
+            Node::Leaf { entries, next } => {
+                page[0] = LEAF;
+                page[1..3].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+                write_u32(page, 3, *next);
+                for (key, payload) in entries.iter() {
+                    write_key(page, offset, key);
+                    match payload {
+                        Payload::Inline(record) => {
+                            write_u32(page, offset + KEY_SIZE, record.len() as u32);
+                            offset += LEAF_CELL_HEADER_SIZE;
+                            page[offset..offset + record.len()].copy_from_slice(record);
+                            offset += record.len();
+                        }
+                        Payload::Overflow {
+                            length,
+                            page: overflow,
+                        } => {
+                            write_u32(page, offset + KEY_SIZE, *length);
+                            write_u32(page, offset + LEAF_CELL_HEADER_SIZE, *overflow);
+                            offset += LEAF_CELL_HEADER_SIZE + 4;
+                        }
+                    }
+                }
+            }
+            Node::Internal { keys, children } => {
+                page[0] = INTERNAL;
+                page[1..3].copy_from_slice(&(keys.len() as u16).to_le_bytes());
+                write_u32(page, 3, children[keys.len()]);
+                for (key, child) in keys.iter().zip(children.iter()) {
+                    write_u32(page, offset, *child);
+                    write_key(page, offset + 4, key);
+                    offset += INTERNAL_CELL_SIZE;
+                }
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Node::Leaf { entries, .. } => {
+                HEADER_SIZE
+                    + entries
+                        .iter()
+                        .map(|(_, payload)| payload.cell_size())
+                        .sum::<usize>()
+            }
+            Node::Internal { keys, .. } => HEADER_SIZE + keys.len() * INTERNAL_CELL_SIZE,
+        }
+    }
+
+    /// Moves the upper half of this node into a new node that will live on page `right`,
+    /// returning the key separating the two along with the new node.
+    fn split(&mut self, right: u32) -> (Key, Node) {
+        match self {
+            Node::Leaf { entries, next } => {
+                // Split by size rather than count, since records can be very different sizes.
+                let total: usize = entries.iter().map(|(_, p)| p.cell_size()).sum();
+                let mut used = 0;
+                let mut at = entries.len() - 1;
+                for (i, (_, payload)) in entries.iter().enumerate() {
+                    used += payload.cell_size();
+                    if used * 2 >= total {
+                        at = (i + 1).min(entries.len() - 1);
+                        break;
+                    }
+                }
+
+                let upper = entries.split_off(at);
+                let separator = upper[0].0;
+                let node = Node::Leaf {
+                    entries: upper,
+                    next: *next,
+                };
+                *next = right;
+                (separator, node)
+            }
+            Node::Internal { keys, children } => {
+                let middle = keys.len() / 2;
+                let upper_keys = keys.split_off(middle + 1);
+                let separator = keys.pop().unwrap();
+                let upper_children = children.split_off(middle + 1);
+                let node = Node::Internal {
+                    keys: upper_keys,
+                    children: upper_children,
+                };
+                (separator, node)
+            }
+        }
+    }
+}
+
+/// A B+tree of records stored in the pager, ordered by `Key`.
+///
+/// Deleting doesn't merge nodes back together, so pages emptied by deletes stay part of the tree
+/// until it is destroyed.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct BTree {
+    root: u32,
+}
+
+impl BTree {
+    /// Makes an empty tree on a freshly allocated page.
+    pub fn create(pager: &mut Pager) -> Result<BTree, StorageError> {
+        let root = pager.allocate()?;
+        let node = Node::Leaf {
+            entries: Vec::new(),
+            next: 0,
+        };
+        node.write(pager.page_mut(root)?);
+        Ok(BTree { root })
+    }
+
+    /// The tree whose root is on page `root`.
+    pub fn open(root: u32) -> BTree {
+        BTree { root }
+    }
+
+    /// The root never moves, so this is all that needs remembering to find the tree again.
+    pub fn root(&self) -> u32 {
+        self.root
+    }
+
+    /// Stores `record` under `key`, replacing whatever was there before.
+    pub fn insert(&self, pager: &mut Pager, key: Key, record: &[u8]) -> Result<(), StorageError> {
+        let payload = if record.len() > MAX_INLINE {
+            let page = pager.allocate()?;
+            pager.write_chain(page, record)?;
+            Payload::Overflow {
+                length: record.len() as u32,
+                page,
+            }
+        } else {
+            Payload::Inline(record.to_vec())
+        };
+
+        if let Some((separator, right)) = self.insert_into(pager, self.root, key, payload)? {
+            // The root has to stay where it is, so its lower half moves to a new page instead.
+            let left = pager.allocate()?;
+            let root = *pager.page(self.root)?;
+            *pager.page_mut(left)? = root;
+
+            let node = Node::Internal {
+                keys: vec![separator],
+                children: vec![left, right],
+            };
+            node.write(pager.page_mut(self.root)?);
+        }
+        Ok(())
+    }
+
+    /// Inserts into the subtree on page `number`. When the node had to be split, returns the
+    /// separator and page of the new node that the parent needs to point to.
+    fn insert_into(
+        &self,
+        pager: &mut Pager,
+        number: u32,
+        key: Key,
+        payload: Payload,
+    ) -> Result<Option<(Key, u32)>, StorageError> {
+        let mut node = Node::read(&*pager.page(number)?, number)?;
+
+        match &mut node {
+            Node::Leaf { entries, .. } => {
+                match entries.binary_search_by(|(other, _)| other.cmp(&key)) {
+                    Ok(i) => {
+                        let old = std::mem::replace(&mut entries[i].1, payload);
+                        free_payload(pager, &old)?;
+                    }
+                    Err(i) => entries.insert(i, (key, payload)),
+                }
+            }
+            Node::Internal { keys, children } => {
+                let i = child_index(keys, &key);
+                if let Some((separator, right)) =
+                    self.insert_into(pager, children[i], key, payload)?
+                {
+                    keys.insert(i, separator);
+                    children.insert(i + 1, right);
+                }
+            }
+        }
+
+        if node.size() <= PAGE_SIZE {
+            node.write(pager.page_mut(number)?);
+            return Ok(None);
+        }
+
+        let right = pager.allocate()?;
+        let (separator, upper) = node.split(right);
+        node.write(pager.page_mut(number)?);
+        upper.write(pager.page_mut(right)?);
+        Ok(Some((separator, right)))
+    }
+
+    /// Removes the record stored under `key`, returning whether there was one.
+    pub fn remove(&self, pager: &mut Pager, key: Key) -> Result<bool, StorageError> {
+        let number = self.find_leaf(pager, &key)?;
+        let mut node = Node::read(&*pager.page(number)?, number)?;
+        if let Node::Leaf { entries, .. } = &mut node {
+            if let Ok(i) = entries.binary_search_by(|(other, _)| other.cmp(&key)) {
+                let (_, payload) = entries.remove(i);
+                free_payload(pager, &payload)?;
+                node.write(pager.page_mut(number)?);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Finds the record stored under `key`.
+    pub fn get(&self, pager: &Pager, key: Key) -> Result<Option<Vec<u8>>, StorageError> {
+        let mut cursor = self.seek(pager, Bound::Included(key))?;
+        match cursor.next(pager)? {
+            Some((found, record)) if found == key => Ok(Some(record)),
+            _ => Ok(None),
+        }
+    }
+
+    /// A cursor over the records in key order, starting at `from`.
+    pub fn seek(&self, pager: &Pager, from: Bound<Key>) -> Result<Cursor, StorageError> {
+        let number = match &from {
+            Bound::Included(key) | Bound::Excluded(key) => self.find_leaf(pager, key)?,
+            Bound::Unbounded => self.first_leaf(pager)?,
+        };
+
+        let (entries, next) = match Node::read(&*pager.page(number)?, number)? {
+            Node::Leaf { entries, next } => (entries, next),
+            Node::Internal { .. } => unreachable!("find_leaf always stops at a leaf"),
+        };
+        let index = match &from {
+            Bound::Included(key) => entries.partition_point(|(other, _)| other < key),
+            Bound::Excluded(key) => entries.partition_point(|(other, _)| other <= key),
+            Bound::Unbounded => 0,
+        };
+
+        Ok(Cursor {
+            entries,
+            index,
+            next,
+        })
+    }
+
+    /// The biggest key in the tree.
+    pub fn last(&self, pager: &Pager) -> Result<Option<Key>, StorageError> {
+        self.last_in(pager, self.root)
+    }
+
+    fn last_in(&self, pager: &Pager, number: u32) -> Result<Option<Key>, StorageError> {
+        match Node::read(&*pager.page(number)?, number)? {
+            Node::Leaf { entries, .. } => Ok(entries.last().map(|(key, _)| *key)),
+            Node::Internal { children, .. } => {
+                // Leaves can be left empty by deletes, so keep looking further left.
+                for child in children.iter().rev() {
+                    if let Some(key) = self.last_in(pager, *child)? {
+                        return Ok(Some(key));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Frees every page of the tree, including the root.
+    pub fn destroy(self, pager: &mut Pager) -> Result<(), StorageError> {
+        destroy_node(pager, self.root)
+    }
+
+    /// The leaf that `key` belongs in.
+    fn find_leaf(&self, pager: &Pager, key: &Key) -> Result<u32, StorageError> {
+        let mut number = self.root;
+        loop {
+            match Node::read(&*pager.page(number)?, number)? {
+                Node::Leaf { .. } => return Ok(number),
+                Node::Internal { keys, children } => number = children[child_index(&keys, key)],
+            }
+        }
+    }
+
+    fn first_leaf(&self, pager: &Pager) -> Result<u32, StorageError> {
+        let mut number = self.root;
+        loop {
+            match Node::read(&*pager.page(number)?, number)? {
+                Node::Leaf { .. } => return Ok(number),
+                Node::Internal { children, .. } => number = children[0],
+            }
+        }
+    }
+}
+
+/// Walks through the records of a tree in key order, one leaf at a time.
+pub struct Cursor {
+    entries: Vec<(Key, Payload)>,
+    index: usize,
+    next: u32,
+}
+
+impl Cursor {
+    pub fn next(&mut self, pager: &Pager) -> Result<Option<(Key, Vec<u8>)>, StorageError> {
+        while self.index >= self.entries.len() {
+            if self.next == 0 {
+                return Ok(None);
+            }
+            let number = self.next;
+            match Node::read(&*pager.page(number)?, number)? {
+                Node::Leaf { entries, next } => {
+                    self.entries = entries;
+                    self.next = next;
+                    self.index = 0;
+                }
+                Node::Internal { .. } => {
+                    return Err(StorageError::Corrupt(format!(
+                        "page {} should be a leaf",
+                        number
+                    )))
+                }
+            }
+        }
+
+        let (key, payload) = &self.entries[self.index];
+        self.index += 1;
+        let record = match payload {
+            Payload::Inline(record) => record.clone(),
+            Payload::Overflow { page, .. } => pager.read_chain(*page)?,
+        };
+        Ok(Some((*key, record)))
+    }
+}
+
+/// Which child of an internal node `key` belongs in.
+fn child_index(keys: &[Key], key: &Key) -> usize {
+    keys.partition_point(|separator| separator <= key)
+}
+
+fn free_payload(pager: &mut Pager, payload: &Payload) -> Result<(), StorageError> {
+    match payload {
+        Payload::Inline(_) => Ok(()),
+        Payload::Overflow { page, .. } => pager.free_chain(*page),
+    }
+}
+
+fn destroy_node(pager: &mut Pager, number: u32) -> Result<(), StorageError> {
+    match Node::read(&*pager.page(number)?, number)? {
+        Node::Leaf { entries, .. } => {
+            for (_, payload) in entries.iter() {
+                free_payload(pager, payload)?;
+            }
+        }
+        Node::Internal { children, .. } => {
+            for child in children {
+                destroy_node(pager, child)?;
+            }
+        }
+    }
+    pager.free(number)
+}
+
+fn check_fits(number: u32, end: usize) -> Result<(), StorageError> {
+    if end > PAGE_SIZE {
+        return Err(StorageError::Corrupt(format!(
+            "page {} holds more than fits in a page",
+            number
+        )));
+    }
+    Ok(())
+}
+
+fn read_key(page: &Page, offset: usize) -> Key {
+    let mut bytes = [0; KEY_SIZE];
+    bytes.copy_from_slice(&page[offset..offset + KEY_SIZE]);
+    Key::from_le_bytes(bytes)
+}
+
+fn write_key(page: &mut Page, offset: usize, key: &Key) {
+    page[offset..offset + KEY_SIZE].copy_from_slice(&key.to_le_bytes());
+}
+
+fn read_u32(page: &Page, offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&page[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn write_u32(page: &mut Page, offset: usize, value: u32) {
+    page[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: i64) -> Vec<u8> {
+        format!("record {}", id).into_bytes()
+    }
+
+    /// Helper method to read every key of a tree in order.
+    fn keys(tree: &BTree, pager: &Pager) -> Vec<i64> {
+        let mut cursor = tree.seek(pager, Bound::Unbounded).unwrap();
+        let mut keys = Vec::new();
+        while let Some((key, _)) = cursor.next(pager).unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    /// Helper method to count how many levels the tree has.
+    fn depth(tree: &BTree, pager: &Pager) -> usize {
+        let mut number = tree.root();
+        let mut depth = 1;
+        while let Node::Internal { children, .. } =
+            Node::read(&pager.page(number).unwrap(), number).unwrap()
+        {
+            number = children[0];
+            depth += 1;
+        }
+        depth
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut pager = Pager::memory();
+        let tree = BTree::create(&mut pager).unwrap();
+        tree.insert(&mut pager, 5, b"five").unwrap();
+        tree.insert(&mut pager, -3, b"minus three").unwrap();
+
+        assert_eq!(tree.get(&pager, 5).unwrap(), Some(b"five".to_vec()));
+        assert_eq!(tree.get(&pager, 4).unwrap(), None);
+        assert_eq!(keys(&tree, &pager), vec![-3, 5]);
+
+        // Inserting under an existing key replaces the record.
+        tree.insert(&mut pager, 5, b"FIVE").unwrap();
+        assert_eq!(tree.get(&pager, 5).unwrap(), Some(b"FIVE".to_vec()));
+        assert_eq!(keys(&tree, &pager), vec![-3, 5]);
+    }
+
+    #[test]
+    fn many_inserts_stay_ordered_and_shallow() {
+        let mut pager = Pager::memory();
+        let tree = BTree::create(&mut pager).unwrap();
+        // Insert in a scrambled order so splits happen all over the tree.
+        let ids: Vec<i64> = (0..20_000).map(|i| (i * 7919) % 20_000).collect();
+        for id in ids.iter() {
+            tree.insert(&mut pager, *id, &record(*id)).unwrap();
+        }
+
+        assert_eq!(keys(&tree, &pager), (0..20_000).collect::<Vec<_>>());
+        assert!(depth(&tree, &pager) <= 3);
+        for id in [0, 1, 9_999, 19_999] {
+            assert_eq!(tree.get(&pager, id).unwrap(), Some(record(id)));
+        }
+        assert_eq!(tree.last(&pager).unwrap(), Some(19_999));
+    }
+
+    #[test]
+    fn seek_starts_at_bound() {
+        let mut pager = Pager::memory();
+        let tree = BTree::create(&mut pager).unwrap();
+        for id in (0..1_000).map(|i| i * 2) {
+            tree.insert(&mut pager, id, &record(id)).unwrap();
+        }
+
+        let mut cursor = tree.seek(&pager, Bound::Included(500)).unwrap();
+        assert_eq!(cursor.next(&pager).unwrap().unwrap().0, 500);
+        assert_eq!(cursor.next(&pager).unwrap().unwrap().0, 502);
+
+        let mut cursor = tree.seek(&pager, Bound::Excluded(500)).unwrap();
+        assert_eq!(cursor.next(&pager).unwrap().unwrap().0, 502);
+
+        let mut cursor = tree.seek(&pager, Bound::Included(501)).unwrap();
+        assert_eq!(cursor.next(&pager).unwrap().unwrap().0, 502);
+
+        let mut cursor = tree.seek(&pager, Bound::Included(5_000)).unwrap();
+        assert_eq!(cursor.next(&pager).unwrap(), None);
+    }
+
+    #[test]
+    fn remove_keys() {
+        let mut pager = Pager::memory();
+        let tree = BTree::create(&mut pager).unwrap();
+        for id in 0..2_000 {
+            tree.insert(&mut pager, id, &record(id)).unwrap();
+        }
+        for id in (0..2_000).filter(|id| id % 3 != 0 || *id >= 1_500) {
+            assert!(tree.remove(&mut pager, id).unwrap());
+        }
+        assert!(!tree.remove(&mut pager, 1).unwrap());
+
+        let expected: Vec<i64> = (0..1_500).filter(|id| id % 3 == 0).collect();
+        assert_eq!(keys(&tree, &pager), expected);
+        // The rightmost leaves are now empty, so the last key has to be found further left.
+        assert_eq!(tree.last(&pager).unwrap(), Some(1_497));
+    }
+
+    #[test]
+    fn large_records_overflow() {
+        let mut pager = Pager::memory();
+        let tree = BTree::create(&mut pager).unwrap();
+        let big = vec![42; 10_000];
+        for id in 0..20 {
+            tree.insert(&mut pager, id, &big).unwrap();
+        }
+        assert_eq!(tree.get(&pager, 13).unwrap(), Some(big.clone()));
+
+        // Replacing and removing records hands their overflow pages back.
+        let count = pager.page_count();
+        tree.insert(&mut pager, 13, b"small").unwrap();
+        tree.remove(&mut pager, 14).unwrap();
+        tree.insert(&mut pager, 100, &big).unwrap();
+        tree.insert(&mut pager, 101, &big).unwrap();
+        assert_eq!(pager.page_count(), count);
+    }
+
+    #[test]
+    fn destroy_frees_every_page() {
+        let mut pager = Pager::memory();
+        let tree = BTree::create(&mut pager).unwrap();
+        for id in 0..5_000 {
+            tree.insert(&mut pager, id, &record(id)).unwrap();
+        }
+        let count = pager.page_count();
+        tree.destroy(&mut pager).unwrap();
+
+        // A new tree of the same size fits entirely in the freed pages.
+        let tree = BTree::create(&mut pager).unwrap();
+        for id in 0..5_000 {
+            tree.insert(&mut pager, id, &record(id)).unwrap();
+        }
+        assert_eq!(pager.page_count(), count);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::ast::{Select, Statement};
+use crate::backend::{execute_read, execute_statement, Database, ExecuteResult};
+use crate::error::Error;
+use crate::mvcc::Snapshot;
+use crate::pager::StorageError;
+use crate::transaction::TransactionError;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::{self, ThreadId};
+
+/// A handle on a database that any number of threads can share. Cloning it is cheap, and every
+/// clone works on the same database.
+///
+/// Statements that change the database run one at a time, each committing on its own, unless
+/// they are part of a transaction started with `begin`, which keeps every other writer out until
+/// it ends. Reads don't wait for transactions: they see everything committed before they started.
+/// Any number of reads run side by side, and only wait while a statement is changing the database.
+#[derive(Clone)]
+pub struct Connection {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    /// Shared by reads, taken whole by each statement that writes while it runs.
+    database: RwLock<Database>,
+    /// Held by whoever is writing, for as long as their statement or transaction lasts.
+    writer: Mutex<()>,
+    /// The thread holding the open transaction, if there is one.
+    owner: Mutex<Option<ThreadId>>,
+}
+
+impl Connection {
+    /// A database that only lives in memory, shared by every clone of the connection.
+    pub fn memory() -> Connection {
+        Connection::from(Database::new())
+    }
+
+    /// Opens the database file at `path`, creating it if it doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>) -> Result<Connection, StorageError> {
+        Ok(Connection::from(Database::open(path)?))
+    }
+
+    /// Runs a statement on its own. One that changes the database waits for any other writer to
+    /// finish first, and commits before this returns.
+    ///
+    /// Transaction statements are refused: a transaction on a shared connection has to be kept
+    /// apart from everyone else's statements, which is what `begin` is for.
+    pub fn execute(&self, statement: Statement) -> Result<ExecuteResult, Error> {
+        match statement {
+            Statement::Select(select) => {
+                let database = self.database();
+                let snapshot = database.snapshot();
+                execute_read(select, &snapshot, &database)
+            }
+            Statement::Begin
+            | Statement::Commit
+            | Statement::Rollback
+            | Statement::Savepoint(_)
+            | Statement::RollbackTo(_)
+            | Statement::Release(_) => Err(Error::Transaction(TransactionError::Shared)),
+            statement => {
+                let _writer = self.writer()?;
+                execute_statement(statement, &mut self.database_mut())
+            }
+        }
+    }
+
+    /// A snapshot of everything committed so far, for `read` to look at.
+    pub fn snapshot(&self) -> Snapshot {
+        self.database().snapshot()
+    }
+
+    /// Runs a SELECT against `snapshot`, so that any number of reads see the database as it was
+    /// when the snapshot was taken.
+    pub fn read(&self, select: Select, snapshot: &Snapshot) -> Result<ExecuteResult, Error> {
+        execute_read(select, snapshot, &self.database())
+    }
+
+    /// Starts a transaction, once any other writer is done. Until it is committed or rolled back
+    /// it is the only writer, and nobody else sees what it changes.
+    ///
+    /// The thread holding the transaction writes through it alone. Anything else it tries on the
+    /// connection that writes, `begin` included, fails with `TransactionError::AlreadyActive`.
+    pub fn begin(&self) -> Result<Transaction<'_>, TransactionError> {
+        let writer = self.writer()?;
+        self.database_mut().begin()?;
+        *self.owner() = Some(thread::current().id());
+        Ok(Transaction {
+            connection: self,
+            _writer: writer,
+        })
+    }
+
+    /// Clears away row versions that no snapshot in use can see anymore, returning how many went.
+    pub fn collect_garbage(&self) -> Result<usize, Error> {
+        let _writer = self.writer()?;
+        Ok(self.database_mut().collect_garbage()?)
+    }
+
+    /// Folds the write-ahead log back into the database file.
+    pub fn checkpoint(&self) -> Result<(), Error> {
+        let _writer = self.writer()?;
+        Ok(self.database_mut().checkpoint()?)
+    }
+
+    /// A thread that panicked while holding a lock doesn't stop the others from using the
+    /// database, so poisoned locks are taken over as they are.
+    fn database(&self) -> RwLockReadGuard<'_, Database> {
+        let database = self.shared.database.read();
+        database.unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn database_mut(&self) -> RwLockWriteGuard<'_, Database> {
+        let database = self.shared.database.write();
+        database.unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits for the writer's lock, unless the calling thread holds it already for a transaction,
+    /// in which case waiting would never end.
+    fn writer(&self) -> Result<MutexGuard<'_, ()>, TransactionError> {
+        if *self.owner() == Some(thread::current().id()) {
+            return Err(TransactionError::AlreadyActive);
+        }
+        let writer = self.shared.writer.lock();
+        Ok(writer.unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn owner(&self) -> MutexGuard<'_, Option<ThreadId>> {
+        let owner = self.shared.owner.lock();
+        owner.unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<Database> for Connection {
+    fn from(database: Database) -> Connection {
+        Connection {
+            shared: Arc::new(Shared {
+                database: RwLock::new(database),
+                writer: Mutex::new(()),
+                owner: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+/// A transaction open on a `Connection`. Dropping it without committing rolls it back.
+pub struct Transaction<'a> {
+    connection: &'a Connection,
+    _writer: MutexGuard<'a, ()>,
+}
+
+impl Transaction<'_> {
+    /// Runs a statement as part of the transaction. Savepoints work as usual.
+    pub fn execute(&self, statement: Statement) -> Result<ExecuteResult, Error> {
+        execute_statement(statement, &mut self.connection.database_mut())
+    }
+
+    /// Writes every change the transaction made to disk, for everyone to see.
+    pub fn commit(self) -> Result<(), StorageError> {
+        let mut database = self.connection.database_mut();
+        match database.in_transaction() {
+            true => database.commit(),
+            false => Ok(()),
+        }
+    }
+
+    /// Throws away every change the transaction made, just as dropping it does.
+    pub fn rollback(self) {}
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        let mut database = self.connection.database_mut();
+        if database.in_transaction() {
+            database.rollback();
+        }
+        *self.connection.owner() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_is_shareable() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<Connection>();
+    }
+}
@@ -0,0 +1,124 @@
+use crate::eval::EvalError;
+use crate::limits::TableFull;
+use crate::pager::StorageError;
+use crate::parser::ParseError;
+use crate::schema::{ConstraintViolation, SchemaError};
+use crate::transaction::TransactionError;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong preparing or running a statement. Each kind of failure keeps its
+/// own error type, and converts into this one, so callers can use `?` whatever went wrong.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Error {
+    /// The SQL couldn't be parsed.
+    Parse(ParseError),
+    /// An expression couldn't be evaluated, e.g because its operands have the wrong types.
+    Eval(EvalError),
+    /// A statement doesn't fit the schema of the table it works on, or a table doesn't exist.
+    Schema(SchemaError),
+    /// A statement would break a constraint on a table. Nothing was written.
+    Constraint(ConstraintViolation),
+    /// A statement would have made a table hold more than a limit allows. Nothing was written.
+    TableFull(TableFull),
+    /// Reading or writing the database file failed, or its contents don't make sense.
+    Storage(StorageError),
+    /// A transaction statement doesn't make sense in the state the database is in.
+    Transaction(TransactionError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(err) => err.fmt(f),
+            Error::Eval(err) => err.fmt(f),
+            Error::Schema(err) => err.fmt(f),
+            Error::Constraint(err) => err.fmt(f),
+            Error::TableFull(err) => err.fmt(f),
+            Error::Storage(err) => err.fmt(f),
+            Error::Transaction(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse(err) => Some(err),
+            Error::Eval(err) => Some(err),
+            Error::Schema(err) => Some(err),
+            Error::Constraint(err) => Some(err),
+            Error::TableFull(err) => Some(err),
+            Error::Storage(err) => Some(err),
+            Error::Transaction(err) => Some(err),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(err: EvalError) -> Error {
+        Error::Eval(err)
+    }
+}
+
+impl From<SchemaError> for Error {
+    fn from(err: SchemaError) -> Error {
+        Error::Schema(err)
+    }
+}
+
+impl From<ConstraintViolation> for Error {
+    fn from(err: ConstraintViolation) -> Error {
+        Error::Constraint(err)
+    }
+}
+
+impl From<TableFull> for Error {
+    fn from(err: TableFull) -> Error {
+        Error::TableFull(err)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(err: StorageError) -> Error {
+        Error::Storage(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Storage(err.into())
+    }
+}
+
+impl From<TransactionError> for Error {
+    fn from(err: TransactionError) -> Error {
+        Error::Transaction(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn errors_convert_and_chain() {
+        fn failing() -> Result<(), Error> {
+            Err(TransactionError::NotActive)?
+        }
+        let err = failing().unwrap_err();
+        assert_eq!(err, Error::Transaction(TransactionError::NotActive));
+        assert_eq!(err.to_string(), "no transaction is open");
+        assert_eq!(err.source().unwrap().to_string(), "no transaction is open");
+
+        let err = Error::from(io::Error::other("disk on fire"));
+        assert_eq!(err.to_string(), "I/O error: disk on fire");
+    }
+}
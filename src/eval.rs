@@ -0,0 +1,478 @@
+use crate::ast::*;
+use crate::schema::{DataType, Row, Schema};
+use crate::value::Value;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Why an expression couldn't be evaluated.
+#[derive(PartialEq, Debug, Clone)]
+pub enum EvalError {
+    UnknownColumn(String),
+    /// A column name that more than one of the tables a query reads has, and that isn't qualified
+    /// with the table it should come from.
+    AmbiguousColumn(String),
+    /// Two of the tables a query reads go by the same name, so their columns can't be told apart.
+    DuplicateTable(String),
+    /// An operator was applied to values it doesn't support, e.g `'abc' + 1`.
+    TypeMismatch(String),
+    DivisionByZero,
+    Overflow,
+    /// LIMIT or OFFSET wasn't a number of rows, e.g `LIMIT -1`.
+    InvalidLimit(String),
+    /// ORDER BY a position that isn't one of the result's columns, e.g `ORDER BY 3` with only two.
+    InvalidOrderBy(String),
+    /// An aggregate was used somewhere it can't be computed, e.g in a WHERE clause or within
+    /// another aggregate.
+    MisusedAggregate(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnknownColumn(name) => write!(f, "unknown column '{}'", name),
+            EvalError::AmbiguousColumn(name) => write!(f, "ambiguous column name '{}'", name),
+            EvalError::DuplicateTable(name) => {
+                write!(f, "table name '{}' is used more than once", name)
+            }
+            EvalError::TypeMismatch(message) => write!(f, "{}", message),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "integer overflow"),
+            EvalError::InvalidLimit(message) => write!(f, "{}", message),
+            EvalError::InvalidOrderBy(message) => write!(f, "{}", message),
+            EvalError::MisusedAggregate(aggregate) => {
+                write!(f, "misuse of aggregate function {}", aggregate)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Evaluates `expr` with column references resolved against `row`, laid out as `schema`.
+pub fn eval(expr: &Expr, schema: &Schema, row: &Row) -> Result<Value, EvalError> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Column { table, column } => match schema.find(table.as_deref(), column) {
+            Some(index) => Ok(row.values[index].clone()),
+            None => Err(EvalError::UnknownColumn(expr.to_string())),
+        },
+        Expr::Unary { op, expr } => eval_unary(*op, eval(expr, schema, row)?),
+        Expr::Binary { left, op, right } => {
+            let left = eval(left, schema, row)?;
+            // AND and OR don't need the right hand side when the left already decides the result.
+            match (op, &left) {
+                (BinaryOp::And, Value::Boolean(false)) => return Ok(left),
+                (BinaryOp::Or, Value::Boolean(true)) => return Ok(left),
+                _ => {}
+            }
+            eval_binary(*op, left, eval(right, schema, row)?)
+        }
+        Expr::IsNull { expr, negated } => {
+            let is_null = eval(expr, schema, row)?.is_null();
+            Ok(Value::Boolean(is_null != *negated))
+        }
+        // Aggregates are worked out over whole groups of rows before anything is evaluated
+        // against them, so one that gets this far is in the wrong place.
+        Expr::Aggregate { .. } => Err(EvalError::MisusedAggregate(expr.to_string())),
+    }
+}
+
+/// Evaluates an expression that can't refer to any columns, e.g the values of an INSERT.
+pub fn eval_constant(expr: &Expr) -> Result<Value, EvalError> {
+    eval(expr, &Schema::default(), &Row::default())
+}
+
+/// Evaluates a WHERE clause. Rows only match when it is true, NULL counts as no match.
+pub fn eval_predicate(expr: &Expr, schema: &Schema, row: &Row) -> Result<bool, EvalError> {
+    match eval(expr, schema, row)? {
+        Value::Boolean(matched) => Ok(matched),
+        Value::Null => Ok(false),
+        other => Err(EvalError::TypeMismatch(format!(
+            "WHERE clause must be a boolean expression, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// Works out the type of value `expr` gives for rows laid out as `schema`, without evaluating
+/// it. `None` when it can't be known up front, e.g for a NULL literal or for an expression that
+/// is bound to fail. Every column `expr` refers to has to exist.
+pub fn expr_type(expr: &Expr, schema: &Schema) -> Result<Option<DataType>, EvalError> {
+    let data_type = match expr {
+        Expr::Literal(value) => match value {
+            Value::Null => None,
+            Value::Integer(_) => Some(DataType::Integer),
+            Value::Real(_) => Some(DataType::Real),
+            Value::Text(_) => Some(DataType::Text),
+            Value::Boolean(_) => Some(DataType::Boolean),
+        },
+        Expr::Column { table, column } => match schema.find(table.as_deref(), column) {
+            Some(index) => Some(schema.columns[index].data_type),
+            None => return Err(EvalError::UnknownColumn(expr.to_string())),
+        },
+        Expr::Unary { op, expr } => match (op, expr_type(expr, schema)?) {
+            (UnaryOp::Not, _) => Some(DataType::Boolean),
+            (_, data_type) => data_type,
+        },
+        Expr::Binary { left, op, right } => {
+            let types = (expr_type(left, schema)?, expr_type(right, schema)?);
+            match op {
+                BinaryOp::Concat => Some(DataType::Text),
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+                    match types {
+                        (Some(DataType::Integer), Some(DataType::Integer)) => {
+                            Some(DataType::Integer)
+                        }
+                        (Some(DataType::Integer | DataType::Real), None)
+                        | (None, Some(DataType::Integer | DataType::Real)) => types.0.or(types.1),
+                        (Some(DataType::Integer | DataType::Real), Some(DataType::Real))
+                        | (Some(DataType::Real), Some(DataType::Integer)) => Some(DataType::Real),
+                        _ => None,
+                    }
+                }
+                _ => Some(DataType::Boolean),
+            }
+        }
+        Expr::IsNull { expr, .. } => {
+            expr_type(expr, schema)?;
+            Some(DataType::Boolean)
+        }
+        Expr::Aggregate { function, arg, .. } => {
+            let arg_type = match arg {
+                Some(arg) if arg.find_aggregate().is_some() => {
+                    return Err(EvalError::MisusedAggregate(expr.to_string()))
+                }
+                Some(arg) => expr_type(arg, schema)?,
+                None => None,
+            };
+            match function {
+                AggregateFunction::Count => Some(DataType::Integer),
+                AggregateFunction::Avg => Some(DataType::Real),
+                AggregateFunction::Sum => arg_type
+                    .filter(|data_type| matches!(data_type, DataType::Integer | DataType::Real)),
+                AggregateFunction::Min | AggregateFunction::Max => arg_type,
+            }
+        }
+    };
+    Ok(data_type)
+}
+
+fn eval_unary(op: UnaryOp, value: Value) -> Result<Value, EvalError> {
+    match (op, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        (UnaryOp::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+        (UnaryOp::Minus, Value::Integer(i)) => i
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or(EvalError::Overflow),
+        (UnaryOp::Minus, Value::Real(r)) => Ok(Value::Real(-r)),
+        (UnaryOp::Plus, value @ (Value::Integer(_) | Value::Real(_))) => Ok(value),
+        (op, value) => Err(EvalError::TypeMismatch(format!(
+            "cannot apply {} to {}",
+            match op {
+                UnaryOp::Not => "NOT",
+                UnaryOp::Minus => "unary -",
+                UnaryOp::Plus => "unary +",
+            },
+            value.type_name()
+        ))),
+    }
+}
+
+fn mismatch(op: BinaryOp, left: &Value, right: &Value) -> EvalError {
+    EvalError::TypeMismatch(format!(
+        "cannot apply {} to {} and {}",
+        op.as_str(),
+        left.type_name(),
+        right.type_name()
+    ))
+}
+
+fn eval_binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, EvalError> {
+    match op {
+        BinaryOp::And | BinaryOp::Or => eval_logical(op, left, right),
+        BinaryOp::Eq
+        | BinaryOp::NotEq
+        | BinaryOp::Lt
+        | BinaryOp::LtEq
+        | BinaryOp::Gt
+        | BinaryOp::GtEq => {
+            if left.is_null() || right.is_null() {
+                return Ok(Value::Null);
+            }
+            let ordering = compare(&left, &right).ok_or_else(|| mismatch(op, &left, &right))?;
+            let result = match op {
+                BinaryOp::Eq => ordering == Ordering::Equal,
+                BinaryOp::NotEq => ordering != Ordering::Equal,
+                BinaryOp::Lt => ordering == Ordering::Less,
+                BinaryOp::LtEq => ordering != Ordering::Greater,
+                BinaryOp::Gt => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            };
+            Ok(Value::Boolean(result))
+        }
+        BinaryOp::Concat => match (left, right) {
+            (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+            (left, right) => Ok(Value::Text(format!("{}{}", left, right))),
+        },
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+            eval_arithmetic(op, left, right)
+        }
+    }
+}
+
+/// Three-valued logic: NULL means unknown, so e.g `NULL OR TRUE` is still true.
+fn eval_logical(op: BinaryOp, left: Value, right: Value) -> Result<Value, EvalError> {
+    let as_bool = |value: &Value| match value {
+        Value::Boolean(b) => Ok(Some(*b)),
+        Value::Null => Ok(None),
+        _ => Err(mismatch(op, &left, &right)),
+    };
+    let (l, r) = (as_bool(&left)?, as_bool(&right)?);
+
+    let result = match op {
+        BinaryOp::And => match (l, r) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        },
+        _ => match (l, r) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+    };
+    Ok(result.map_or(Value::Null, Value::Boolean))
+}
+
+fn eval_arithmetic(op: BinaryOp, left: Value, right: Value) -> Result<Value, EvalError> {
+    match (&left, &right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Integer(l), Value::Integer(r)) => {
+            let (l, r) = (*l, *r);
+            if matches!(op, BinaryOp::Div | BinaryOp::Mod) && r == 0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            let result = match op {
+                BinaryOp::Add => l.checked_add(r),
+                BinaryOp::Sub => l.checked_sub(r),
+                BinaryOp::Mul => l.checked_mul(r),
+                BinaryOp::Div => l.checked_div(r),
+                _ => l.checked_rem(r),
+            };
+            result.map(Value::Integer).ok_or(EvalError::Overflow)
+        }
+        _ => {
+            let (l, r) = match (as_real(&left), as_real(&right)) {
+                (Some(l), Some(r)) => (l, r),
+                _ => return Err(mismatch(op, &left, &right)),
+            };
+            if matches!(op, BinaryOp::Div | BinaryOp::Mod) && r == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            let result = match op {
+                BinaryOp::Add => l + r,
+                BinaryOp::Sub => l - r,
+                BinaryOp::Mul => l * r,
+                BinaryOp::Div => l / r,
+                _ => l % r,
+            };
+            Ok(Value::Real(result))
+        }
+    }
+}
+
+fn as_real(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Real(r) => Some(*r),
+        _ => None,
+    }
+}
+
+/// Orders two non-NULL values. Integers and reals compare numerically, other types only
+/// compare with themselves, so `None` means the two can't be compared.
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Integer(l), Value::Integer(r)) => Some(l.cmp(r)),
+        (Value::Text(l), Value::Text(r)) => Some(l.cmp(r)),
+        (Value::Boolean(l), Value::Boolean(r)) => Some(l.cmp(r)),
+        _ => as_real(left)?.partial_cmp(&as_real(right)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Column, DataType};
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Column::new("id", DataType::Integer),
+            Column::new("username", DataType::Text),
+            Column::new("email", DataType::Text),
+        ])
+        .unwrap()
+    }
+
+    fn row() -> Row {
+        Row::new(vec![
+            Value::Integer(13),
+            Value::Text("rosh".to_string()),
+            Value::Text("kakapio@gmail.com".to_string()),
+        ])
+    }
+
+    fn lit(value: Value) -> Expr {
+        Expr::Literal(value)
+    }
+
+    fn col(name: &str) -> Expr {
+        Expr::Column {
+            table: None,
+            column: name.to_string(),
+        }
+    }
+
+    fn bin(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+        Expr::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    // Testing that columns resolve against the row.
+    #[test]
+    fn eval_columns() {
+        let expr = col("username");
+        assert_eq!(
+            eval(&expr, &schema(), &row()),
+            Ok(Value::Text("rosh".to_string()))
+        );
+
+        let expr = col("missing");
+        assert_eq!(
+            eval(&expr, &schema(), &row()),
+            Err(EvalError::UnknownColumn("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn eval_arithmetic_on_columns() {
+        let expr = bin(col("id"), BinaryOp::Mul, lit(Value::Integer(2)));
+        assert_eq!(eval(&expr, &schema(), &row()), Ok(Value::Integer(26)));
+
+        let expr = bin(lit(Value::Integer(1)), BinaryOp::Add, lit(Value::Real(0.5)));
+        assert_eq!(eval(&expr, &schema(), &row()), Ok(Value::Real(1.5)));
+    }
+
+    #[test]
+    fn eval_division_by_zero() {
+        let expr = bin(
+            lit(Value::Integer(1)),
+            BinaryOp::Div,
+            lit(Value::Integer(0)),
+        );
+        assert_eq!(
+            eval(&expr, &schema(), &row()),
+            Err(EvalError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn eval_overflow() {
+        let expr = bin(
+            lit(Value::Integer(i64::MAX)),
+            BinaryOp::Add,
+            lit(Value::Integer(1)),
+        );
+        assert_eq!(eval(&expr, &schema(), &row()), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn eval_type_mismatch() {
+        let expr = bin(col("username"), BinaryOp::Add, lit(Value::Integer(1)));
+        assert!(matches!(
+            eval(&expr, &schema(), &row()),
+            Err(EvalError::TypeMismatch(_))
+        ));
+    }
+
+    // NULL behaves as unknown in AND/OR rather than poisoning the whole expression.
+    #[test]
+    fn eval_three_valued_logic() {
+        let null = || lit(Value::Null);
+        let t = || lit(Value::Boolean(true));
+        let f = || lit(Value::Boolean(false));
+
+        assert_eq!(
+            eval(&bin(null(), BinaryOp::Or, t()), &schema(), &row()),
+            Ok(Value::Boolean(true))
+        );
+        assert_eq!(
+            eval(&bin(null(), BinaryOp::And, f()), &schema(), &row()),
+            Ok(Value::Boolean(false))
+        );
+        assert_eq!(
+            eval(&bin(null(), BinaryOp::And, t()), &schema(), &row()),
+            Ok(Value::Null)
+        );
+        assert_eq!(
+            eval(&bin(null(), BinaryOp::Eq, null()), &schema(), &row()),
+            Ok(Value::Null)
+        );
+    }
+
+    #[test]
+    fn eval_predicate_requires_boolean() {
+        assert_eq!(
+            eval_predicate(&lit(Value::Null), &schema(), &row()),
+            Ok(false)
+        );
+        assert!(matches!(
+            eval_predicate(&lit(Value::Integer(1)), &schema(), &row()),
+            Err(EvalError::TypeMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn eval_concat() {
+        let expr = bin(col("username"), BinaryOp::Concat, col("id"));
+        assert_eq!(
+            eval(&expr, &schema(), &row()),
+            Ok(Value::Text("rosh13".to_string()))
+        );
+    }
+
+    // Testing that the type of an expression is worked out from the schema alone.
+    #[test]
+    fn expr_types() {
+        let id = || col("id");
+        let cases = vec![
+            (id(), Some(DataType::Integer)),
+            (lit(Value::Null), None),
+            (
+                bin(id(), BinaryOp::Add, lit(Value::Integer(1))),
+                Some(DataType::Integer),
+            ),
+            (
+                bin(id(), BinaryOp::Div, lit(Value::Real(2.0))),
+                Some(DataType::Real),
+            ),
+            (
+                bin(id(), BinaryOp::Add, lit(Value::Null)),
+                Some(DataType::Integer),
+            ),
+            (bin(id(), BinaryOp::Concat, id()), Some(DataType::Text)),
+            (bin(id(), BinaryOp::Lt, id()), Some(DataType::Boolean)),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(expr_type(&expr, &schema()), Ok(expected), "{}", expr);
+        }
+        assert_eq!(
+            expr_type(&col("age"), &schema()),
+            Err(EvalError::UnknownColumn("age".to_string()))
+        );
+    }
+}
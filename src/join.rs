@@ -0,0 +1,445 @@
+use crate::ast::{BinaryOp, Expr, JoinKind};
+use crate::eval::{eval, eval_predicate, expr_type, EvalError};
+use crate::record::serialize_row;
+use crate::schema::{Column, DataType, Row, Schema};
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// How the rows of the two sides of a join are paired up.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Strategy {
+    /// Every row of the left side is checked against every row of the right side.
+    NestedLoop,
+    /// The right side is put in a hash table on the values of `right`, and each row of the left
+    /// side only looks at the rows whose values equal its own values of `left`.
+    Hash { left: Vec<Expr>, right: Vec<Expr> },
+}
+
+impl Strategy {
+    /// Hashes on every `<left> = <right>` that the ON condition requires, where one side only
+    /// reads columns of the left side of the join and the other only columns of the right side.
+    /// Both sides have to be of types that compare, so that a mismatch fails the same way whichever
+    /// way the rows are paired up. Anything else takes a nested loop.
+    pub fn plan(on: &Expr, left: &Schema, right: &Schema) -> Strategy {
+        let (mut left_keys, mut right_keys) = (Vec::new(), Vec::new());
+        for condition in conjuncts(on) {
+            let (a, b) = match condition {
+                Expr::Binary {
+                    left: a,
+                    op: BinaryOp::Eq,
+                    right: b,
+                } => (a.as_ref(), b.as_ref()),
+                _ => continue,
+            };
+            let (a, b) = match (reads_only(a, left), reads_only(b, left)) {
+                (true, false) if reads_only(b, right) => (a, b),
+                (false, true) if reads_only(a, right) => (b, a),
+                _ => continue,
+            };
+            if comparable(expr_type(a, left), expr_type(b, right)) {
+                left_keys.push(a.clone());
+                right_keys.push(b.clone());
+            }
+        }
+        match left_keys.is_empty() {
+            true => Strategy::NestedLoop,
+            false => Strategy::Hash {
+                left: left_keys,
+                right: right_keys,
+            },
+        }
+    }
+}
+
+/// Joins rows laid out as one schema with rows laid out as another. Each row of the result holds
+/// the values of a left row followed by those of a right row.
+#[derive(Debug)]
+pub struct Joiner {
+    kind: JoinKind,
+    on: Expr,
+    strategy: Strategy,
+    left: Schema,
+    right: Schema,
+    schema: Schema,
+}
+
+impl Joiner {
+    /// Checks that `on` only reads columns of either side, so that a mistake shows up even when
+    /// both sides are empty.
+    pub fn new(
+        kind: JoinKind,
+        on: Expr,
+        left: &Schema,
+        right: &Schema,
+    ) -> Result<Joiner, EvalError> {
+        if let Some(aggregate) = on.find_aggregate() {
+            return Err(EvalError::MisusedAggregate(aggregate.to_string()));
+        }
+        // The columns are only there to be read, they aren't keys of anything.
+        let columns = left
+            .columns
+            .iter()
+            .chain(right.columns.iter())
+            .map(Column::unconstrained)
+            .collect();
+        let schema = Schema { columns };
+        expr_type(&on, &schema)?;
+
+        Ok(Joiner {
+            kind,
+            strategy: Strategy::plan(&on, left, right),
+            on,
+            left: left.clone(),
+            right: right.clone(),
+            schema,
+        })
+    }
+
+    pub fn strategy(&self) -> &Strategy {
+        &self.strategy
+    }
+
+    /// The layout of the joined rows.
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Pairs up every left row with every right row it matches, in the order of the left rows.
+    /// Rows an outer join keeps without a match are padded with NULLs, left rows in their place
+    /// and right rows after everything else.
+    pub fn join(&self, left_rows: Vec<Row>, right_rows: Vec<Row>) -> Result<Vec<Row>, EvalError> {
+        let hashed = match &self.strategy {
+            Strategy::Hash { left: keys, right } => {
+                let mut buckets: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+                for (i, row) in right_rows.iter().enumerate() {
+                    if let Some(key) = hash_key(right, &self.right, row)? {
+                        buckets.entry(key).or_default().push(i);
+                    }
+                }
+                Some((keys, buckets))
+            }
+            Strategy::NestedLoop => None,
+        };
+        let everything: Vec<usize> = match hashed {
+            Some(_) => Vec::new(),
+            None => (0..right_rows.len()).collect(),
+        };
+
+        let keeps_left = matches!(self.kind, JoinKind::Left | JoinKind::Full);
+        let keeps_right = matches!(self.kind, JoinKind::Right | JoinKind::Full);
+        let mut matched_right = vec![false; right_rows.len()];
+        let mut rows = Vec::new();
+        for left_row in left_rows.iter() {
+            let candidates = match &hashed {
+                Some((keys, buckets)) => match hash_key(keys, &self.left, left_row)? {
+                    Some(key) => buckets.get(&key).map_or(&[][..], Vec::as_slice),
+                    None => &[],
+                },
+                None => everything.as_slice(),
+            };
+
+            let mut matched = false;
+            for &i in candidates.iter() {
+                let row = concat(left_row, &right_rows[i]);
+                // A hash only narrows down the candidates, the whole condition still has to hold.
+                if eval_predicate(&self.on, &self.schema, &row)? {
+                    matched = true;
+                    matched_right[i] = true;
+                    rows.push(row);
+                }
+            }
+            if !matched && keeps_left {
+                rows.push(concat(left_row, &nulls(self.right.columns.len())));
+            }
+        }
+
+        if keeps_right {
+            let unmatched = right_rows
+                .iter()
+                .zip(matched_right)
+                .filter(|(_, matched)| !matched);
+            for (right_row, _) in unmatched {
+                rows.push(concat(&nulls(self.left.columns.len()), right_row));
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// The conditions that all have to hold for `expr` to be true.
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Binary {
+            left,
+            op: BinaryOp::And,
+            right,
+        } => {
+            let mut conditions = conjuncts(left);
+            conditions.extend(conjuncts(right));
+            conditions
+        }
+        expr => vec![expr],
+    }
+}
+
+/// Whether `expr` reads at least one column, and only ever columns of `schema`.
+fn reads_only(expr: &Expr, schema: &Schema) -> bool {
+    let mut columns = Vec::new();
+    collect_columns(expr, &mut columns);
+    !columns.is_empty()
+        && columns
+            .iter()
+            .all(|(table, column)| schema.find(*table, column).is_some())
+}
+
+/// Whether values of the two types can be told equal or not.
+fn comparable(
+    left: Result<Option<DataType>, EvalError>,
+    right: Result<Option<DataType>, EvalError>,
+) -> bool {
+    match (left, right) {
+        (Ok(Some(left)), Ok(Some(right))) => {
+            let numeric = |data_type| matches!(data_type, DataType::Integer | DataType::Real);
+            left == right || (numeric(left) && numeric(right))
+        }
+        _ => false,
+    }
+}
+
+fn collect_columns<'a>(expr: &'a Expr, columns: &mut Vec<(Option<&'a str>, &'a str)>) {
+    match expr {
+        Expr::Literal(_) => {}
+        Expr::Column { table, column } => columns.push((table.as_deref(), column)),
+        Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } => collect_columns(expr, columns),
+        Expr::Binary { left, right, .. } => {
+            collect_columns(left, columns);
+            collect_columns(right, columns);
+        }
+        Expr::Aggregate { arg, .. } => {
+            if let Some(arg) = arg {
+                collect_columns(arg, columns);
+            }
+        }
+    }
+}
+
+/// What `row` is hashed on, or `None` if any of its keys is NULL or NaN, neither of which ever
+/// equals anything. Integers are hashed as reals so that `1 = 1.0` finds its match, and `-0.0` is
+/// hashed as the `0.0` it equals.
+fn hash_key(keys: &[Expr], schema: &Schema, row: &Row) -> Result<Option<Vec<u8>>, EvalError> {
+    let mut values = Vec::with_capacity(keys.len());
+    for key in keys.iter() {
+        values.push(match eval(key, schema, row)? {
+            Value::Null => return Ok(None),
+            Value::Real(r) if r.is_nan() => return Ok(None),
+            // Matches -0.0 as well.
+            Value::Real(0.0) => Value::Real(0.0),
+            Value::Integer(i) => Value::Real(i as f64),
+            value => value,
+        });
+    }
+    Ok(Some(serialize_row(&Row::new(values))))
+}
+
+fn concat(left: &Row, right: &Row) -> Row {
+    Row::new(
+        left.values
+            .iter()
+            .chain(right.values.iter())
+            .cloned()
+            .collect(),
+    )
+}
+
+fn nulls(count: usize) -> Row {
+    Row::new(vec![Value::Null; count])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Statement;
+    use crate::parser::prepare;
+
+    /// Helper method to build a column of one of the joined tables.
+    fn column(table: &str, name: &str, data_type: DataType) -> Column {
+        Column {
+            table: Some(table.to_string()),
+            ..Column::new(name, data_type)
+        }
+    }
+
+    /// Helper method to refer to a column of one of the joined tables.
+    fn qualified(table: &str, name: &str) -> Expr {
+        Expr::Column {
+            table: Some(table.to_string()),
+            column: name.to_string(),
+        }
+    }
+
+    fn users() -> Schema {
+        Schema::new(vec![
+            column("u", "id", DataType::Integer),
+            column("u", "name", DataType::Text),
+        ])
+        .unwrap()
+    }
+
+    fn orders() -> Schema {
+        Schema::new(vec![
+            column("o", "user_id", DataType::Integer),
+            column("o", "total", DataType::Real),
+        ])
+        .unwrap()
+    }
+
+    fn user(id: i64, name: &str) -> Row {
+        Row::new(vec![Value::Integer(id), Value::Text(name.to_string())])
+    }
+
+    fn order(user_id: Option<i64>, total: f64) -> Row {
+        Row::new(vec![
+            user_id.map_or(Value::Null, Value::Integer),
+            Value::Real(total),
+        ])
+    }
+
+    /// Helper method to build a joiner from the ON condition of a query.
+    fn joiner(cmd: &str) -> Result<Joiner, EvalError> {
+        let join = match prepare(cmd).unwrap() {
+            Statement::Select(mut select) => select.joins.remove(0),
+            other => panic!("Expected a select, got {:?}", other),
+        };
+        Joiner::new(join.kind, join.on, &users(), &orders())
+    }
+
+    /// Helper method to join the test rows with both strategies, checking that they agree.
+    fn join_both_ways(cmd: &str) -> Vec<Row> {
+        let mut joiner = joiner(cmd).unwrap();
+        assert!(matches!(joiner.strategy(), Strategy::Hash { .. }));
+        let users = vec![user(1, "ann"), user(2, "bob"), user(3, "cy")];
+        let orders = vec![
+            order(Some(1), 5.0),
+            order(Some(4), 7.0),
+            order(Some(1), 2.5),
+            order(None, 1.0),
+            order(Some(4), -0.0),
+        ];
+        let hashed = joiner.join(users.clone(), orders.clone()).unwrap();
+        joiner.strategy = Strategy::NestedLoop;
+        let looped = joiner.join(users, orders).unwrap();
+        assert_eq!(hashed, looped);
+        hashed
+    }
+
+    fn ids(rows: &[Row]) -> Vec<(Value, Value)> {
+        rows.iter()
+            .map(|row| (row.values[0].clone(), row.values[2].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn plans_hash_join_on_equality() {
+        let plan = joiner("select * from u join o on o.user_id = u.id and o.total > 1").unwrap();
+        assert_eq!(
+            plan.strategy(),
+            &Strategy::Hash {
+                left: vec![qualified("u", "id")],
+                right: vec![qualified("o", "user_id")],
+            }
+        );
+
+        for cmd in [
+            "select * from u join o on u.id < o.user_id",
+            "select * from u join o on u.id = o.user_id or o.total > 1",
+            "select * from u join o on u.id = 1",
+            "select * from u join o on u.id + o.user_id = 2",
+            "select * from u join o on u.name = o.total",
+        ] {
+            assert_eq!(joiner(cmd).unwrap().strategy(), &Strategy::NestedLoop);
+        }
+    }
+
+    #[test]
+    fn joins_every_kind() {
+        let one = Value::Integer(1);
+        let (two, three, four) = (Value::Integer(2), Value::Integer(3), Value::Integer(4));
+
+        let rows = join_both_ways("select * from u join o on u.id = o.user_id");
+        assert_eq!(
+            ids(&rows),
+            vec![(one.clone(), one.clone()), (one.clone(), one.clone())]
+        );
+
+        let rows = join_both_ways("select * from u left join o on u.id = o.user_id");
+        assert_eq!(
+            ids(&rows),
+            vec![
+                (one.clone(), one.clone()),
+                (one.clone(), one.clone()),
+                (two.clone(), Value::Null),
+                (three.clone(), Value::Null),
+            ]
+        );
+
+        // Right rows without a match come last, and a NULL key never matches anything.
+        let rows = join_both_ways("select * from u right join o on u.id = o.user_id");
+        assert_eq!(
+            ids(&rows),
+            vec![
+                (one.clone(), one.clone()),
+                (one.clone(), one.clone()),
+                (Value::Null, four.clone()),
+                (Value::Null, Value::Null),
+                (Value::Null, four.clone()),
+            ]
+        );
+
+        let rows = join_both_ways("select * from u full join o on u.id = o.user_id");
+        assert_eq!(rows.len(), 7);
+        assert_eq!(
+            ids(&rows[2..4]),
+            vec![(two, Value::Null), (three, Value::Null)]
+        );
+        assert_eq!(rows[5], concat(&nulls(2), &order(None, 1.0)));
+
+        // Conditions other than the hashed equality still have to hold.
+        let rows = join_both_ways("select * from u join o on u.id = o.user_id and o.total > 3");
+        assert_eq!(rows, vec![concat(&user(1, "ann"), &order(Some(1), 5.0))]);
+
+        // 0 and -0.0 are equal, so they have to hash alike too.
+        let rows = join_both_ways("select * from u join o on u.id - 1 = o.total");
+        assert_eq!(
+            rows,
+            vec![
+                concat(&user(1, "ann"), &order(Some(4), -0.0)),
+                concat(&user(2, "bob"), &order(None, 1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn nan_keys_never_match() {
+        let key = [qualified("o", "total")];
+        assert_eq!(
+            hash_key(&key, &orders(), &order(Some(1), f64::NAN)),
+            Ok(None)
+        );
+        assert_eq!(
+            hash_key(&key, &orders(), &order(Some(1), -0.0)),
+            hash_key(&key, &orders(), &order(Some(1), 0.0))
+        );
+    }
+
+    #[test]
+    fn join_condition_errors() {
+        assert_eq!(
+            joiner("select * from u join o on u.id = o.missing").unwrap_err(),
+            EvalError::UnknownColumn("o.missing".to_string())
+        );
+        assert_eq!(
+            joiner("select * from u join o on count(*) = 1").unwrap_err(),
+            EvalError::MisusedAggregate("COUNT(*)".to_string())
+        );
+    }
+}
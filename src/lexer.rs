@@ -0,0 +1,560 @@
+/// A byte range within the source text that a token or error refers to.
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Reserved words of our SQL dialect. Matched case-insensitively.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Keyword {
+    Select,
+    Insert,
+    Into,
+    Values,
+    From,
+    Where,
+    And,
+    Or,
+    Not,
+    Is,
+    Null,
+    True,
+    False,
+    Update,
+    Set,
+    Delete,
+    Create,
+    Table,
+    Drop,
+    Primary,
+    Key,
+    Begin,
+    Commit,
+    Rollback,
+    Transaction,
+    Savepoint,
+    Release,
+    To,
+    As,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Offset,
+    Group,
+    Having,
+    Distinct,
+    Join,
+    Inner,
+    Left,
+    Right,
+    Full,
+    Outer,
+    On,
+}
+
+impl Keyword {
+    fn from_word(word: &str) -> Option<Keyword> {
+        let keyword = match word.to_ascii_uppercase().as_str() {
+            "SELECT" => Keyword::Select,
+            "INSERT" => Keyword::Insert,
+            "INTO" => Keyword::Into,
+            "VALUES" => Keyword::Values,
+            "FROM" => Keyword::From,
+            "WHERE" => Keyword::Where,
+            "AND" => Keyword::And,
+            "OR" => Keyword::Or,
+            "NOT" => Keyword::Not,
+            "IS" => Keyword::Is,
+            "NULL" => Keyword::Null,
+            "TRUE" => Keyword::True,
+            "FALSE" => Keyword::False,
+            "UPDATE" => Keyword::Update,
+            "SET" => Keyword::Set,
+            "DELETE" => Keyword::Delete,
+            "CREATE" => Keyword::Create,
+            "TABLE" => Keyword::Table,
+            "DROP" => Keyword::Drop,
+            "PRIMARY" => Keyword::Primary,
+            "KEY" => Keyword::Key,
+            "BEGIN" => Keyword::Begin,
+            "COMMIT" => Keyword::Commit,
+            "ROLLBACK" => Keyword::Rollback,
+            "TRANSACTION" => Keyword::Transaction,
+            "SAVEPOINT" => Keyword::Savepoint,
+            "RELEASE" => Keyword::Release,
+            "TO" => Keyword::To,
+            "AS" => Keyword::As,
+            "ORDER" => Keyword::Order,
+            "BY" => Keyword::By,
+            "ASC" => Keyword::Asc,
+            "DESC" => Keyword::Desc,
+            "LIMIT" => Keyword::Limit,
+            "OFFSET" => Keyword::Offset,
+            "GROUP" => Keyword::Group,
+            "HAVING" => Keyword::Having,
+            "DISTINCT" => Keyword::Distinct,
+            "JOIN" => Keyword::Join,
+            "INNER" => Keyword::Inner,
+            "LEFT" => Keyword::Left,
+            "RIGHT" => Keyword::Right,
+            "FULL" => Keyword::Full,
+            "OUTER" => Keyword::Outer,
+            "ON" => Keyword::On,
+            _ => return None,
+        };
+        Some(keyword)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Keyword::Select => "SELECT",
+            Keyword::Insert => "INSERT",
+            Keyword::Into => "INTO",
+            Keyword::Values => "VALUES",
+            Keyword::From => "FROM",
+            Keyword::Where => "WHERE",
+            Keyword::And => "AND",
+            Keyword::Or => "OR",
+            Keyword::Not => "NOT",
+            Keyword::Is => "IS",
+            Keyword::Null => "NULL",
+            Keyword::True => "TRUE",
+            Keyword::False => "FALSE",
+            Keyword::Update => "UPDATE",
+            Keyword::Set => "SET",
+            Keyword::Delete => "DELETE",
+            Keyword::Create => "CREATE",
+            Keyword::Table => "TABLE",
+            Keyword::Drop => "DROP",
+            Keyword::Primary => "PRIMARY",
+            Keyword::Key => "KEY",
+            Keyword::Begin => "BEGIN",
+            Keyword::Commit => "COMMIT",
+            Keyword::Rollback => "ROLLBACK",
+            Keyword::Transaction => "TRANSACTION",
+            Keyword::Savepoint => "SAVEPOINT",
+            Keyword::Release => "RELEASE",
+            Keyword::To => "TO",
+            Keyword::As => "AS",
+            Keyword::Order => "ORDER",
+            Keyword::By => "BY",
+            Keyword::Asc => "ASC",
+            Keyword::Desc => "DESC",
+            Keyword::Limit => "LIMIT",
+            Keyword::Offset => "OFFSET",
+            Keyword::Group => "GROUP",
+            Keyword::Having => "HAVING",
+            Keyword::Distinct => "DISTINCT",
+            Keyword::Join => "JOIN",
+            Keyword::Inner => "INNER",
+            Keyword::Left => "LEFT",
+            Keyword::Right => "RIGHT",
+            Keyword::Full => "FULL",
+            Keyword::Outer => "OUTER",
+            Keyword::On => "ON",
+        }
+    }
+}
+
+/// The different kinds of token the lexer can produce.
+#[derive(PartialEq, Debug, Clone)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    /// A table or column name. Unquoted identifiers are folded to lowercase.
+    Identifier(String),
+    /// A single-quoted string literal with its escapes resolved.
+    String(String),
+    /// A numeric literal, kept as written so the parser can pick its type.
+    Number(String),
+    Comma,
+    Semicolon,
+    Dot,
+    LeftParen,
+    RightParen,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Concat,
+    /// End of input. Always the final token.
+    Eof,
+}
+
+impl TokenKind {
+    /// A short human readable description, used when reporting errors.
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Keyword(keyword) => keyword.as_str().to_string(),
+            TokenKind::Identifier(name) => format!("identifier '{}'", name),
+            TokenKind::String(value) => format!("string '{}'", value),
+            TokenKind::Number(value) => format!("number {}", value),
+            TokenKind::Comma => "','".to_string(),
+            TokenKind::Semicolon => "';'".to_string(),
+            TokenKind::Dot => "'.'".to_string(),
+            TokenKind::LeftParen => "'('".to_string(),
+            TokenKind::RightParen => "')'".to_string(),
+            TokenKind::Star => "'*'".to_string(),
+            TokenKind::Plus => "'+'".to_string(),
+            TokenKind::Minus => "'-'".to_string(),
+            TokenKind::Slash => "'/'".to_string(),
+            TokenKind::Percent => "'%'".to_string(),
+            TokenKind::Eq => "'='".to_string(),
+            TokenKind::NotEq => "'!='".to_string(),
+            TokenKind::Lt => "'<'".to_string(),
+            TokenKind::LtEq => "'<='".to_string(),
+            TokenKind::Gt => "'>'".to_string(),
+            TokenKind::GtEq => "'>='".to_string(),
+            TokenKind::Concat => "'||'".to_string(),
+            TokenKind::Eof => "end of input".to_string(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Something in the input that isn't a valid token, e.g an unterminated string.
+#[derive(PartialEq, Debug, Clone)]
+pub struct LexError {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Splits SQL text into tokens on demand. The parser pulls one token at a time so that
+/// we can tell the statement type apart before looking at the rest of the input.
+#[derive(Clone)]
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Lexer<'a> {
+        Lexer { src, pos: 0 }
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.src[self.pos..].chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match (self.peek_char(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                // Line comments run until the end of the line.
+                (Some('-'), Some('-')) => {
+                    while let Some(c) = self.bump() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Produces the next token, or `Eof` once the input is exhausted.
+    pub fn next_token(&mut self) -> Result<Token, LexError> {
+        self.skip_whitespace_and_comments();
+        let start = self.pos;
+
+        let c = match self.bump() {
+            Some(c) => c,
+            None => {
+                return Ok(Token {
+                    kind: TokenKind::Eof,
+                    span: Span { start, end: start },
+                })
+            }
+        };
+
+        let kind = match c {
+            ',' => TokenKind::Comma,
+            ';' => TokenKind::Semicolon,
+            '.' if !matches!(self.peek_char(), Some(d) if d.is_ascii_digit()) => TokenKind::Dot,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '*' => TokenKind::Star,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '/' => TokenKind::Slash,
+            '%' => TokenKind::Percent,
+            '=' => TokenKind::Eq,
+            '!' if self.peek_char() == Some('=') => {
+                self.bump();
+                TokenKind::NotEq
+            }
+            '<' => match self.peek_char() {
+                Some('=') => {
+                    self.bump();
+                    TokenKind::LtEq
+                }
+                Some('>') => {
+                    self.bump();
+                    TokenKind::NotEq
+                }
+                _ => TokenKind::Lt,
+            },
+            '>' => match self.peek_char() {
+                Some('=') => {
+                    self.bump();
+                    TokenKind::GtEq
+                }
+                _ => TokenKind::Gt,
+            },
+            '|' if self.peek_char() == Some('|') => {
+                self.bump();
+                TokenKind::Concat
+            }
+            '\'' => self.lex_string(start)?,
+            '"' => self.lex_quoted_identifier(start)?,
+            c if c.is_ascii_digit() || c == '.' => self.lex_number(start),
+            c if c.is_alphabetic() || c == '_' => self.lex_word(start),
+            other => {
+                return Err(LexError {
+                    span: Span {
+                        start,
+                        end: self.pos,
+                    },
+                    message: format!("unexpected character '{}'", other),
+                })
+            }
+        };
+
+        Ok(Token {
+            kind,
+            span: Span {
+                start,
+                end: self.pos,
+            },
+        })
+    }
+
+    fn lex_word(&mut self, start: usize) -> TokenKind {
+        while matches!(self.peek_char(), Some(c) if c.is_alphanumeric() || c == '_') {
+            self.bump();
+        }
+
+        let word = &self.src[start..self.pos];
+        match Keyword::from_word(word) {
+            Some(keyword) => TokenKind::Keyword(keyword),
+            None => TokenKind::Identifier(word.to_lowercase()),
+        }
+    }
+
+    fn lex_number(&mut self, start: usize) -> TokenKind {
+        let mut seen_dot = self.src[start..self.pos] == *".";
+        loop {
+            match self.peek_char() {
+                Some(c) if c.is_ascii_digit() => {
+                    self.bump();
+                }
+                Some('.') if !seen_dot => {
+                    seen_dot = true;
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+
+        TokenKind::Number(self.src[start..self.pos].to_string())
+    }
+
+    /// Strings are wrapped in single quotes. A doubled quote ('') inside is a literal quote.
+    fn lex_string(&mut self, start: usize) -> Result<TokenKind, LexError> {
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('\'') if self.peek_char() == Some('\'') => {
+                    self.bump();
+                    value.push('\'');
+                }
+                Some('\'') => return Ok(TokenKind::String(value)),
+                Some(c) => value.push(c),
+                None => {
+                    return Err(LexError {
+                        span: Span {
+                            start,
+                            end: self.pos,
+                        },
+                        message: "unterminated string literal".to_string(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Double quoted identifiers keep their case and may contain any character.
+    fn lex_quoted_identifier(&mut self, start: usize) -> Result<TokenKind, LexError> {
+        let mut name = String::new();
+        loop {
+            match self.bump() {
+                Some('"') if self.peek_char() == Some('"') => {
+                    self.bump();
+                    name.push('"');
+                }
+                Some('"') => return Ok(TokenKind::Identifier(name)),
+                Some(c) => name.push(c),
+                None => {
+                    return Err(LexError {
+                        span: Span {
+                            start,
+                            end: self.pos,
+                        },
+                        message: "unterminated quoted identifier".to_string(),
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// Convenience wrapper that lexes the whole input at once, including the trailing `Eof`.
+pub fn tokenize(src: &str) -> Result<Vec<Token>, LexError> {
+    let mut lexer = Lexer::new(src);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token()?;
+        let done = token.kind == TokenKind::Eof;
+        tokens.push(token);
+        if done {
+            return Ok(tokens);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        tokenize(src)
+            .expect("Input should lex.")
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    // Keywords are case-insensitive while identifiers are folded to lowercase.
+    #[test]
+    fn lex_keywords_and_identifiers() {
+        assert_eq!(
+            kinds("SeLeCt * FROM Users"),
+            vec![
+                TokenKind::Keyword(Keyword::Select),
+                TokenKind::Star,
+                TokenKind::Keyword(Keyword::From),
+                TokenKind::Identifier("users".to_string()),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_quoted_identifier_keeps_case() {
+        assert_eq!(
+            kinds("\"Users\""),
+            vec![TokenKind::Identifier("Users".to_string()), TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn lex_string_with_escaped_quote() {
+        assert_eq!(
+            kinds("'it''s'"),
+            vec![TokenKind::String("it's".to_string()), TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn lex_numbers() {
+        assert_eq!(
+            kinds("42 3.14 .5"),
+            vec![
+                TokenKind::Number("42".to_string()),
+                TokenKind::Number("3.14".to_string()),
+                TokenKind::Number(".5".to_string()),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_operators() {
+        assert_eq!(
+            kinds("= != <> < <= > >= || + - / %"),
+            vec![
+                TokenKind::Eq,
+                TokenKind::NotEq,
+                TokenKind::NotEq,
+                TokenKind::Lt,
+                TokenKind::LtEq,
+                TokenKind::Gt,
+                TokenKind::GtEq,
+                TokenKind::Concat,
+                TokenKind::Plus,
+                TokenKind::Minus,
+                TokenKind::Slash,
+                TokenKind::Percent,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_skips_comments() {
+        assert_eq!(
+            kinds("select -- everything\n*"),
+            vec![
+                TokenKind::Keyword(Keyword::Select),
+                TokenKind::Star,
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_spans() {
+        let tokens = tokenize("  select  x").unwrap();
+        assert_eq!(tokens[0].span, Span { start: 2, end: 8 });
+        assert_eq!(tokens[1].span, Span { start: 10, end: 11 });
+    }
+
+    #[test]
+    fn lex_unterminated_string() {
+        let err = tokenize("'abc").unwrap_err();
+        assert_eq!(err.span, Span { start: 0, end: 4 });
+    }
+
+    #[test]
+    fn lex_unexpected_character() {
+        let err = tokenize("select #").unwrap_err();
+        assert_eq!(err.span, Span { start: 7, end: 8 });
+    }
+}
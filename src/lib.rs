@@ -1,2 +1,23 @@
+pub mod aggregate;
+pub mod ast;
 pub mod backend;
+pub mod btree;
+pub mod connection;
+pub mod error;
+pub mod eval;
+pub mod join;
+pub mod lexer;
+pub mod limits;
+pub mod mvcc;
+pub mod pager;
 pub mod parser;
+pub mod query;
+pub mod record;
+pub mod repl;
+pub mod schema;
+pub mod sort;
+pub mod transaction;
+pub mod value;
+pub mod wal;
+
+pub use error::Error;
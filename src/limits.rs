@@ -0,0 +1,195 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+
+/// How much a table, or a whole database, holds. Bytes count the encoded rows, not the pages
+/// they are stored in.
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    /// The usage of rows whose encoded records are `records` long.
+    pub fn of_records(records: impl IntoIterator<Item = usize>) -> Usage {
+        records.into_iter().fold(Usage::default(), |usage, len| {
+            usage
+                + Usage {
+                    rows: 1,
+                    bytes: len as u64,
+                }
+        })
+    }
+}
+
+impl Add for Usage {
+    type Output = Usage;
+
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            rows: self.rows.saturating_add(other.rows),
+            bytes: self.bytes.saturating_add(other.bytes),
+        }
+    }
+}
+
+impl Sub for Usage {
+    type Output = Usage;
+
+    fn sub(self, other: Usage) -> Usage {
+        Usage {
+            rows: self.rows.saturating_sub(other.rows),
+            bytes: self.bytes.saturating_sub(other.bytes),
+        }
+    }
+}
+
+/// Caps on how much a table, or a whole database, may hold. `None` means no cap.
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub struct Limits {
+    pub max_rows: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_rows.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Checks that `usage` stays within these limits, which belong to `table` or to the database
+    /// `table` is in, as `scope` says.
+    pub fn check(&self, usage: Usage, table: &str, scope: Scope) -> Result<(), TableFull> {
+        let full = |limit| TableFull {
+            table: table.to_string(),
+            scope,
+            limit,
+        };
+        if let Some(max) = self.max_rows.filter(|max| usage.rows > *max) {
+            return Err(full(Limit::Rows {
+                max,
+                needed: usage.rows,
+            }));
+        }
+        if let Some(max) = self.max_bytes.filter(|max| usage.bytes > *max) {
+            return Err(full(Limit::Bytes {
+                max,
+                needed: usage.bytes,
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Whose limit a statement ran into.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Scope {
+    Table,
+    Database,
+}
+
+/// The limit a statement ran into: at most `max` was allowed, the statement needed `needed`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Limit {
+    Rows { max: u64, needed: u64 },
+    Bytes { max: u64, needed: u64 },
+}
+
+/// A statement would have made `table` hold more than a limit allows. Nothing was written.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TableFull {
+    pub table: String,
+    pub scope: Scope,
+    pub limit: Limit,
+}
+
+impl fmt::Display for TableFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scope = match self.scope {
+            Scope::Table => "the table",
+            Scope::Database => "the database",
+        };
+        let (max, needed, unit) = match self.limit {
+            Limit::Rows { max, needed } => (max, needed, "rows"),
+            Limit::Bytes { max, needed } => (max, needed, "bytes"),
+        };
+        write!(
+            f,
+            "table '{}' is full: {} would hold {} {}, over its limit of {}",
+            self.table, scope, needed, unit, max
+        )
+    }
+}
+
+impl std::error::Error for TableFull {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_arithmetic() {
+        let usage = Usage::of_records([10, 20, 5]);
+        assert_eq!(usage, Usage { rows: 3, bytes: 35 });
+        assert_eq!(
+            usage - Usage::of_records([20]),
+            Usage { rows: 2, bytes: 15 }
+        );
+        assert_eq!(usage - usage - usage, Usage::default());
+    }
+
+    #[test]
+    fn limits_check() {
+        let limits = Limits {
+            max_rows: Some(2),
+            max_bytes: Some(100),
+        };
+        assert_eq!(
+            limits.check(
+                Usage {
+                    rows: 2,
+                    bytes: 100
+                },
+                "users",
+                Scope::Table
+            ),
+            Ok(())
+        );
+
+        let err = limits
+            .check(Usage { rows: 3, bytes: 10 }, "users", Scope::Table)
+            .unwrap_err();
+        assert_eq!(err.limit, Limit::Rows { max: 2, needed: 3 });
+        assert_eq!(
+            err.to_string(),
+            "table 'users' is full: the table would hold 3 rows, over its limit of 2"
+        );
+
+        let err = limits
+            .check(
+                Usage {
+                    rows: 1,
+                    bytes: 101,
+                },
+                "users",
+                Scope::Database,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "table 'users' is full: the database would hold 101 bytes, over its limit of 100"
+        );
+
+        assert!(Limits::default().is_unlimited());
+        assert_eq!(
+            Limits::default().check(
+                Usage {
+                    rows: 1 << 40,
+                    bytes: 1 << 50
+                },
+                "t",
+                Scope::Table
+            ),
+            Ok(())
+        );
+    }
+}
@@ -1,12 +1,13 @@
-use crate::backend::*;
+use sql_engine::repl::entrypoint;
+use std::env;
 
-/* All our modules must be imported into either our main.rs or lib.rs to 'unite' them for the compiler.
- * They cannot be imported into submodules, e.g backend.rs cannot do 'mod parser' as it would then expect
- * to find a directory called /backend/parser.rs or /backend/parser/mod.rs.
+/* All our modules are declared in lib.rs and compiled once as the `sql_engine` library.
+ * The binary only needs the REPL entrypoint from it.
  */
-mod backend;
-mod parser;
-
 fn main() {
-    entrypoint();
+    // The database file to open can be given as the first argument.
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "sql_engine.db".to_string());
+    entrypoint(&path);
 }
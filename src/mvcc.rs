@@ -0,0 +1,270 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Weak};
+
+/// Identifies a transaction. Ids only ever go up, so a smaller id started earlier. 0 is never used.
+pub type TxnId = u64;
+
+/// One version of a row: the record written by transaction `created`, which stays the current
+/// version until transaction `deleted` replaces or deletes it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Version {
+    pub created: TxnId,
+    pub deleted: Option<TxnId>,
+    pub record: Vec<u8>,
+}
+
+impl Version {
+    /// Whether this is the latest version of its row, as of everything written so far.
+    pub fn is_current(&self) -> bool {
+        self.deleted.is_none()
+    }
+}
+
+/// A consistent view of the database: everything committed before it was taken, and nothing
+/// committed after. A snapshot taken by a writing transaction also sees that transaction's own
+/// changes. Cloning is cheap, and versions it can see are kept for as long as any clone is alive.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    view: Arc<View>,
+}
+
+#[derive(Debug)]
+struct View {
+    /// The transaction this snapshot belongs to, if it writes.
+    own: Option<TxnId>,
+    /// The first transaction that hadn't started when the snapshot was taken.
+    horizon: TxnId,
+    /// Transactions that had started but not finished when the snapshot was taken.
+    active: BTreeSet<TxnId>,
+}
+
+impl Snapshot {
+    /// The transaction this snapshot belongs to, for snapshots that write.
+    pub fn id(&self) -> Option<TxnId> {
+        self.view.own
+    }
+
+    /// Whether the changes made by transaction `txn` are visible in this snapshot.
+    pub fn sees(&self, txn: TxnId) -> bool {
+        self.view.own == Some(txn) || (txn < self.view.horizon && !self.view.active.contains(&txn))
+    }
+
+    /// The version of a row this snapshot sees, if it sees the row at all.
+    pub fn visible<'a>(&self, versions: &'a [Version]) -> Option<&'a Version> {
+        Some(&versions[self.position(versions)?])
+    }
+
+    fn position(&self, versions: &[Version]) -> Option<usize> {
+        versions.iter().position(|version| {
+            self.sees(version.created) && version.deleted.is_none_or(|txn| !self.sees(txn))
+        })
+    }
+
+    /// Marks the version of a row this snapshot sees as deleted by its transaction. Returns
+    /// whether there was one. A version the transaction wrote itself is dropped outright, since no
+    /// one else can ever see it.
+    pub fn delete(&self, versions: &mut Vec<Version>) -> bool {
+        let own = self.writer();
+        let index = match self.position(versions) {
+            Some(index) => index,
+            None => return false,
+        };
+        if versions[index].created == own {
+            versions.remove(index);
+        } else {
+            versions[index].deleted = Some(own);
+        }
+        true
+    }
+
+    /// Makes `record` the version of a row this snapshot sees, replacing the one it saw before.
+    pub fn put(&self, versions: &mut Vec<Version>, record: Vec<u8>) {
+        self.delete(versions);
+        versions.push(Version {
+            created: self.writer(),
+            deleted: None,
+            record,
+        });
+    }
+
+    fn writer(&self) -> TxnId {
+        self.view
+            .own
+            .expect("only snapshots of writing transactions can change rows")
+    }
+
+    /// The oldest transaction, other than its own, whose changes this snapshot doesn't see.
+    fn oldest_unseen(&self) -> TxnId {
+        let oldest_active = self.view.active.first().copied();
+        oldest_active.map_or(self.view.horizon, |txn| txn.min(self.view.horizon))
+    }
+}
+
+/// Hands out transaction ids and snapshots, and keeps track of which transactions are running and
+/// which snapshots are still in use.
+pub struct Transactions {
+    next: TxnId,
+    active: BTreeSet<TxnId>,
+    snapshots: Vec<Weak<View>>,
+}
+
+impl Transactions {
+    /// Picks up from `next`, the first id that no transaction in the database has used yet.
+    pub fn new(next: TxnId) -> Transactions {
+        Transactions {
+            next: next.max(1),
+            active: BTreeSet::new(),
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// The first id that no transaction has used yet.
+    pub fn next_id(&self) -> TxnId {
+        self.next
+    }
+
+    /// Starts a transaction that writes, returning the snapshot it reads and writes through.
+    pub fn start(&mut self) -> Snapshot {
+        let own = self.next;
+        self.next += 1;
+        let snapshot = self.take(Some(own), own);
+        self.active.insert(own);
+        snapshot
+    }
+
+    /// A snapshot of everything committed so far, for reading.
+    pub fn snapshot(&mut self) -> Snapshot {
+        self.take(None, self.next)
+    }
+
+    fn take(&mut self, own: Option<TxnId>, horizon: TxnId) -> Snapshot {
+        // Snapshots that are no longer in use are forgotten here too, so that reading alone
+        // doesn't keep adding to the list.
+        self.snapshots.retain(|view| view.strong_count() > 0);
+        let view = Arc::new(View {
+            own,
+            horizon,
+            active: self.active.clone(),
+        });
+        self.snapshots.push(Arc::downgrade(&view));
+        Snapshot { view }
+    }
+
+    /// Ends the transaction `snapshot` belongs to, whether it committed or rolled back.
+    pub fn finish(&mut self, snapshot: &Snapshot) {
+        if let Some(own) = snapshot.view.own {
+            self.active.remove(&own);
+        }
+    }
+
+    /// Versions deleted by a transaction older than this are deleted as far as every snapshot
+    /// still in use is concerned, and any snapshot taken from now on. Nobody can see them anymore.
+    pub fn horizon(&mut self) -> TxnId {
+        self.snapshots.retain(|view| view.strong_count() > 0);
+        let mut horizon = self.active.first().copied().unwrap_or(self.next);
+        for view in self.snapshots.iter().filter_map(Weak::upgrade) {
+            horizon = horizon.min(Snapshot { view }.oldest_unseen());
+        }
+        horizon
+    }
+}
+
+/// Drops the versions of a row that were deleted before `horizon`, returning how many went.
+pub fn prune(versions: &mut Vec<Version>, horizon: TxnId) -> usize {
+    let before = versions.len();
+    versions.retain(|version| version.deleted.is_none_or(|txn| txn >= horizon));
+    before - versions.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Helper method to make a version holding a single byte.
+    fn version(created: TxnId, deleted: Option<TxnId>, byte: u8) -> Version {
+        Version {
+            created,
+            deleted,
+            record: vec![byte],
+        }
+    }
+
+    #[test]
+    fn snapshots_see_what_was_committed_before_them() {
+        let mut txns = Transactions::new(1);
+        let writer = txns.start();
+        let reader = txns.snapshot();
+        let mut versions = vec![];
+        writer.put(&mut versions, vec![1]);
+
+        // The writer sees its own change, a reader that started alongside it doesn't.
+        assert_eq!(writer.visible(&versions), Some(&version(1, None, 1)));
+        assert_eq!(reader.visible(&versions), None);
+        txns.finish(&writer);
+        assert_eq!(reader.visible(&versions), None);
+        assert_eq!(
+            txns.snapshot().visible(&versions),
+            Some(&version(1, None, 1))
+        );
+
+        let later = txns.start();
+        later.put(&mut versions, vec![2]);
+        assert_eq!(versions, vec![version(1, Some(2), 1), version(2, None, 2)]);
+        assert_eq!(
+            txns.snapshot().visible(&versions),
+            Some(&version(1, Some(2), 1))
+        );
+        assert_eq!(later.visible(&versions), Some(&version(2, None, 2)));
+    }
+
+    #[test]
+    fn deleting_own_versions_drops_them() {
+        let mut txns = Transactions::new(7);
+        let writer = txns.start();
+        let mut versions = vec![version(3, None, 1)];
+        writer.put(&mut versions, vec![2]);
+        writer.put(&mut versions, vec![3]);
+        assert_eq!(versions, vec![version(3, Some(7), 1), version(7, None, 3)]);
+
+        assert!(writer.delete(&mut versions));
+        assert!(!writer.delete(&mut versions));
+        assert_eq!(versions, vec![version(3, Some(7), 1)]);
+    }
+
+    #[test]
+    fn horizon_waits_for_snapshots_in_use() {
+        let mut txns = Transactions::new(1);
+        let first = txns.start();
+        txns.finish(&first);
+        drop(first);
+        assert_eq!(txns.horizon(), 2);
+
+        let reader = txns.snapshot();
+        let writer = txns.start();
+        let mut versions = vec![version(1, None, 1)];
+        writer.put(&mut versions, vec![2]);
+        txns.finish(&writer);
+        drop(writer);
+
+        // The reader still sees the first version, so it has to stay.
+        assert_eq!(txns.horizon(), 2);
+        assert_eq!(prune(&mut versions, txns.horizon()), 0);
+        drop(reader);
+        assert_eq!(txns.horizon(), 3);
+        assert_eq!(prune(&mut versions, txns.horizon()), 1);
+        assert_eq!(versions, vec![version(2, None, 2)]);
+    }
+
+    #[test]
+    fn dropped_snapshots_are_forgotten() {
+        let mut txns = Transactions::new(1);
+        let kept = txns.snapshot();
+        for _ in 0..100 {
+            drop(txns.snapshot());
+        }
+        assert_eq!(txns.snapshots.len(), 2);
+        drop(kept);
+        drop(txns.snapshot());
+        assert_eq!(txns.snapshots.len(), 1);
+    }
+}
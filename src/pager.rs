@@ -0,0 +1,930 @@
+use crate::wal::{wal_path, Wal};
+use log::debug;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Size in bytes of every page in the database file.
+pub const PAGE_SIZE: usize = 4096;
+
+pub type Page = [u8; PAGE_SIZE];
+
+/// Identifies the file as one of ours, and which version of the format it uses.
+const MAGIC: &[u8; 16] = b"sql_engine fmt5\0";
+
+/// Page 0 holds the file header, page 1 is the start of the catalog.
+const HEADER_PAGE: u32 = 0;
+pub const CATALOG_PAGE: u32 = 1;
+
+/// How many pages a pager keeps in memory, unless `Pager::set_cache_pages` says otherwise.
+pub const DEFAULT_CACHE_PAGES: usize = 2000;
+
+/// Once the log holds this many pages, committing folds it back into the database file.
+const CHECKPOINT_FRAMES: u64 = 1000;
+
+/// Every page in a chain starts with the number of the next page (0 at the end of the chain)
+/// followed by how many bytes of the page are in use.
+const CHAIN_HEADER_SIZE: usize = 6;
+const CHAIN_CAPACITY: usize = PAGE_SIZE - CHAIN_HEADER_SIZE;
+
+/// Something went wrong reading or writing the database file.
+#[derive(PartialEq, Debug, Clone)]
+pub enum StorageError {
+    Io(String),
+    /// The file isn't a database, or its contents don't make sense.
+    Corrupt(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(message) => write!(f, "I/O error: {}", message),
+            StorageError::Corrupt(message) => write!(f, "database file is corrupt: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Io(err.to_string())
+    }
+}
+
+/// Reads and writes fixed-size pages of a single database file.
+///
+/// Pages are only read from disk the first time they are asked for, and changes stay in memory
+/// until `flush` commits them to the write-ahead log. They reach the database file itself at the
+/// next checkpoint. Once the cache is full, pages that can be read back from the file make room
+/// for new ones. A pager without a file keeps every page in memory.
+///
+/// Reading a page only needs a shared reference, so any number of threads can read at once. The
+/// cache has a lock of its own for when a page has to be brought in from disk.
+pub struct Pager {
+    file: Option<File>,
+    wal: Option<Wal>,
+    cache: Mutex<Cache>,
+    dirty: BTreeSet<u32>,
+    /// Pages committed to the log since the last checkpoint.
+    logged: BTreeSet<u32>,
+    /// First page of the list of pages that are no longer used, 0 when there are none.
+    free_head: u32,
+    /// The first transaction id that hasn't been handed out yet.
+    next_txn: u64,
+    /// How things stood when the open transaction began, followed by how they stood at each
+    /// savepoint inside it. Empty outside of a transaction.
+    journal: Vec<Journal>,
+    /// How many more pages can be changed before changing one fails, for tests to make writes
+    /// fail part way through a statement.
+    #[cfg(test)]
+    writes_left: Option<usize>,
+}
+
+/// The pages a pager holds in memory.
+struct Cache {
+    /// Pages that have been read or written, indexed by page number. `None` for pages that were
+    /// never read, or have been dropped since. Readers get a page of their own to hold on to, and
+    /// a page that is still being read is copied before it changes.
+    pages: Vec<Option<Arc<Page>>>,
+    /// How many pages to hold on to. Changed pages can't be dropped until they are in the
+    /// database file, so a big transaction can go over this for a while.
+    capacity: usize,
+    /// How many of `pages` are in memory.
+    len: usize,
+    /// Pages that were the same as in the database file when they were put here, oldest first.
+    /// Pages that changed since are skipped when it comes to dropping one.
+    clean: VecDeque<u32>,
+}
+
+impl Cache {
+    fn new(pages: Vec<Option<Arc<Page>>>) -> Cache {
+        Cache {
+            len: pages.iter().filter(|page| page.is_some()).count(),
+            pages,
+            capacity: DEFAULT_CACHE_PAGES,
+            clean: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, number: u32, page: Arc<Page>) {
+        if self.pages[number as usize].replace(page).is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn push(&mut self, page: Arc<Page>) {
+        self.pages.push(Some(page));
+        self.len += 1;
+    }
+
+    fn truncate(&mut self, count: usize) {
+        if count < self.pages.len() {
+            self.len -= self.pages[count..]
+                .iter()
+                .filter(|page| page.is_some())
+                .count();
+            self.pages.truncate(count);
+        }
+    }
+
+    /// Gets a page that is in memory to change, copying it first if someone is still reading it.
+    fn page_mut(&mut self, number: u32) -> &mut Page {
+        Arc::make_mut(self.pages[number as usize].as_mut().unwrap())
+    }
+
+    /// Notes that `number` is the same as in the database file, so it can be dropped to make room.
+    fn mark_clean(&mut self, number: u32) {
+        self.clean.push_back(number);
+        // A page can be marked more than once before it is dropped. Clearing out the extra entries
+        // every so often keeps the queue from outgrowing the cache.
+        if self.clean.len() > 2 * self.pages.len() {
+            let mut seen = BTreeSet::new();
+            let pages = &self.pages;
+            self.clean.retain(|number| {
+                pages.get(*number as usize).is_some_and(Option::is_some) && seen.insert(*number)
+            });
+        }
+    }
+
+    /// Drops the oldest clean pages until there is room for one more, leaving the ones `pinned`
+    /// says must stay.
+    fn make_room(&mut self, pinned: impl Fn(u32) -> bool) {
+        while self.len >= self.capacity {
+            let number = match self.clean.pop_front() {
+                Some(number) => number,
+                None => return,
+            };
+            if pinned(number) {
+                continue;
+            }
+            if let Some(slot @ Some(_)) = self.pages.get_mut(number as usize) {
+                *slot = None;
+                self.len -= 1;
+            }
+        }
+    }
+}
+
+/// Enough to put the pager back the way it was when a transaction or savepoint began: every page
+/// as it was before it first changed since then, and what the header said.
+struct Journal {
+    pages: BTreeMap<u32, Arc<Page>>,
+    page_count: u32,
+    free_head: u32,
+    dirty: BTreeSet<u32>,
+}
+
+impl Pager {
+    /// Opens the database file at `path`, creating and initialising it if it doesn't exist yet.
+    /// Work committed to the write-ahead log before a crash is recovered first.
+    pub fn open(path: impl AsRef<Path>) -> Result<Pager, StorageError> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let recovered = Wal::recover(&wal_path(path))?;
+        if !recovered.is_empty() {
+            debug!(
+                "recovering {} pages from the write-ahead log",
+                recovered.len()
+            );
+            for (number, page) in recovered.iter() {
+                file.seek(SeekFrom::Start(*number as u64 * PAGE_SIZE as u64))?;
+                file.write_all(&page[..])?;
+            }
+            file.sync_all()?;
+        }
+
+        let length = file.metadata()?.len();
+        if length == 0 {
+            let mut pager = Pager::empty(Some(file));
+            pager.wal = Some(Wal::create(&wal_path(path))?);
+            pager.flush()?;
+            pager.checkpoint()?;
+            return Ok(pager);
+        }
+        if length % PAGE_SIZE as u64 != 0 {
+            return Err(StorageError::Corrupt(format!(
+                "file size {} is not a multiple of the page size",
+                length
+            )));
+        }
+
+        let page_count = (length / PAGE_SIZE as u64) as usize;
+        let mut pager = Pager {
+            file: Some(file),
+            wal: None,
+            cache: Mutex::new(Cache::new(vec![None; page_count])),
+            dirty: BTreeSet::new(),
+            logged: BTreeSet::new(),
+            free_head: 0,
+            next_txn: 1,
+            journal: Vec::new(),
+            #[cfg(test)]
+            writes_left: None,
+        };
+
+        let header = pager.page(HEADER_PAGE)?;
+        if &header[0..16] != MAGIC {
+            return Err(StorageError::Corrupt("not a database file".to_string()));
+        }
+        if read_u32(&header, 16) as usize != PAGE_SIZE {
+            return Err(StorageError::Corrupt(format!(
+                "page size {} is not supported",
+                read_u32(&header, 16)
+            )));
+        }
+        if read_u32(&header, 20) as usize != page_count {
+            return Err(StorageError::Corrupt(format!(
+                "header says there are {} pages, the file holds {}",
+                read_u32(&header, 20),
+                page_count
+            )));
+        }
+        let free_head = read_u32(&header, 24);
+        let next_txn = read_u64(&header, 28);
+        pager.free_head = free_head;
+        pager.next_txn = next_txn;
+
+        // Everything the log held is in the database file now, so it can start over.
+        pager.wal = Some(Wal::create(&wal_path(path))?);
+        Ok(pager)
+    }
+
+    /// A pager that never touches the disk.
+    pub fn memory() -> Pager {
+        Pager::empty(None)
+    }
+
+    /// Sets up the header and an empty catalog for a brand new database.
+    fn empty(file: Option<File>) -> Pager {
+        let mut pager = Pager {
+            file,
+            wal: None,
+            cache: Mutex::new(Cache::new(Vec::new())),
+            dirty: BTreeSet::new(),
+            logged: BTreeSet::new(),
+            free_head: 0,
+            next_txn: 1,
+            journal: Vec::new(),
+            #[cfg(test)]
+            writes_left: None,
+        };
+        pager.push_page();
+        pager.push_page();
+        pager.write_header();
+        pager
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.lock_cache().pages.len() as u32
+    }
+
+    /// Sets how many pages to keep in memory. Past that, pages are read from the database file
+    /// again when they are needed. Pages of a pager without a file are always kept.
+    pub fn set_cache_pages(&mut self, pages: usize) {
+        self.cache_mut().capacity = pages;
+    }
+
+    /// The first transaction id that no transaction in the database has used.
+    pub fn next_txn(&self) -> u64 {
+        self.next_txn
+    }
+
+    /// Moves the next transaction id along. It is stored with the next flush.
+    pub fn set_next_txn(&mut self, next_txn: u64) {
+        if next_txn != self.next_txn {
+            self.next_txn = next_txn;
+            self.write_header();
+        }
+    }
+
+    /// Gets a page, reading it from disk if this is the first time it is used. The page stays as
+    /// it was when this was called, even if it changes afterwards.
+    pub fn page(&self, number: u32) -> Result<Arc<Page>, StorageError> {
+        let mut cache = self.lock_cache();
+        self.load(&mut cache, number)
+    }
+
+    /// Gets a page to change. It will be written out on the next flush.
+    pub fn page_mut(&mut self, number: u32) -> Result<&mut Page, StorageError> {
+        #[cfg(test)]
+        if let Some(left) = self.writes_left.as_mut() {
+            if *left == 0 {
+                return Err(StorageError::Io("injected write failure".to_string()));
+            }
+            *left -= 1;
+        }
+        self.page(number)?;
+        self.save_original(number);
+        self.dirty.insert(number);
+        Ok(self.cache_mut().page_mut(number))
+    }
+
+    /// Keeps a copy of a page that is about to change for the first time since the latest
+    /// savepoint. Pages added since then don't need one, rolling back drops them altogether.
+    fn save_original(&mut self, number: u32) {
+        if let Some(journal) = self.journal.last_mut() {
+            if number < journal.page_count && !journal.pages.contains_key(&number) {
+                let cache = self.cache.get_mut().unwrap_or_else(PoisonError::into_inner);
+                let page = cache.pages[number as usize].clone().unwrap();
+                journal.pages.insert(number, page);
+            }
+        }
+    }
+
+    /// Makes every change to a page after the next `writes` fail, or lets them all through again
+    /// when `None`.
+    #[cfg(test)]
+    pub(crate) fn fail_writes_after(&mut self, writes: Option<usize>) {
+        self.writes_left = writes;
+    }
+
+    /// Starts a transaction. Nothing changed from here on is committed by `flush` until the
+    /// transaction ends, and `rollback` can undo all of it.
+    pub fn begin(&mut self) {
+        self.journal.clear();
+        self.savepoint();
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        !self.journal.is_empty()
+    }
+
+    /// Marks a point inside the open transaction that `rollback_to` can go back to. Savepoints
+    /// are numbered from 1 in the order they were made, the start of the transaction is 0.
+    pub fn savepoint(&mut self) -> usize {
+        self.journal.push(Journal {
+            pages: BTreeMap::new(),
+            page_count: self.page_count(),
+            free_head: self.free_head,
+            dirty: self.dirty.clone(),
+        });
+        self.journal.len() - 1
+    }
+
+    /// Throws away every change made since savepoint `depth`, along with the savepoints made after
+    /// it. Savepoint `depth` itself stays, so it can be rolled back to again.
+    pub fn rollback_to(&mut self, depth: usize) {
+        if depth >= self.journal.len() {
+            return;
+        }
+        // Undo the latest changes first, so every page ends up as it was at the savepoint.
+        let changed = self.dirty.clone();
+        while self.journal.len() > depth {
+            let journal = self.journal.pop().unwrap();
+            let cache = self.cache_mut();
+            cache.truncate(journal.page_count as usize);
+            for (number, page) in journal.pages {
+                cache.insert(number, page);
+            }
+            self.free_head = journal.free_head;
+            self.dirty = journal.dirty;
+        }
+        // Pages that are back to how they were before they changed are clean again.
+        let page_count = self.page_count();
+        let reverted: Vec<u32> = changed
+            .difference(&self.dirty)
+            .filter(|number| **number < page_count && !self.logged.contains(number))
+            .copied()
+            .collect();
+        let mut cache = self.lock_cache();
+        for number in reverted {
+            self.mark_clean(&mut cache, number);
+        }
+        self.make_room(&mut cache);
+        drop(cache);
+        self.savepoint();
+    }
+
+    /// Forgets savepoint `depth` and the ones made after it, keeping their changes as part of
+    /// whatever came before.
+    pub fn release(&mut self, depth: usize) {
+        if depth == 0 || depth >= self.journal.len() {
+            return;
+        }
+        for journal in self.journal.split_off(depth) {
+            // The copy the earlier savepoint holds, if any, is older and so the one to keep.
+            let parent = self.journal.last_mut().unwrap();
+            for (number, page) in journal.pages {
+                if number < parent.page_count {
+                    parent.pages.entry(number).or_insert(page);
+                }
+            }
+        }
+    }
+
+    /// Throws away every change made since the open transaction began, and ends it.
+    pub fn rollback(&mut self) {
+        self.rollback_to(0);
+        self.journal.clear();
+    }
+
+    /// Helper method to get a page out of `cache`, reading it from the database file if it isn't
+    /// there.
+    fn load(&self, cache: &mut Cache, number: u32) -> Result<Arc<Page>, StorageError> {
+        let slot = match cache.pages.get(number as usize) {
+            Some(slot) => slot,
+            None => {
+                return Err(StorageError::Corrupt(format!(
+                    "page {} is past the end of the file",
+                    number
+                )))
+            }
+        };
+        if let Some(page) = slot {
+            return Ok(Arc::clone(page));
+        }
+
+        self.make_room(cache);
+        let mut page = [0; PAGE_SIZE];
+        if let Some(mut file) = self.file.as_ref() {
+            file.seek(SeekFrom::Start(number as u64 * PAGE_SIZE as u64))?;
+            file.read_exact(&mut page[..])?;
+        }
+        let page = Arc::new(page);
+        cache.insert(number, Arc::clone(&page));
+        self.mark_clean(cache, number);
+        Ok(page)
+    }
+
+    /// Notes that a page is the same as in the database file. Pages of a pager without a file
+    /// have nowhere to be read back from, so they are never dropped.
+    fn mark_clean(&self, cache: &mut Cache, number: u32) {
+        if self.file.is_some() {
+            cache.mark_clean(number);
+        }
+    }
+
+    /// Drops pages from a full cache to make room for another one. Pages that changed since they
+    /// were last in the database file have to stay, as do the header and pages a rollback needs.
+    fn make_room(&self, cache: &mut Cache) {
+        let (dirty, logged, journal) = (&self.dirty, &self.logged, &self.journal);
+        cache.make_room(|number| {
+            number == HEADER_PAGE
+                || dirty.contains(&number)
+                || logged.contains(&number)
+                || journal
+                    .iter()
+                    .any(|journal| journal.pages.contains_key(&number))
+        });
+    }
+
+    /// Hands out a zeroed page, reusing one from the free list when possible.
+    pub fn allocate(&mut self) -> Result<u32, StorageError> {
+        if self.free_head == 0 {
+            return Ok(self.push_page());
+        }
+
+        let number = self.free_head;
+        let page = self.page_mut(number)?;
+        let next = read_u32(page, 0);
+        page.fill(0);
+        self.free_head = next;
+        self.write_header();
+        Ok(number)
+    }
+
+    fn push_page(&mut self) -> u32 {
+        let mut cache = self.lock_cache();
+        self.make_room(&mut cache);
+        let number = cache.pages.len() as u32;
+        cache.push(Arc::new([0; PAGE_SIZE]));
+        drop(cache);
+        self.dirty.insert(number);
+        number
+    }
+
+    /// Puts a page on the free list so it can be handed out again.
+    pub fn free(&mut self, number: u32) -> Result<(), StorageError> {
+        let free_head = self.free_head;
+        let page = self.page_mut(number)?;
+        page.fill(0);
+        write_u32(page, 0, free_head);
+        self.free_head = number;
+        self.write_header();
+        Ok(())
+    }
+
+    /// Reads back everything stored in the chain of pages starting at `first`.
+    pub fn read_chain(&self, first: u32) -> Result<Vec<u8>, StorageError> {
+        let mut bytes = Vec::new();
+        let mut number = first;
+        loop {
+            let page = self.page(number)?;
+            let next = read_u32(&page, 0);
+            let used = read_u16(&page, 4) as usize;
+            if used > CHAIN_CAPACITY {
+                return Err(StorageError::Corrupt(format!(
+                    "page {} claims to hold {} bytes",
+                    number, used
+                )));
+            }
+            bytes.extend_from_slice(&page[CHAIN_HEADER_SIZE..CHAIN_HEADER_SIZE + used]);
+
+            if next == 0 {
+                return Ok(bytes);
+            }
+            number = next;
+        }
+    }
+
+    /// Stores `bytes` in the chain of pages starting at `first`, reusing the pages it already has,
+    /// adding pages as needed and freeing the ones that are left over.
+    pub fn write_chain(&mut self, first: u32, bytes: &[u8]) -> Result<(), StorageError> {
+        let mut chunks = bytes.chunks(CHAIN_CAPACITY);
+        let mut number = first;
+        loop {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let mut next = read_u32(&*self.page(number)?, 0);
+            let more = chunks.len() > 0;
+            if more && next == 0 {
+                next = self.allocate()?;
+            }
+
+            let page = self.page_mut(number)?;
+            page.fill(0);
+            write_u32(page, 0, if more { next } else { 0 });
+            write_u16(page, 4, chunk.len() as u16);
+            page[CHAIN_HEADER_SIZE..CHAIN_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+
+            if !more {
+                // Whatever used to come after this page isn't needed anymore.
+                if next != 0 {
+                    self.free_chain(next)?;
+                }
+                return Ok(());
+            }
+            number = next;
+        }
+    }
+
+    /// Frees every page of the chain starting at `first`.
+    pub fn free_chain(&mut self, first: u32) -> Result<(), StorageError> {
+        let mut number = first;
+        while number != 0 {
+            let next = read_u32(&*self.page(number)?, 0);
+            self.free(number)?;
+            number = next;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self) {
+        let page_count = self.page_count();
+        let free_head = self.free_head;
+        let next_txn = self.next_txn;
+        self.save_original(HEADER_PAGE);
+        let header = self.cache_mut().page_mut(HEADER_PAGE);
+        header[0..16].copy_from_slice(MAGIC);
+        write_u32(header, 16, PAGE_SIZE as u32);
+        write_u32(header, 20, page_count);
+        write_u32(header, 24, free_head);
+        write_u64(header, 28, next_txn);
+        self.dirty.insert(HEADER_PAGE);
+    }
+
+    /// Commits every changed page to the write-ahead log and waits for the disk to confirm it has
+    /// them, ending the open transaction if there is one. Checkpoints once the log has grown long
+    /// enough.
+    pub fn flush(&mut self) -> Result<(), StorageError> {
+        self.journal.clear();
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        // The page count in the header may be out of date if pages were added.
+        self.write_header();
+
+        let wal = match &mut self.wal {
+            Some(wal) => wal,
+            None => {
+                self.dirty.clear();
+                return Ok(());
+            }
+        };
+
+        let pages = &self
+            .cache
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pages;
+        wal.append(
+            self.dirty
+                .iter()
+                .map(|number| (*number, pages[*number as usize].as_deref().unwrap())),
+        )?;
+        self.logged.append(&mut self.dirty);
+
+        if wal.frames() >= CHECKPOINT_FRAMES {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Copies every page committed to the log into the database file, then empties the log.
+    /// Changes that haven't been flushed yet stay where they are.
+    pub fn checkpoint(&mut self) -> Result<(), StorageError> {
+        let (file, wal) = match (&mut self.file, &mut self.wal) {
+            (Some(file), Some(wal)) => (file, wal),
+            _ => return Ok(()),
+        };
+        if self.logged.is_empty() {
+            return Ok(());
+        }
+        debug!("checkpointing {} pages", self.logged.len());
+
+        // A page changed since it was logged holds uncommitted work, so it can't be copied out of
+        // memory as it is. Flushing first means this never happens.
+        if !self.dirty.is_disjoint(&self.logged) {
+            return Err(StorageError::Io(
+                "cannot checkpoint pages with uncommitted changes".to_string(),
+            ));
+        }
+
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        for number in self.logged.iter() {
+            let page = cache.pages[*number as usize].as_deref().unwrap();
+            file.seek(SeekFrom::Start(*number as u64 * PAGE_SIZE as u64))?;
+            file.write_all(page)?;
+        }
+        file.sync_all()?;
+        wal.reset()?;
+        for number in mem::take(&mut self.logged) {
+            self.mark_clean(&mut cache, number);
+        }
+        self.make_room(&mut cache);
+        Ok(())
+    }
+
+    /// A thread that panicked while holding the cache doesn't stop the others from using it, as
+    /// it is never left half changed.
+    fn lock_cache(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cache_mut(&mut self) -> &mut Cache {
+        self.cache.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Pager {
+    /// Folds the log into the database file on the way out, so a database that was closed cleanly
+    /// is a single file again. An open transaction is rolled back. If that fails the log stays,
+    /// to be replayed on the next open.
+    fn drop(&mut self) {
+        // Work that was never committed doesn't belong in the database file.
+        self.rollback();
+        if self.checkpoint().is_ok() {
+            if let Some(wal) = self.wal.take() {
+                let _ = wal.remove();
+            }
+        }
+    }
+}
+
+fn read_u64(page: &Page, offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&page[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write_u64(page: &mut Page, offset: usize, value: u64) {
+    page[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u16(page: &Page, offset: usize) -> u16 {
+    u16::from_le_bytes([page[offset], page[offset + 1]])
+}
+
+fn write_u16(page: &mut Page, offset: usize, value: u16) {
+    page[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u32(page: &Page, offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&page[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn write_u32(page: &mut Page, offset: usize, value: u32) {
+    page[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Helper method to get a path for a database file that doesn't exist yet.
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("sql_engine_pager_{}_{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn new_pager_has_header_and_catalog() {
+        let pager = Pager::memory();
+        assert_eq!(pager.page_count(), 2);
+        assert_eq!(pager.read_chain(CATALOG_PAGE).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn chain_round_trip_across_pages() {
+        let mut pager = Pager::memory();
+        let first = pager.allocate().unwrap();
+        let bytes: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+
+        pager.write_chain(first, &bytes).unwrap();
+        assert_eq!(pager.read_chain(first).unwrap(), bytes);
+        assert_eq!(pager.page_count(), 5);
+    }
+
+    #[test]
+    fn shrinking_a_chain_frees_pages() {
+        let mut pager = Pager::memory();
+        let first = pager.allocate().unwrap();
+        pager.write_chain(first, &[7; 10_000]).unwrap();
+        pager.write_chain(first, &[8; 10]).unwrap();
+        assert_eq!(pager.read_chain(first).unwrap(), vec![8; 10]);
+
+        // The freed pages are handed out again instead of growing the file.
+        let count = pager.page_count();
+        pager.allocate().unwrap();
+        pager.allocate().unwrap();
+        assert_eq!(pager.page_count(), count);
+    }
+
+    #[test]
+    fn pages_survive_reopening() {
+        let path = temp_path("reopen");
+        let first = {
+            let mut pager = Pager::open(&path).unwrap();
+            let first = pager.allocate().unwrap();
+            pager.write_chain(first, b"hello pages").unwrap();
+            pager.flush().unwrap();
+            first
+        };
+
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.read_chain(first).unwrap(), b"hello pages".to_vec());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rollback_restores_pages() {
+        let mut pager = Pager::memory();
+        let first = pager.allocate().unwrap();
+        pager.write_chain(first, b"before").unwrap();
+        let spare = pager.allocate().unwrap();
+        pager.free(spare).unwrap();
+        pager.flush().unwrap();
+
+        pager.begin();
+        pager.write_chain(first, &[5; 10_000]).unwrap();
+        assert_eq!(pager.page_count(), 5);
+        pager.rollback();
+
+        assert!(!pager.in_transaction());
+        assert_eq!(pager.read_chain(first).unwrap(), b"before".to_vec());
+        assert_eq!(pager.page_count(), 4);
+        // The free list is back too, so the spare page is handed out again.
+        assert_eq!(pager.allocate().unwrap(), spare);
+    }
+
+    #[test]
+    fn savepoints_roll_back_and_release() {
+        let mut pager = Pager::memory();
+        let first = pager.allocate().unwrap();
+        pager.write_chain(first, b"start").unwrap();
+        pager.flush().unwrap();
+
+        pager.begin();
+        pager.write_chain(first, b"one").unwrap();
+        assert_eq!(pager.savepoint(), 1);
+        pager.write_chain(first, b"two").unwrap();
+        assert_eq!(pager.savepoint(), 2);
+        pager.write_chain(first, &[3; 10_000]).unwrap();
+
+        pager.rollback_to(2);
+        assert_eq!(pager.read_chain(first).unwrap(), b"two".to_vec());
+        assert_eq!(pager.page_count(), 3);
+
+        // Releasing savepoint 1 folds its changes into the transaction, so only a full rollback
+        // undoes them.
+        pager.release(1);
+        pager.rollback_to(1);
+        assert_eq!(pager.read_chain(first).unwrap(), b"two".to_vec());
+        pager.rollback();
+        assert_eq!(pager.read_chain(first).unwrap(), b"start".to_vec());
+    }
+
+    #[test]
+    fn committed_pages_survive_a_crash() {
+        let path = temp_path("crash");
+        let first = {
+            let mut pager = Pager::open(&path).unwrap();
+            let first = pager.allocate().unwrap();
+            pager.write_chain(first, b"committed").unwrap();
+            pager.flush().unwrap();
+
+            // Never committed, so it must not show up after the crash.
+            pager.write_chain(first, b"lost").unwrap();
+            let second = pager.allocate().unwrap();
+            pager.write_chain(second, &[9; 5000]).unwrap();
+
+            // Crashing skips the checkpoint a clean close would make.
+            std::mem::forget(pager);
+            first
+        };
+        assert!(wal_path(&path).exists());
+
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.read_chain(first).unwrap(), b"committed".to_vec());
+        assert_eq!(pager.page_count(), 3);
+        drop(pager);
+        assert!(!wal_path(&path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_folds_the_log_into_the_file() {
+        let path = temp_path("checkpoint");
+        let mut pager = Pager::open(&path).unwrap();
+        let first = pager.allocate().unwrap();
+        pager.write_chain(first, b"hello pages").unwrap();
+        pager.flush().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * PAGE_SIZE as u64);
+
+        pager.checkpoint().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * PAGE_SIZE as u64);
+        assert_eq!(Wal::recover(&wal_path(&path)).unwrap().len(), 0);
+
+        // Long enough logs are folded in without being asked.
+        for _ in 0..CHECKPOINT_FRAMES {
+            let number = pager.allocate().unwrap();
+            pager.page_mut(number).unwrap();
+            pager.flush().unwrap();
+        }
+        assert!(pager.wal.as_ref().unwrap().frames() < CHECKPOINT_FRAMES);
+
+        std::mem::forget(pager);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(wal_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn cache_drops_clean_pages() {
+        let path = temp_path("cache");
+        let mut pager = Pager::open(&path).unwrap();
+        pager.set_cache_pages(4);
+        let first = pager.allocate().unwrap();
+        let bytes: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+
+        // Nothing can be dropped before it is in the database file.
+        pager.write_chain(first, &bytes).unwrap();
+        pager.flush().unwrap();
+        assert!(pager.cache_mut().len > 4);
+        pager.checkpoint().unwrap();
+        assert!(pager.cache_mut().len <= 4);
+        assert_eq!(pager.read_chain(first).unwrap(), bytes);
+        assert!(pager.cache_mut().len <= 4);
+
+        // Pages changed by a transaction stay until it is over, and are read back from the file
+        // once it is rolled back.
+        pager.begin();
+        pager.write_chain(first, &[7; 50_000]).unwrap();
+        assert_eq!(pager.read_chain(first).unwrap(), vec![7; 50_000]);
+        assert!(pager.cache_mut().len > 4);
+        pager.rollback();
+        assert!(pager.cache_mut().len <= 4);
+        assert_eq!(pager.read_chain(first).unwrap(), bytes);
+
+        drop(pager);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_databases() {
+        let path = temp_path("garbage");
+        fs::write(&path, vec![1; PAGE_SIZE]).unwrap();
+        let result = Pager::open(&path);
+        assert_eq!(
+            result.err(),
+            Some(StorageError::Corrupt("not a database file".to_string()))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,9 @@
-use scan_fmt::*;
+use crate::ast::*;
+use crate::error::Error;
+use crate::lexer::*;
+use crate::schema::{Column, DataType};
+use crate::value::Value;
+use std::fmt;
 
 /// The execution result of a non-SQL command.
 #[derive(PartialEq, Debug, Default)]
@@ -6,6 +11,8 @@ pub enum MetaCommandResult {
     Success,
     #[default]
     Unrecognized,
+    /// The user asked to leave. The REPL shuts the database down and returns.
+    Exit,
 }
 
 /// The result of parsing a statement and converting it to virtual machine bytecode.
@@ -14,90 +21,830 @@ pub enum PrepareResult {
     Success,
     #[default]
     Unrecognized,
-    SyntaxError,
+    SyntaxError(ParseError),
 }
 
-/// The SQL statements we have available to the user.
-#[derive(PartialEq, Debug, Default)]
-pub enum StatementType {
-    Insert,
-    #[default]
-    Select,
+/// Why the parser gave up on a statement.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ParseError {
+    /// The offending part of the input.
+    pub span: Span,
+    /// Descriptions of every token that would have been accepted at `span`.
+    pub expected: Vec<String>,
+    pub message: String,
 }
 
-/// A particular statement with its corresponding data.
-#[derive(PartialEq, Debug, Default)]
-pub struct Statement {
-    pub cmd: StatementType,
-    pub row_instance: Option<Row>,
+impl ParseError {
+    /// The 1-based line and column that the error starts at within `src`.
+    pub fn line_column(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.span.start.min(src.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        (line, column)
+    }
+
+    /// Renders the error with the offending line of `src` and a caret underneath it, e.g
+    ///
+    /// ```text
+    /// error: expected VALUES, found identifier 'valuse'
+    ///   --> line 1, column 19
+    ///   |
+    /// 1 | insert into users valuse (1, 'a', 'b')
+    ///   |                   ^^^^^^
+    /// ```
+    pub fn render(&self, src: &str) -> String {
+        let (line, column) = self.line_column(src);
+        let text = src.lines().nth(line - 1).unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+
+        // Underline the span but never past the end of its line, and always show at least one caret.
+        let start = self.span.start.min(src.len());
+        let end = self.span.end.clamp(start, src.len());
+        let underline = src[start..end]
+            .lines()
+            .next()
+            .map_or(0, |spanned| spanned.chars().count())
+            .max(1);
+
+        format!(
+            "error: {}\n{} --> line {}, column {}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            line,
+            column,
+            gutter,
+            line,
+            text,
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(underline)
+        )
+    }
 }
 
-#[derive(PartialEq, Debug, Default, Clone)]
-pub struct Row {
-    pub id: u32,
-    pub username: String,
-    pub email: String,
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<LexError> for ParseError {
+    fn from(err: LexError) -> Self {
+        ParseError {
+            span: err.span,
+            expected: Vec::new(),
+            message: err.message,
+        }
+    }
+}
+
+/// Parses a single SQL statement. Unlike `prepare_statement`, input that doesn't start with a
+/// statement keyword is a syntax error like any other.
+pub fn prepare(cmd: &str) -> Result<Statement, Error> {
+    let mut parser = Parser::new(cmd)?;
+    Ok(parser.parse_statement()?)
 }
 
 /// Converts a SQL statement into bytecode.
+#[deprecated(note = "use `prepare`, which hands the statement back in a `Result`")]
 pub fn prepare_statement(cmd: &str, statement: &mut Statement) -> PrepareResult {
-    // TODO: Use fall-through logic here in the future.
-    if cmd.len() >= 6 {
-        // First six chars are insert. We use a substring since this is followed by data.
-        if &cmd[0..6] == "insert" {
-            return prepare_insert(statement, cmd);
-        }
-        // This can be either 'select' returning all, or 'select 2' return item with ID 2.
-        if &cmd[0..6] == "select" {
-            return prepare_select(statement, cmd);
+    let mut parser = match Parser::new(cmd) {
+        Ok(parser) => parser,
+        Err(err) => return PrepareResult::SyntaxError(err),
+    };
+
+    // Anything that doesn't start with a statement keyword isn't something we know about.
+    if !parser.at_statement_start() {
+        return PrepareResult::Unrecognized;
+    }
+
+    match parser.parse_statement() {
+        Ok(parsed) => {
+            *statement = parsed;
+            PrepareResult::Success
         }
+        Err(err) => PrepareResult::SyntaxError(err),
     }
+}
 
-    PrepareResult::Unrecognized
+/// A recursive-descent parser with a single token of lookahead.
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    current: Token,
+    /// Everything we've tried to match against `current` so far, for error reporting.
+    expected: Vec<String>,
 }
 
-fn prepare_insert(statement: &mut Statement, cmd: &str) -> PrepareResult {
-    statement.cmd = StatementType::Insert;
-    let (id, username, email) = match scan_fmt!(cmd, "insert {} {} {}", u32, String, String) {
-        Ok((id, username, email)) => (id, username, email),
-        Err(_) => {
-            println!("Parsing error");
-            return PrepareResult::SyntaxError;
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Result<Parser<'a>, ParseError> {
+        let mut lexer = Lexer::new(src);
+        let current = lexer.next_token()?;
+        Ok(Parser {
+            lexer,
+            current,
+            expected: Vec::new(),
+        })
+    }
+
+    fn at_statement_start(&self) -> bool {
+        matches!(
+            self.current.kind,
+            TokenKind::Keyword(Keyword::Insert)
+                | TokenKind::Keyword(Keyword::Select)
+                | TokenKind::Keyword(Keyword::Update)
+                | TokenKind::Keyword(Keyword::Delete)
+                | TokenKind::Keyword(Keyword::Create)
+                | TokenKind::Keyword(Keyword::Drop)
+                | TokenKind::Keyword(Keyword::Begin)
+                | TokenKind::Keyword(Keyword::Commit)
+                | TokenKind::Keyword(Keyword::Rollback)
+                | TokenKind::Keyword(Keyword::Savepoint)
+                | TokenKind::Keyword(Keyword::Release)
+        )
+    }
+
+    /// Moves to the next token, returning the one we were looking at.
+    fn advance(&mut self) -> Result<Token, ParseError> {
+        let next = self.lexer.next_token()?;
+        self.expected.clear();
+        Ok(std::mem::replace(&mut self.current, next))
+    }
+
+    /// The kind of the token `n` places past the current one, without consuming anything.
+    fn peek(&self, n: usize) -> Result<TokenKind, ParseError> {
+        let mut lexer = self.lexer.clone();
+        let mut kind = self.current.kind.clone();
+        for _ in 0..n {
+            kind = lexer.next_token()?.kind;
         }
-    };
+        Ok(kind)
+    }
+
+    /// Records that `description` would have been accepted at the current token.
+    fn note_expected(&mut self, description: String) {
+        if !self.expected.contains(&description) {
+            self.expected.push(description);
+        }
+    }
 
-    statement.row_instance = Some(Row {
-        id,
-        username,
-        email,
-    });
+    /// Builds an error at the current token listing everything we would have accepted.
+    fn error(&mut self, expected: &str) -> ParseError {
+        self.note_expected(expected.to_string());
 
-    PrepareResult::Success
-}
+        let wanted = match self.expected.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+            None => unreachable!("we always note at least one expected token"),
+        };
 
-fn prepare_select(statement: &mut Statement, cmd: &str) -> PrepareResult {
-    statement.cmd = StatementType::Select;
+        ParseError {
+            span: self.current.span,
+            expected: self.expected.clone(),
+            message: format!(
+                "expected {}, found {}",
+                wanted,
+                self.current.kind.describe()
+            ),
+        }
+    }
 
-    // We are selecting everything in this case, e.g "select". Do not bother looking for specifics.
-    if cmd.len() == 6 {
-        statement.row_instance = None;
-        return PrepareResult::Success;
+    /// Consumes the current token if it matches `kind`.
+    fn eat(&mut self, kind: &TokenKind) -> Result<bool, ParseError> {
+        if self.current.kind == *kind {
+            self.advance()?;
+            return Ok(true);
+        }
+        self.note_expected(kind.describe());
+        Ok(false)
     }
 
-    // Grab the specific row we are looking for.
-    let id = match scan_fmt!(cmd, "select {}", u32) {
-        Ok(id) => id,
-        Err(_) => {
-            println!("Parsing error");
-            return PrepareResult::SyntaxError;
+    fn expect(&mut self, kind: TokenKind) -> Result<(), ParseError> {
+        if self.eat(&kind)? {
+            return Ok(());
         }
-    };
+        Err(self.error(&kind.describe()))
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> Result<(), ParseError> {
+        self.expect(TokenKind::Keyword(keyword))
+    }
+
+    /// Consumes the current token if it is `word`, one of the few words that only mean something
+    /// in one spot, like FIRST in `NULLS FIRST`, and are plain identifiers everywhere else.
+    fn eat_word(&mut self, word: &str) -> Result<bool, ParseError> {
+        if matches!(&self.current.kind, TokenKind::Identifier(name) if name == word) {
+            self.advance()?;
+            return Ok(true);
+        }
+        self.note_expected(word.to_ascii_uppercase());
+        Ok(false)
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, ParseError> {
+        if let TokenKind::Identifier(name) = &self.current.kind {
+            let name = name.clone();
+            self.advance()?;
+            return Ok(name);
+        }
+        Err(self.error("identifier"))
+    }
+
+    /// A statement may be followed by a single optional semicolon and nothing else.
+    fn expect_end(&mut self) -> Result<(), ParseError> {
+        self.eat(&TokenKind::Semicolon)?;
+        if self.current.kind != TokenKind::Eof {
+            return Err(self.error("end of statement"));
+        }
+        Ok(())
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        let statement = match self.current.kind {
+            TokenKind::Keyword(Keyword::Insert) => Statement::Insert(self.parse_insert()?),
+            TokenKind::Keyword(Keyword::Select) => Statement::Select(self.parse_select()?),
+            TokenKind::Keyword(Keyword::Update) => Statement::Update(self.parse_update()?),
+            TokenKind::Keyword(Keyword::Delete) => Statement::Delete(self.parse_delete()?),
+            TokenKind::Keyword(Keyword::Create) => {
+                Statement::CreateTable(self.parse_create_table()?)
+            }
+            TokenKind::Keyword(Keyword::Drop) => Statement::DropTable(self.parse_drop_table()?),
+            TokenKind::Keyword(Keyword::Begin) => self.parse_transaction(Statement::Begin)?,
+            TokenKind::Keyword(Keyword::Commit) => self.parse_transaction(Statement::Commit)?,
+            TokenKind::Keyword(Keyword::Rollback) => self.parse_rollback()?,
+            TokenKind::Keyword(Keyword::Savepoint) => {
+                self.advance()?;
+                Statement::Savepoint(self.parse_identifier()?)
+            }
+            TokenKind::Keyword(Keyword::Release) => {
+                self.advance()?;
+                self.eat(&TokenKind::Keyword(Keyword::Savepoint))?;
+                Statement::Release(self.parse_identifier()?)
+            }
+            _ => {
+                return Err(self.error(
+                    "INSERT, SELECT, UPDATE, DELETE, CREATE, DROP, BEGIN, COMMIT, ROLLBACK, \
+                     SAVEPOINT or RELEASE",
+                ))
+            }
+        };
+        self.expect_end()?;
+        Ok(statement)
+    }
+
+    fn parse_insert(&mut self) -> Result<Insert, ParseError> {
+        self.expect_keyword(Keyword::Insert)?;
+        self.expect_keyword(Keyword::Into)?;
+        let table = self.parse_identifier()?;
+
+        let mut columns = None;
+        if self.eat(&TokenKind::LeftParen)? {
+            columns = Some(self.parse_identifier_list()?);
+            self.expect(TokenKind::RightParen)?;
+        }
+
+        self.expect_keyword(Keyword::Values)?;
+        let mut rows = vec![self.parse_values()?];
+        while self.eat(&TokenKind::Comma)? {
+            rows.push(self.parse_values()?);
+        }
+
+        Ok(Insert {
+            table,
+            columns,
+            rows,
+        })
+    }
+
+    fn parse_identifier_list(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut names = vec![self.parse_identifier()?];
+        while self.eat(&TokenKind::Comma)? {
+            names.push(self.parse_identifier()?);
+        }
+        Ok(names)
+    }
+
+    /// `(<expr>, ...)`
+    fn parse_values(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.expect(TokenKind::LeftParen)?;
+        let mut values = vec![self.parse_expr()?];
+        while self.eat(&TokenKind::Comma)? {
+            values.push(self.parse_expr()?);
+        }
+        self.expect(TokenKind::RightParen)?;
+        Ok(values)
+    }
+
+    fn parse_select(&mut self) -> Result<Select, ParseError> {
+        self.expect_keyword(Keyword::Select)?;
+        let mut projection = vec![self.parse_select_item()?];
+        while self.eat(&TokenKind::Comma)? {
+            projection.push(self.parse_select_item()?);
+        }
+        self.expect_keyword(Keyword::From)?;
+        let table = self.parse_identifier()?;
+        let alias = self.parse_table_alias()?;
+        let mut joins = Vec::new();
+        while let Some(join) = self.parse_join()? {
+            joins.push(join);
+        }
+
+        let mut selection = None;
+        if self.eat(&TokenKind::Keyword(Keyword::Where))? {
+            selection = Some(self.parse_expr()?);
+        }
+
+        let (mut group_by, mut having) = (Vec::new(), None);
+        if self.eat(&TokenKind::Keyword(Keyword::Group))? {
+            self.expect_keyword(Keyword::By)?;
+            group_by.push(self.parse_expr()?);
+            while self.eat(&TokenKind::Comma)? {
+                group_by.push(self.parse_expr()?);
+            }
+        }
+        // Without GROUP BY, HAVING filters the single group the whole table makes up.
+        if self.eat(&TokenKind::Keyword(Keyword::Having))? {
+            having = Some(self.parse_expr()?);
+        }
+
+        let mut order_by = Vec::new();
+        if self.eat(&TokenKind::Keyword(Keyword::Order))? {
+            self.expect_keyword(Keyword::By)?;
+            order_by.push(self.parse_order_by()?);
+            while self.eat(&TokenKind::Comma)? {
+                order_by.push(self.parse_order_by()?);
+            }
+        }
+
+        let (mut limit, mut offset) = (None, None);
+        if self.eat(&TokenKind::Keyword(Keyword::Limit))? {
+            limit = Some(self.parse_expr()?);
+            if self.eat(&TokenKind::Keyword(Keyword::Offset))? {
+                offset = Some(self.parse_expr()?);
+            }
+        }
+
+        Ok(Select {
+            projection,
+            table,
+            alias,
+            joins,
+            selection,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    /// `[[AS] <alias>]` following the name of a table a query reads.
+    fn parse_table_alias(&mut self) -> Result<Option<String>, ParseError> {
+        match &self.current.kind {
+            TokenKind::Keyword(Keyword::As) => {
+                self.advance()?;
+                Ok(Some(self.parse_identifier()?))
+            }
+            TokenKind::Identifier(_) => Ok(Some(self.parse_identifier()?)),
+            _ => {
+                self.note_expected(Keyword::As.as_str().to_string());
+                Ok(None)
+            }
+        }
+    }
 
-    statement.row_instance = Some(Row {
-        id,
-        username: Default::default(),
-        email: Default::default(),
-    });
+    /// `[INNER | LEFT [OUTER] | RIGHT [OUTER] | FULL [OUTER]] JOIN <table> [[AS] <alias>]
+    /// ON <expr>`, or `None` if the current token doesn't start a join.
+    fn parse_join(&mut self) -> Result<Option<Join>, ParseError> {
+        if self.eat(&TokenKind::Keyword(Keyword::Inner))? {
+            return self.parse_join_table(JoinKind::Inner).map(Some);
+        }
+        let kind = match self.current.kind {
+            TokenKind::Keyword(Keyword::Join) => JoinKind::Inner,
+            TokenKind::Keyword(Keyword::Left) => JoinKind::Left,
+            TokenKind::Keyword(Keyword::Right) => JoinKind::Right,
+            TokenKind::Keyword(Keyword::Full) => JoinKind::Full,
+            _ => {
+                for keyword in [Keyword::Join, Keyword::Left, Keyword::Right, Keyword::Full] {
+                    self.note_expected(keyword.as_str().to_string());
+                }
+                return Ok(None);
+            }
+        };
+        if kind != JoinKind::Inner {
+            self.advance()?;
+            self.eat(&TokenKind::Keyword(Keyword::Outer))?;
+        }
+        self.parse_join_table(kind).map(Some)
+    }
+
+    /// `JOIN <table> [[AS] <alias>] ON <expr>`, the rest of a join of the given kind.
+    fn parse_join_table(&mut self, kind: JoinKind) -> Result<Join, ParseError> {
+        self.expect_keyword(Keyword::Join)?;
+
+        let table = self.parse_identifier()?;
+        let alias = self.parse_table_alias()?;
+        self.expect_keyword(Keyword::On)?;
+        let on = self.parse_expr()?;
+        Ok(Join {
+            kind,
+            table,
+            alias,
+            on,
+        })
+    }
+
+    /// `<expr> [ASC | DESC] [NULLS FIRST | NULLS LAST]`
+    fn parse_order_by(&mut self) -> Result<OrderBy, ParseError> {
+        let expr = self.parse_expr()?;
+        let descending = match self.eat(&TokenKind::Keyword(Keyword::Asc))? {
+            true => false,
+            false => self.eat(&TokenKind::Keyword(Keyword::Desc))?,
+        };
+
+        let mut nulls_first = !descending;
+        if self.eat_word("nulls")? {
+            nulls_first = match self.eat_word("first")? {
+                true => true,
+                false if self.eat_word("last")? => false,
+                false => return Err(self.error("LAST")),
+            };
+        }
+
+        Ok(OrderBy {
+            expr,
+            descending,
+            nulls_first,
+        })
+    }
+
+    /// `*` or `<expr> [[AS] <alias>]`
+    fn parse_select_item(&mut self) -> Result<SelectItem, ParseError> {
+        if self.eat(&TokenKind::Star)? {
+            return Ok(SelectItem::Wildcard);
+        }
+        if let TokenKind::Identifier(table) = &self.current.kind {
+            if self.peek(1)? == TokenKind::Dot && self.peek(2)? == TokenKind::Star {
+                let table = table.clone();
+                for _ in 0..3 {
+                    self.advance()?;
+                }
+                return Ok(SelectItem::TableWildcard(table));
+            }
+        }
+
+        let expr = self.parse_expr()?;
+        let alias = match self.current.kind {
+            TokenKind::Keyword(Keyword::As) => {
+                self.advance()?;
+                Some(self.parse_identifier()?)
+            }
+            TokenKind::Identifier(_) => Some(self.parse_identifier()?),
+            _ => {
+                self.note_expected(Keyword::As.as_str().to_string());
+                None
+            }
+        };
+        Ok(SelectItem::Expr { expr, alias })
+    }
+
+    fn parse_update(&mut self) -> Result<Update, ParseError> {
+        self.expect_keyword(Keyword::Update)?;
+        let table = self.parse_identifier()?;
+        self.expect_keyword(Keyword::Set)?;
+
+        let mut assignments = vec![self.parse_assignment()?];
+        while self.eat(&TokenKind::Comma)? {
+            assignments.push(self.parse_assignment()?);
+        }
+
+        let mut selection = None;
+        if self.eat(&TokenKind::Keyword(Keyword::Where))? {
+            selection = Some(self.parse_expr()?);
+        }
+
+        Ok(Update {
+            table,
+            assignments,
+            selection,
+        })
+    }
+
+    fn parse_assignment(&mut self) -> Result<Assignment, ParseError> {
+        let column = self.parse_identifier()?;
+        self.expect(TokenKind::Eq)?;
+        let value = self.parse_expr()?;
+        Ok(Assignment { column, value })
+    }
+
+    fn parse_delete(&mut self) -> Result<Delete, ParseError> {
+        self.expect_keyword(Keyword::Delete)?;
+        self.expect_keyword(Keyword::From)?;
+        let table = self.parse_identifier()?;
+
+        let mut selection = None;
+        if self.eat(&TokenKind::Keyword(Keyword::Where))? {
+            selection = Some(self.parse_expr()?);
+        }
+
+        Ok(Delete { table, selection })
+    }
+
+    /// `BEGIN | COMMIT [TRANSACTION]`, whichever keyword we are looking at.
+    fn parse_transaction(&mut self, statement: Statement) -> Result<Statement, ParseError> {
+        self.advance()?;
+        self.eat(&TokenKind::Keyword(Keyword::Transaction))?;
+        Ok(statement)
+    }
+
+    /// `ROLLBACK [TRANSACTION] [TO [SAVEPOINT] <name>]`
+    fn parse_rollback(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword(Keyword::Rollback)?;
+        self.eat(&TokenKind::Keyword(Keyword::Transaction))?;
+        if !self.eat(&TokenKind::Keyword(Keyword::To))? {
+            return Ok(Statement::Rollback);
+        }
+        self.eat(&TokenKind::Keyword(Keyword::Savepoint))?;
+        Ok(Statement::RollbackTo(self.parse_identifier()?))
+    }
+
+    /// `CREATE TABLE <table> (<column definition> | PRIMARY KEY (<column>), ...)`
+    fn parse_create_table(&mut self) -> Result<CreateTable, ParseError> {
+        self.expect_keyword(Keyword::Create)?;
+        self.expect_keyword(Keyword::Table)?;
+        let name = self.parse_identifier()?;
+
+        self.expect(TokenKind::LeftParen)?;
+        let mut columns = Vec::new();
+        let mut primary_keys = Vec::new();
+        loop {
+            if self.eat(&TokenKind::Keyword(Keyword::Primary))? {
+                self.expect_keyword(Keyword::Key)?;
+                self.expect(TokenKind::LeftParen)?;
+                let span = self.current.span;
+                primary_keys.push((self.parse_identifier()?, span));
+                self.expect(TokenKind::RightParen)?;
+            } else {
+                columns.push(self.parse_column_definition()?);
+            }
+            if !self.eat(&TokenKind::Comma)? {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightParen)?;
+
+        // `PRIMARY KEY (<column>)` may name a column defined after it.
+        for (key, span) in primary_keys {
+            let column = columns.iter_mut().find(|column| column.name == key);
+            let column = column.ok_or_else(|| ParseError {
+                span,
+                expected: vec!["column name".to_string()],
+                message: format!("unknown column '{}' in PRIMARY KEY", key),
+            })?;
+            column.primary_key = true;
+            column.not_null = true;
+        }
+
+        Ok(CreateTable { name, columns })
+    }
+
+    fn parse_drop_table(&mut self) -> Result<DropTable, ParseError> {
+        self.expect_keyword(Keyword::Drop)?;
+        self.expect_keyword(Keyword::Table)?;
+        let name = self.parse_identifier()?;
+        Ok(DropTable { name })
+    }
+
+    /// `<name> <type> [NOT NULL | NULL | PRIMARY KEY]...`
+    fn parse_column_definition(&mut self) -> Result<Column, ParseError> {
+        let name = self.parse_identifier()?;
+
+        let data_type = match &self.current.kind {
+            TokenKind::Identifier(type_name) => {
+                DataType::from_name(type_name).ok_or_else(|| ParseError {
+                    span: self.current.span,
+                    expected: vec!["column type".to_string()],
+                    message: format!("unknown column type '{}'", type_name),
+                })?
+            }
+            _ => return Err(self.error("column type")),
+        };
+        self.advance()?;
+
+        let mut column = Column::new(&name, data_type);
+        // Constraints can come in any order, e.g `id INTEGER NOT NULL PRIMARY KEY`.
+        loop {
+            if self.eat(&TokenKind::Keyword(Keyword::Not))? {
+                self.expect_keyword(Keyword::Null)?;
+                column.not_null = true;
+            } else if self.eat(&TokenKind::Keyword(Keyword::Primary))? {
+                self.expect_keyword(Keyword::Key)?;
+                column.primary_key = true;
+                column.not_null = true;
+            } else if !self.eat(&TokenKind::Keyword(Keyword::Null))? {
+                break;
+            }
+        }
+
+        Ok(column)
+    }
+
+    /* Expressions are parsed by precedence climbing, loosest binding first:
+     *   OR < AND < NOT < comparison and IS NULL < + - || < * / % < unary + - < primary
+     */
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.eat(&TokenKind::Keyword(Keyword::Or))? {
+            let right = self.parse_and()?;
+            left = binary(left, BinaryOp::Or, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_not()?;
+        while self.eat(&TokenKind::Keyword(Keyword::And))? {
+            let right = self.parse_not()?;
+            left = binary(left, BinaryOp::And, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat(&TokenKind::Keyword(Keyword::Not))? {
+            let expr = self.parse_not()?;
+            return Ok(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.parse_additive()?;
+
+        if self.eat(&TokenKind::Keyword(Keyword::Is))? {
+            let negated = self.eat(&TokenKind::Keyword(Keyword::Not))?;
+            self.expect_keyword(Keyword::Null)?;
+            return Ok(Expr::IsNull {
+                expr: Box::new(left),
+                negated,
+            });
+        }
 
-    PrepareResult::Success
+        let op = match self.current.kind {
+            TokenKind::Eq => BinaryOp::Eq,
+            TokenKind::NotEq => BinaryOp::NotEq,
+            TokenKind::Lt => BinaryOp::Lt,
+            TokenKind::LtEq => BinaryOp::LtEq,
+            TokenKind::Gt => BinaryOp::Gt,
+            TokenKind::GtEq => BinaryOp::GtEq,
+            _ => {
+                self.note_expected("comparison operator".to_string());
+                return Ok(left);
+            }
+        };
+        self.advance()?;
+        let right = self.parse_additive()?;
+        Ok(binary(left, op, right))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.current.kind {
+                TokenKind::Plus => BinaryOp::Add,
+                TokenKind::Minus => BinaryOp::Sub,
+                TokenKind::Concat => BinaryOp::Concat,
+                _ => return Ok(left),
+            };
+            self.advance()?;
+            let right = self.parse_multiplicative()?;
+            left = binary(left, op, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.current.kind {
+                TokenKind::Star => BinaryOp::Mul,
+                TokenKind::Slash => BinaryOp::Div,
+                TokenKind::Percent => BinaryOp::Mod,
+                _ => return Ok(left),
+            };
+            self.advance()?;
+            let right = self.parse_unary()?;
+            left = binary(left, op, right);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let op = match self.current.kind {
+            TokenKind::Minus => UnaryOp::Minus,
+            TokenKind::Plus => UnaryOp::Plus,
+            _ => return self.parse_primary(),
+        };
+        self.advance()?;
+        // The sign of a number is part of its literal, since `-9223372036854775808` is in range
+        // but `9223372036854775808` isn't.
+        if let (UnaryOp::Minus, TokenKind::Number(text)) = (op, &self.current.kind) {
+            let value = self.parse_number(&format!("-{}", text))?;
+            self.advance()?;
+            return Ok(Expr::Literal(value));
+        }
+        let expr = self.parse_unary()?;
+        Ok(Expr::Unary {
+            op,
+            expr: Box::new(expr),
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let expr = match &self.current.kind {
+            TokenKind::Number(text) => Expr::Literal(self.parse_number(text)?),
+            TokenKind::String(value) => Expr::Literal(Value::Text(value.clone())),
+            TokenKind::Keyword(Keyword::True) => Expr::Literal(Value::Boolean(true)),
+            TokenKind::Keyword(Keyword::False) => Expr::Literal(Value::Boolean(false)),
+            TokenKind::Keyword(Keyword::Null) => Expr::Literal(Value::Null),
+            TokenKind::Identifier(name) => {
+                let name = name.clone();
+                let span = self.current.span;
+                self.advance()?;
+                if self.eat(&TokenKind::Dot)? {
+                    return Ok(Expr::Column {
+                        table: Some(name),
+                        column: self.parse_identifier()?,
+                    });
+                }
+                if !self.eat(&TokenKind::LeftParen)? {
+                    return Ok(Expr::Column {
+                        table: None,
+                        column: name,
+                    });
+                }
+                return match AggregateFunction::from_name(&name) {
+                    Some(function) => self.parse_aggregate(function),
+                    None => Err(ParseError {
+                        span,
+                        expected: Vec::new(),
+                        message: format!("unknown function '{}'", name),
+                    }),
+                };
+            }
+            TokenKind::LeftParen => {
+                self.advance()?;
+                let expr = self.parse_expr()?;
+                self.expect(TokenKind::RightParen)?;
+                return Ok(expr);
+            }
+            _ => return Err(self.error("expression")),
+        };
+        self.advance()?;
+        Ok(expr)
+    }
+
+    /// `COUNT(*)` or `([DISTINCT] <expr>)` following the name of an aggregate function.
+    fn parse_aggregate(&mut self, function: AggregateFunction) -> Result<Expr, ParseError> {
+        let (arg, distinct) =
+            match function == AggregateFunction::Count && self.eat(&TokenKind::Star)? {
+                true => (None, false),
+                false => {
+                    let distinct = self.eat(&TokenKind::Keyword(Keyword::Distinct))?;
+                    (Some(Box::new(self.parse_expr()?)), distinct)
+                }
+            };
+        self.expect(TokenKind::RightParen)?;
+        Ok(Expr::Aggregate {
+            function,
+            arg,
+            distinct,
+        })
+    }
+
+    /// Numbers without a decimal point are integers, everything else is a real.
+    fn parse_number(&self, text: &str) -> Result<Value, ParseError> {
+        let value = if text.contains('.') {
+            text.parse().map(Value::Real).ok()
+        } else {
+            text.parse().map(Value::Integer).ok()
+        };
+
+        value.ok_or_else(|| ParseError {
+            span: self.current.span,
+            expected: Vec::new(),
+            message: format!("number {} is out of range", text),
+        })
+    }
+}
+
+fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+    Expr::Binary {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
 }
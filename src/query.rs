@@ -0,0 +1,542 @@
+use crate::ast::{Expr, OrderBy, Select, SelectItem};
+use crate::error::Error;
+use crate::eval::{eval, eval_constant, expr_type, EvalError};
+use crate::schema::{Column, DataType, Row, Schema, SchemaError};
+use crate::sort::Direction;
+use crate::value::Value;
+
+/// What one column of a result set holds.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ResultColumn {
+    /// The alias given to the column, or else the column or expression it was selected as.
+    pub name: String,
+    /// `None` when the type isn't known up front, e.g for a NULL literal.
+    pub data_type: Option<DataType>,
+}
+
+/// The rows a query returns, along with what each of their columns holds.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct ResultSet {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Row>,
+}
+
+impl ResultSet {
+    /// Where the column called `name` is in every row, the first one if several share the name.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+}
+
+/// The tables a SELECT reads, under the names it refers to them by.
+///
+/// A query over a single table reads its rows as the table lays them out. Once tables are joined,
+/// every column also carries the name of its table, so that columns of the same name in different
+/// tables can be told apart. Resolving a query qualifies the columns it refers to to match.
+#[derive(Debug)]
+pub struct TableScope<'a> {
+    tables: Vec<(&'a str, &'a Schema)>,
+}
+
+impl<'a> TableScope<'a> {
+    /// `tables` pairs the name each table goes by with its schema, in the order they are joined.
+    pub fn new(tables: Vec<(&'a str, &'a Schema)>) -> Result<TableScope<'a>, EvalError> {
+        for (i, (name, _)) in tables.iter().enumerate() {
+            if tables[..i].iter().any(|(other, _)| other == name) {
+                return Err(EvalError::DuplicateTable(name.to_string()));
+            }
+        }
+        Ok(TableScope { tables })
+    }
+
+    fn joined(&self) -> bool {
+        self.tables.len() > 1
+    }
+
+    /// The columns of the table at `index` in the order they are joined, qualified the way joined
+    /// rows qualify them.
+    pub fn joined_schema(&self, index: usize) -> Schema {
+        let (table, schema) = self.tables[index];
+        let columns = schema
+            .columns
+            .iter()
+            .map(|column| Column {
+                table: Some(table.to_string()),
+                ..column.unconstrained()
+            })
+            .collect();
+        Schema { columns }
+    }
+
+    /// Qualifies every column `select` refers to the way rows read through the scope do.
+    /// Unqualified names that no table has are left alone, as they may name a result column.
+    pub fn resolve(&self, select: Select) -> Result<Select, Error> {
+        let mut projection = Vec::with_capacity(select.projection.len());
+        for item in select.projection.into_iter() {
+            projection.push(match item {
+                SelectItem::Wildcard => SelectItem::Wildcard,
+                SelectItem::TableWildcard(table) => {
+                    if !self.tables.iter().any(|(name, _)| *name == table) {
+                        return Err(Error::Schema(SchemaError::UnknownTable(table)));
+                    }
+                    match self.joined() {
+                        true => SelectItem::TableWildcard(table),
+                        false => SelectItem::Wildcard,
+                    }
+                }
+                // The result column keeps the name it was selected by.
+                SelectItem::Expr { expr, alias } => SelectItem::Expr {
+                    alias: Some(alias.unwrap_or_else(|| column_name(&expr))),
+                    expr: self.resolve_expr(&expr)?,
+                },
+            });
+        }
+
+        let mut joins = select.joins;
+        for join in joins.iter_mut() {
+            join.on = self.resolve_expr(&join.on)?;
+        }
+        let mut order_by = select.order_by;
+        for ordering in order_by.iter_mut() {
+            ordering.expr = self.resolve_expr(&ordering.expr)?;
+        }
+        Ok(Select {
+            projection,
+            joins,
+            selection: self.resolve_opt(&select.selection)?,
+            group_by: select
+                .group_by
+                .iter()
+                .map(|expr| self.resolve_expr(expr))
+                .collect::<Result<_, _>>()?,
+            having: self.resolve_opt(&select.having)?,
+            order_by,
+            ..select
+        })
+    }
+
+    fn resolve_opt(&self, expr: &Option<Expr>) -> Result<Option<Expr>, EvalError> {
+        expr.as_ref()
+            .map(|expr| self.resolve_expr(expr))
+            .transpose()
+    }
+
+    fn resolve_expr(&self, expr: &Expr) -> Result<Expr, EvalError> {
+        Ok(match expr {
+            Expr::Literal(_) => expr.clone(),
+            Expr::Column { table, column } => {
+                self.resolve_column(expr, table.as_deref(), column)?
+            }
+            Expr::Unary { op, expr } => Expr::Unary {
+                op: *op,
+                expr: Box::new(self.resolve_expr(expr)?),
+            },
+            Expr::Binary { left, op, right } => Expr::Binary {
+                left: Box::new(self.resolve_expr(left)?),
+                op: *op,
+                right: Box::new(self.resolve_expr(right)?),
+            },
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: Box::new(self.resolve_expr(expr)?),
+                negated: *negated,
+            },
+            Expr::Aggregate {
+                function,
+                arg,
+                distinct,
+            } => Expr::Aggregate {
+                function: *function,
+                arg: match arg {
+                    Some(arg) => Some(Box::new(self.resolve_expr(arg)?)),
+                    None => None,
+                },
+                distinct: *distinct,
+            },
+        })
+    }
+
+    /// `expr` is the column as written, `table` and `column` what it is made of.
+    fn resolve_column(
+        &self,
+        expr: &Expr,
+        table: Option<&str>,
+        column: &str,
+    ) -> Result<Expr, EvalError> {
+        let mut found = self.tables.iter().filter(|(other, schema)| {
+            table.is_none_or(|table| table == *other) && schema.index_of(column).is_some()
+        });
+        let owner = match (found.next(), found.next()) {
+            (Some((owner, _)), None) => owner,
+            (Some(_), Some(_)) => return Err(EvalError::AmbiguousColumn(expr.to_string())),
+            (None, _) if table.is_some() => return Err(EvalError::UnknownColumn(expr.to_string())),
+            (None, _) => return Ok(expr.clone()),
+        };
+        Ok(Expr::Column {
+            table: self.joined().then(|| owner.to_string()),
+            column: column.to_string(),
+        })
+    }
+}
+
+/// The name of the result column `expr` is selected as when it isn't given an alias: the column
+/// without its table, or else the expression as written.
+fn column_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Column { column, .. } => column.clone(),
+        expr => expr.to_string(),
+    }
+}
+
+/// The column list of a SELECT, worked out against the schema of the table it reads.
+#[derive(Debug)]
+pub struct Projection {
+    exprs: Vec<Expr>,
+    pub columns: Vec<ResultColumn>,
+}
+
+impl Projection {
+    /// Expands `*` into the columns of `schema`, and `<table>.*` into the ones of a joined table.
+    /// Checks that every column the list refers to exists, so that a mistake shows up even when
+    /// no rows match.
+    pub fn new(items: &[SelectItem], schema: &Schema) -> Result<Projection, EvalError> {
+        let mut exprs = Vec::new();
+        let mut columns = Vec::new();
+        for item in items.iter() {
+            match item {
+                SelectItem::Wildcard | SelectItem::TableWildcard(_) => {
+                    let selected = schema.columns.iter().filter(|column| match item {
+                        SelectItem::TableWildcard(table) => column.table.as_ref() == Some(table),
+                        _ => true,
+                    });
+                    for column in selected {
+                        exprs.push(Expr::Column {
+                            table: column.table.clone(),
+                            column: column.name.clone(),
+                        });
+                        columns.push(ResultColumn {
+                            name: column.name.clone(),
+                            data_type: Some(column.data_type),
+                        });
+                    }
+                }
+                SelectItem::Expr { expr, alias } => {
+                    let data_type = expr_type(expr, schema)?;
+                    let name = match alias {
+                        Some(alias) => alias.clone(),
+                        None => expr.to_string(),
+                    };
+                    exprs.push(expr.clone());
+                    columns.push(ResultColumn { name, data_type });
+                }
+            }
+        }
+        Ok(Projection { exprs, columns })
+    }
+
+    /// Replaces every expression of the column list with what `rewrite` makes of it, keeping the
+    /// names and types of the columns as they were.
+    pub fn rewrite(&mut self, rewrite: impl Fn(&Expr) -> Expr) {
+        for expr in self.exprs.iter_mut() {
+            *expr = rewrite(expr);
+        }
+    }
+
+    /// The row of the result that `row`, laid out as `schema`, turns into.
+    pub fn apply(&self, schema: &Schema, row: &Row) -> Result<Row, EvalError> {
+        let mut values = Vec::with_capacity(self.exprs.len());
+        for expr in self.exprs.iter() {
+            values.push(eval(expr, schema, row)?);
+        }
+        Ok(Row::new(values))
+    }
+}
+
+/// Where the value of a sort key comes from.
+#[derive(Debug)]
+enum SortKey {
+    /// An expression over the row as the table stores it.
+    Expr(Expr),
+    /// A column of the result, named by its alias or its position.
+    Result(usize),
+}
+
+/// The ORDER BY clause of a SELECT, worked out against the table it reads and its column list.
+#[derive(Debug)]
+pub struct SortOrder {
+    keys: Vec<SortKey>,
+    pub directions: Vec<Direction>,
+}
+
+impl SortOrder {
+    /// Keys are expressions over the columns of the table, except that a bare name that isn't a
+    /// column of the table can name a column of the result by its alias, and a whole number picks
+    /// out a column of the result by its position, counting from 1.
+    pub fn new(
+        order_by: &[OrderBy],
+        projection: &Projection,
+        schema: &Schema,
+    ) -> Result<SortOrder, EvalError> {
+        let mut keys = Vec::with_capacity(order_by.len());
+        let mut directions = Vec::with_capacity(order_by.len());
+        for ordering in order_by.iter() {
+            let result = match &ordering.expr {
+                Expr::Literal(Value::Integer(position)) => {
+                    let columns = projection.columns.len();
+                    match usize::try_from(*position) {
+                        Ok(position) if (1..=columns).contains(&position) => Some(position - 1),
+                        _ => {
+                            return Err(EvalError::InvalidOrderBy(format!(
+                                "ORDER BY position {} is not between 1 and {}",
+                                position, columns
+                            )))
+                        }
+                    }
+                }
+                Expr::Column {
+                    table: None,
+                    column,
+                } if schema.index_of(column).is_none() => projection
+                    .columns
+                    .iter()
+                    .position(|result| result.name == *column),
+                _ => None,
+            };
+            match result {
+                Some(index) => keys.push(SortKey::Result(index)),
+                None => {
+                    expr_type(&ordering.expr, schema)?;
+                    keys.push(SortKey::Expr(ordering.expr.clone()));
+                }
+            }
+            directions.push(Direction {
+                descending: ordering.descending,
+                nulls_first: ordering.nulls_first,
+            });
+        }
+        Ok(SortOrder { keys, directions })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Whether rows read in primary key order are already sorted, because the first key is the
+    /// primary key going up. Its values are all different, so any keys after it never matter.
+    pub fn follows_key(&self, schema: &Schema) -> bool {
+        match (self.keys.first(), self.directions.first()) {
+            (Some(SortKey::Expr(Expr::Column { table, column })), Some(direction)) => {
+                !direction.descending
+                    && schema.primary_key().is_some()
+                    && schema.find(table.as_deref(), column) == schema.primary_key()
+            }
+            _ => false,
+        }
+    }
+
+    /// The values `row`, laid out as `schema`, sorts on. `projected` is what it turned into in
+    /// the result.
+    pub fn keys(
+        &self,
+        schema: &Schema,
+        row: &Row,
+        projected: &Row,
+    ) -> Result<Vec<Value>, EvalError> {
+        let mut values = Vec::with_capacity(self.keys.len());
+        for key in self.keys.iter() {
+            values.push(match key {
+                SortKey::Expr(expr) => eval(expr, schema, row)?,
+                SortKey::Result(index) => projected.values[*index].clone(),
+            });
+        }
+        Ok(values)
+    }
+}
+
+/// The rows of a result that LIMIT and OFFSET keep.
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub struct Page {
+    pub offset: usize,
+    /// No cap when `None`.
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    /// Both expressions have to be constant. NULL leaves the row count uncapped and skips nothing.
+    pub fn new(limit: &Option<Expr>, offset: &Option<Expr>) -> Result<Page, EvalError> {
+        Ok(Page {
+            offset: row_count("OFFSET", offset)?.unwrap_or(0),
+            limit: row_count("LIMIT", limit)?,
+        })
+    }
+
+    /// How many rows have to be read, counting the ones skipped, to fill the page. All of them
+    /// when `None`.
+    pub fn end(&self) -> Option<usize> {
+        self.limit.map(|limit| limit.saturating_add(self.offset))
+    }
+
+    /// Reads rows off `rows` until the page is full, turning the ones on the page into rows of
+    /// the result with `apply`. Rows past the end of the page are never read at all.
+    pub fn fill<T, E>(
+        &self,
+        rows: impl IntoIterator<Item = Result<T, E>>,
+        mut apply: impl FnMut(T) -> Result<Row, E>,
+    ) -> Result<Vec<Row>, E> {
+        let mut page = Vec::new();
+        let mut skipped = 0;
+        let mut rows = rows.into_iter();
+        while self.limit.is_none_or(|limit| page.len() < limit) {
+            let entry = match rows.next() {
+                Some(entry) => entry?,
+                None => break,
+            };
+            match skipped < self.offset {
+                true => skipped += 1,
+                false => page.push(apply(entry)?),
+            }
+        }
+        Ok(page)
+    }
+}
+
+fn row_count(clause: &str, expr: &Option<Expr>) -> Result<Option<usize>, EvalError> {
+    let value = match expr {
+        Some(expr) => eval_constant(expr)?,
+        None => return Ok(None),
+    };
+    match value {
+        Value::Null => Ok(None),
+        Value::Integer(count) if count >= 0 => Ok(Some(count.try_into().unwrap_or(usize::MAX))),
+        other => Err(EvalError::InvalidLimit(format!(
+            "{} must be a number of rows, got {}",
+            clause, other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::BinaryOp;
+    use crate::schema::Column;
+    use crate::value::Value;
+
+    fn column(name: &str) -> Expr {
+        Expr::Column {
+            table: None,
+            column: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn projection_names_and_types() {
+        let schema = Schema::new(vec![
+            Column::new("id", DataType::Integer),
+            Column::new("name", DataType::Text),
+        ])
+        .unwrap();
+        let doubled = Expr::Binary {
+            left: Box::new(column("id")),
+            op: BinaryOp::Mul,
+            right: Box::new(Expr::Literal(Value::Real(2.0))),
+        };
+        let items = vec![
+            SelectItem::Expr {
+                expr: column("name"),
+                alias: Some("who".to_string()),
+            },
+            SelectItem::Expr {
+                expr: doubled,
+                alias: None,
+            },
+            SelectItem::Wildcard,
+        ];
+
+        let projection = Projection::new(&items, &schema).unwrap();
+        let names: Vec<&str> = projection
+            .columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["who", "id * 2.0", "id", "name"]);
+        assert_eq!(projection.columns[1].data_type, Some(DataType::Real));
+
+        let row = Row::new(vec![Value::Integer(21), Value::Text("rosh".to_string())]);
+        assert_eq!(
+            projection.apply(&schema, &row).unwrap().values,
+            vec![
+                Value::Text("rosh".to_string()),
+                Value::Real(42.0),
+                Value::Integer(21),
+                Value::Text("rosh".to_string()),
+            ]
+        );
+
+        let unknown = vec![SelectItem::Expr {
+            expr: column("nope"),
+            alias: None,
+        }];
+        assert_eq!(
+            Projection::new(&unknown, &schema).unwrap_err(),
+            EvalError::UnknownColumn("nope".to_string())
+        );
+    }
+
+    #[test]
+    fn page_stops_reading_when_full() {
+        let page = Page::new(
+            &Some(Expr::Literal(Value::Integer(2))),
+            &Some(Expr::Literal(Value::Integer(3))),
+        )
+        .unwrap();
+        assert_eq!(page.end(), Some(5));
+
+        let mut read = 0;
+        let rows = (0..100).map(|i| {
+            read += 1;
+            Ok::<_, EvalError>(i)
+        });
+        let filled = page.fill(rows, |i| Ok(Row::new(vec![Value::Integer(i)])));
+        assert_eq!(
+            filled.unwrap(),
+            vec![
+                Row::new(vec![Value::Integer(3)]),
+                Row::new(vec![Value::Integer(4)])
+            ]
+        );
+        assert_eq!(read, 5);
+
+        // Rows that are skipped still have to be read without errors.
+        let rows = vec![Err(EvalError::DivisionByZero), Ok(1)];
+        assert_eq!(
+            page.fill(rows, |i| Ok(Row::new(vec![Value::Integer(i)]))),
+            Err(EvalError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn sort_order_follows_key() {
+        let schema = Schema::new(vec![
+            Column {
+                primary_key: true,
+                ..Column::new("id", DataType::Integer)
+            },
+            Column::new("name", DataType::Text),
+        ])
+        .unwrap();
+        let projection = Projection::new(&[SelectItem::Wildcard], &schema).unwrap();
+        let order = |expr: &str, descending: bool| {
+            let order_by = vec![OrderBy {
+                expr: column(expr),
+                descending,
+                nulls_first: !descending,
+            }];
+            SortOrder::new(&order_by, &projection, &schema)
+                .unwrap()
+                .follows_key(&schema)
+        };
+        assert!(order("id", false));
+        assert!(!order("id", true));
+        assert!(!order("name", false));
+    }
+}
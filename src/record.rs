@@ -0,0 +1,485 @@
+use crate::mvcc::Version;
+use crate::pager::StorageError;
+use crate::schema::{Column, DataType, Row, Schema};
+use crate::value::Value;
+
+/// What the catalog knows about a table: its name, its columns and the first page holding its rows.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TableEntry {
+    pub name: String,
+    pub schema: Schema,
+    pub root_page: u32,
+}
+
+// Flags describing a column in the catalog.
+const FLAG_NOT_NULL: u8 = 1;
+const FLAG_PRIMARY_KEY: u8 = 2;
+
+// Tags written in front of each value so we know how to read it back.
+const TAG_INTEGER: u8 = 1;
+const TAG_REAL: u8 = 2;
+const TAG_TEXT: u8 = 3;
+const TAG_BOOLEAN: u8 = 4;
+
+/// Turns a row into bytes. The layout is:
+///
+/// - the number of columns, as a varint
+/// - a bitmap with one bit per column, set when the column is NULL
+/// - for every column that isn't NULL, a type tag followed by the value
+///
+/// Integers are zigzag varints so small negative numbers stay small, reals are 8 bytes and text
+/// is a varint length followed by its UTF-8 bytes.
+pub fn serialize_row(row: &Row) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    put_varint(&mut bytes, row.values.len() as u64);
+
+    let mut bitmap = vec![0u8; row.values.len().div_ceil(8)];
+    for (i, value) in row.values.iter().enumerate() {
+        if value.is_null() {
+            bitmap[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bytes.extend_from_slice(&bitmap);
+
+    for value in row.values.iter() {
+        match value {
+            Value::Null => {}
+            Value::Integer(i) => {
+                bytes.push(TAG_INTEGER);
+                put_varint(&mut bytes, zigzag(*i));
+            }
+            Value::Real(r) => {
+                bytes.push(TAG_REAL);
+                bytes.extend_from_slice(&r.to_le_bytes());
+            }
+            Value::Text(s) => {
+                bytes.push(TAG_TEXT);
+                put_str(&mut bytes, s);
+            }
+            Value::Boolean(b) => {
+                bytes.push(TAG_BOOLEAN);
+                bytes.push(*b as u8);
+            }
+        }
+    }
+    bytes
+}
+
+/// Reads back a row written by `serialize_row`.
+pub fn deserialize_row(bytes: &[u8]) -> Result<Row, StorageError> {
+    let mut reader = Reader::new(bytes);
+    let row = reader.row()?;
+    reader.finish()?;
+    Ok(row)
+}
+
+/// Reads back a row written by `serialize_row`, checking that it fits the table's schema.
+pub fn decode_row(bytes: &[u8], schema: &Schema) -> Result<Row, StorageError> {
+    let row = deserialize_row(bytes)?;
+    schema
+        .check_row(row.values)
+        .map_err(|err| StorageError::Corrupt(err.to_string()))
+}
+
+/// Turns every version of a row into the bytes stored against its key. The layout is the number of
+/// versions as a varint, then for each one the transaction that created it, the transaction that
+/// deleted it (0 if none did) and the length of its record, all varints, followed by the record.
+pub fn encode_versions(versions: &[Version]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    put_varint(&mut bytes, versions.len() as u64);
+    for version in versions.iter() {
+        put_varint(&mut bytes, version.created);
+        put_varint(&mut bytes, version.deleted.unwrap_or(0));
+        put_varint(&mut bytes, version.record.len() as u64);
+        bytes.extend_from_slice(&version.record);
+    }
+    bytes
+}
+
+/// Reads back the versions written by `encode_versions`.
+pub fn decode_versions(bytes: &[u8]) -> Result<Vec<Version>, StorageError> {
+    let mut reader = Reader::new(bytes);
+    let count = reader.varint()?;
+    let mut versions = Vec::new();
+    for _ in 0..count {
+        let created = reader.varint()?;
+        let deleted = Some(reader.varint()?).filter(|txn| *txn != 0);
+        let length = reader.length()?;
+        versions.push(Version {
+            created,
+            deleted,
+            record: reader.take(length)?.to_vec(),
+        });
+    }
+    reader.finish()?;
+    Ok(versions)
+}
+
+/// Turns the catalog of tables into bytes.
+pub fn encode_catalog(entries: &[TableEntry]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    put_varint(&mut bytes, entries.len() as u64);
+    for entry in entries.iter() {
+        put_str(&mut bytes, &entry.name);
+        put_varint(&mut bytes, entry.root_page as u64);
+        put_varint(&mut bytes, entry.schema.columns.len() as u64);
+        for column in entry.schema.columns.iter() {
+            put_str(&mut bytes, &column.name);
+            bytes.push(type_tag(column.data_type));
+            let mut flags = 0;
+            if column.not_null {
+                flags |= FLAG_NOT_NULL;
+            }
+            if column.primary_key {
+                flags |= FLAG_PRIMARY_KEY;
+            }
+            bytes.push(flags);
+        }
+    }
+    bytes
+}
+
+/// Reads back a catalog written by `encode_catalog`. A brand new database has no bytes at all.
+pub fn decode_catalog(bytes: &[u8]) -> Result<Vec<TableEntry>, StorageError> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut reader = Reader::new(bytes);
+    let count = reader.varint()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let name = reader.string()?;
+        let root_page = u32::try_from(reader.varint()?)
+            .map_err(|_| StorageError::Corrupt("page number is out of range".to_string()))?;
+        let column_count = reader.varint()?;
+
+        let mut columns = Vec::new();
+        for _ in 0..column_count {
+            let mut column = Column::new(&reader.string()?, data_type(reader.u8()?)?);
+            let flags = reader.u8()?;
+            column.not_null = flags & FLAG_NOT_NULL != 0;
+            column.primary_key = flags & FLAG_PRIMARY_KEY != 0;
+            columns.push(column);
+        }
+        let schema = Schema::new(columns).map_err(|err| StorageError::Corrupt(err.to_string()))?;
+
+        entries.push(TableEntry {
+            name,
+            schema,
+            root_page,
+        });
+    }
+    reader.finish()?;
+    Ok(entries)
+}
+
+fn type_tag(data_type: DataType) -> u8 {
+    match data_type {
+        DataType::Integer => TAG_INTEGER,
+        DataType::Real => TAG_REAL,
+        DataType::Text => TAG_TEXT,
+        DataType::Boolean => TAG_BOOLEAN,
+    }
+}
+
+fn data_type(tag: u8) -> Result<DataType, StorageError> {
+    match tag {
+        TAG_INTEGER => Ok(DataType::Integer),
+        TAG_REAL => Ok(DataType::Real),
+        TAG_TEXT => Ok(DataType::Text),
+        TAG_BOOLEAN => Ok(DataType::Boolean),
+        _ => Err(StorageError::Corrupt(format!(
+            "unknown column type {}",
+            tag
+        ))),
+    }
+}
+
+/// Writes `value` 7 bits at a time, lowest bits first, with the top bit set on every byte but the last.
+pub fn put_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Maps signed integers onto unsigned ones so that numbers close to zero get short varints.
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn put_str(bytes: &mut Vec<u8>, s: &str) {
+    put_varint(bytes, s.len() as u64);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+/// Walks through encoded bytes, failing with `Corrupt` instead of panicking when they run out.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], StorageError> {
+        if self.bytes.len() - self.position < count {
+            return Err(StorageError::Corrupt("record ends early".to_string()));
+        }
+        let taken = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, StorageError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, StorageError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(StorageError::Corrupt("varint is too long".to_string()))
+    }
+
+    /// A varint giving the size of something that follows, which can't be more than what is left.
+    fn length(&mut self) -> Result<usize, StorageError> {
+        let length = self.varint()?;
+        if length > (self.bytes.len() - self.position) as u64 {
+            return Err(StorageError::Corrupt("record ends early".to_string()));
+        }
+        Ok(length as usize)
+    }
+
+    fn string(&mut self) -> Result<String, StorageError> {
+        let length = self.length()?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| StorageError::Corrupt("text is not valid UTF-8".to_string()))
+    }
+
+    fn row(&mut self) -> Result<Row, StorageError> {
+        // Every column takes at least a bit of the null bitmap, which bounds how many there can be.
+        let count = self.varint()?;
+        if count.div_ceil(8) > (self.bytes.len() - self.position) as u64 {
+            return Err(StorageError::Corrupt("record ends early".to_string()));
+        }
+        let count = count as usize;
+        let bitmap = self.take(count.div_ceil(8))?;
+
+        let mut values = Vec::with_capacity(count);
+        for i in 0..count {
+            if bitmap[i / 8] & (1 << (i % 8)) != 0 {
+                values.push(Value::Null);
+                continue;
+            }
+
+            let value = match self.u8()? {
+                TAG_INTEGER => Value::Integer(unzigzag(self.varint()?)),
+                TAG_REAL => {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(self.take(8)?);
+                    Value::Real(f64::from_le_bytes(bytes))
+                }
+                TAG_TEXT => Value::Text(self.string()?),
+                TAG_BOOLEAN => Value::Boolean(self.u8()? != 0),
+                tag => return Err(StorageError::Corrupt(format!("unknown value tag {}", tag))),
+            };
+            values.push(value);
+        }
+        Ok(Row::new(values))
+    }
+
+    /// Checks that nothing is left over after everything we expected has been read.
+    fn finish(&self) -> Result<(), StorageError> {
+        if self.position != self.bytes.len() {
+            return Err(StorageError::Corrupt(
+                "unexpected bytes after the end of the record".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        let mut id = Column::new("id", DataType::Integer);
+        id.not_null = true;
+        id.primary_key = true;
+        Schema::new(vec![
+            id,
+            Column::new("name", DataType::Text),
+            Column::new("score", DataType::Real),
+            Column::new("active", DataType::Boolean),
+        ])
+        .unwrap()
+    }
+
+    fn user(id: i64, username: &str, email: &str) -> Row {
+        Row::new(vec![
+            Value::Integer(id),
+            Value::Text(username.to_string()),
+            Value::Text(email.to_string()),
+        ])
+    }
+
+    #[test]
+    fn row_layout() {
+        let row = Row::new(vec![
+            Value::Integer(-2),
+            Value::Null,
+            Value::Text("ab".to_string()),
+        ]);
+        assert_eq!(
+            serialize_row(&row),
+            vec![
+                3,           // column count
+                0b010,       // null bitmap, the second column is NULL
+                TAG_INTEGER, // -2 zigzagged
+                3,
+                TAG_TEXT, // length then bytes
+                2,
+                b'a',
+                b'b'
+            ]
+        );
+    }
+
+    #[test]
+    fn row_round_trip_long_strings() {
+        let row = user(
+            13,
+            &"a".repeat(1000),
+            &format!("{}@gmail.com", "a".repeat(1000)),
+        );
+        let bytes = serialize_row(&row);
+        // A 1000 byte string only needs two bytes for its length.
+        assert_eq!(bytes.len(), 1 + 1 + 2 + 3 + 1000 + 3 + 1010);
+        assert_eq!(deserialize_row(&bytes).unwrap(), row);
+    }
+
+    #[test]
+    fn row_round_trip_every_type() {
+        let row = Row::new(vec![
+            Value::Integer(i64::MIN),
+            Value::Integer(i64::MAX),
+            Value::Integer(0),
+            Value::Real(-0.5),
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Text(String::new()),
+            Value::Text("héllo ✓".to_string()),
+            Value::Null,
+            Value::Null,
+        ]);
+        assert_eq!(deserialize_row(&serialize_row(&row)).unwrap(), row);
+        assert_eq!(
+            deserialize_row(&serialize_row(&Row::default())).unwrap(),
+            Row::default()
+        );
+    }
+
+    #[test]
+    fn varints() {
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u32::MAX as u64,
+            u64::MAX,
+        ] {
+            let mut bytes = Vec::new();
+            put_varint(&mut bytes, value);
+            assert_eq!(Reader::new(&bytes).varint().unwrap(), value);
+        }
+
+        let mut bytes = Vec::new();
+        put_varint(&mut bytes, 127);
+        assert_eq!(bytes, vec![0x7f]);
+        bytes.clear();
+        put_varint(&mut bytes, 128);
+        assert_eq!(bytes, vec![0x80, 0x01]);
+
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+        assert_eq!(unzigzag(zigzag(i64::MIN)), i64::MIN);
+    }
+
+    #[test]
+    fn truncated_rows_are_corrupt() {
+        let bytes = serialize_row(&user(1, "rosh", "kakapio@gmail.com"));
+        assert_eq!(
+            deserialize_row(&bytes[..bytes.len() - 1]),
+            Err(StorageError::Corrupt("record ends early".to_string()))
+        );
+    }
+
+    #[test]
+    fn rows_that_do_not_fit_the_schema_are_corrupt() {
+        let bytes = serialize_row(&user(1, "rosh", "kakapio@gmail.com"));
+        assert!(matches!(
+            decode_row(&bytes, &schema()),
+            Err(StorageError::Corrupt(_))
+        ));
+
+        let row = Row::new(vec![
+            Value::Integer(1),
+            Value::Null,
+            Value::Real(2.5),
+            Value::Boolean(true),
+        ]);
+        assert_eq!(decode_row(&serialize_row(&row), &schema()), Ok(row));
+    }
+
+    #[test]
+    fn catalog_round_trip() {
+        let entries = vec![TableEntry {
+            name: "users".to_string(),
+            schema: schema(),
+            root_page: 3,
+        }];
+        let bytes = encode_catalog(&entries);
+        assert_eq!(decode_catalog(&bytes).unwrap(), entries);
+        assert_eq!(decode_catalog(&[]).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn versions_round_trip() {
+        let versions = vec![
+            Version {
+                created: 3,
+                deleted: Some(300),
+                record: serialize_row(&Row::new(vec![Value::Integer(1)])),
+            },
+            Version {
+                created: 300,
+                deleted: None,
+                record: vec![],
+            },
+        ];
+        let bytes = encode_versions(&versions);
+        assert_eq!(decode_versions(&bytes).unwrap(), versions);
+        assert!(decode_versions(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
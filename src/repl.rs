@@ -0,0 +1,169 @@
+use crate::backend::{execute_command, execute_statement, Database, ExecuteResult};
+use crate::error::Error;
+use crate::parser::{prepare, MetaCommandResult};
+use crate::query::ResultSet;
+use crate::schema::Row;
+use std::io::{self, BufRead, Write};
+
+/// Runs the REPL against the database file at `path`, on stdin and stdout. Everything the user
+/// sees is printed here; the library itself only hands back results.
+pub fn entrypoint(path: &str) {
+    let db = match Database::open(path) {
+        Ok(db) => db,
+        Err(err) => {
+            println!("Could not open {}: {}", path, err);
+            return;
+        }
+    };
+
+    if let Err(err) = run(db, io::stdin().lock(), io::stdout()) {
+        println!("Error: {}", err);
+    }
+}
+
+/// Reads commands from `input` until `.exit` or the end of the input, writing whatever the user
+/// should see to `output`. Either way the database is shut down cleanly before this returns.
+pub fn run(mut db: Database, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let result = read_commands(&mut db, &mut input, &mut output);
+    if let Err(err) = db.close() {
+        writeln!(output, "Error: {}", err)?;
+    }
+    result
+}
+
+fn read_commands(
+    db: &mut Database,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+
+        writeln!(output, "Please enter the SQL command: ")?;
+        output.flush()?;
+        if input.read_line(&mut line)? == 0 {
+            return Ok(()); // End of input, e.g Ctrl-D.
+        }
+        let line = line.trim(); // Remove trailing newline.
+
+        // Is a command
+        if line.starts_with('.') {
+            match execute_command(line) {
+                MetaCommandResult::Exit => return Ok(()),
+                MetaCommandResult::Unrecognized => {
+                    writeln!(output, "Unrecognized command: {}", line)?
+                }
+                MetaCommandResult::Success => {}
+            }
+            continue; // Skip this iteration of our IO loop.
+        }
+
+        let statement = match prepare(line) {
+            Ok(statement) => statement,
+            Err(Error::Parse(err)) => {
+                writeln!(output, "{}", err.render(line))?;
+                continue;
+            }
+            Err(err) => {
+                print_result(output, Err(err))?;
+                continue;
+            }
+        };
+
+        print_result(output, execute_statement(statement, db))?;
+    }
+}
+
+/// Shows the user how a statement went, along with any rows it found.
+fn print_result(output: &mut impl Write, result: Result<ExecuteResult, Error>) -> io::Result<()> {
+    match result {
+        Ok(ExecuteResult::Success(Some(result))) => {
+            writeln!(output, "{}", format_header(&result))?;
+            for row in result.rows.iter() {
+                writeln!(output, "{}", format_row(row))?;
+            }
+            writeln!(
+                output,
+                "Successfully executed, {} row(s) found...",
+                result.rows.len()
+            )
+        }
+        Ok(ExecuteResult::Success(None)) => {
+            writeln!(output, "Successfully executed...")
+        }
+        Ok(ExecuteResult::RowsAffected(count)) => {
+            writeln!(
+                output,
+                "Successfully executed, {} row(s) affected...",
+                count
+            )
+        }
+        Err(err) => {
+            writeln!(output, "Error: {}", err)
+        }
+    }
+}
+
+/// Names the columns of a result set, laid out the way its rows are.
+fn format_header(result: &ResultSet) -> String {
+    let names: Vec<&str> = result.columns.iter().map(|c| c.name.as_str()).collect();
+    names.join(" | ")
+}
+
+/// Lays a row out on one line, its values separated by bars.
+fn format_row(row: &Row) -> String {
+    let values: Vec<String> = row.values.iter().map(ToString::to_string).collect();
+    values.join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    #[test]
+    fn rows_format_on_one_line() {
+        let row = Row::new(vec![
+            Value::Integer(13),
+            Value::Text("rosh".to_string()),
+            Value::Null,
+        ]);
+        assert_eq!(format_row(&row), "13 | rosh | NULL");
+    }
+
+    /// Helper method to run the REPL over `input` on a fresh database, returning what it printed.
+    fn repl_output(input: &str) -> String {
+        let mut output = Vec::new();
+        run(Database::new(), input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn end_of_input_ends_the_repl() {
+        let output = repl_output(
+            "create table users (id integer primary key, username text)\n\
+             insert into users values (1, 'rosh')\n\
+             select * from users\n\
+             select id + 1 as next, username name from users",
+        );
+        assert!(output.contains("id | username\n1 | rosh\n"));
+        assert!(output.contains("next | name\n2 | rosh\n"));
+        assert!(output.ends_with("Please enter the SQL command: \n"));
+    }
+
+    #[test]
+    fn exit_ends_the_repl() {
+        let output = repl_output(".dummy\n.exit\nselect * from users\n");
+        assert!(output.contains("Unrecognized command: .dummy"));
+        assert!(!output.contains("no such table"));
+        assert_eq!(output.matches("Please enter the SQL command").count(), 2);
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_problem() {
+        let output = repl_output("selekt * from users\nselect * from\n");
+        assert!(output.contains("--> line 1, column 1\n"));
+        assert!(output.contains("1 | selekt * from users\n  | ^^^^^^\n"));
+        assert!(output.contains("--> line 1, column 14\n"));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::value::Value;
+use std::fmt;
+
+/// The types a column can be declared with.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DataType {
+    Integer,
+    Real,
+    Text,
+    Boolean,
+}
+
+impl DataType {
+    /// Maps a type name from a column definition onto one of our types, e.g `VARCHAR` is `Text`.
+    pub fn from_name(name: &str) -> Option<DataType> {
+        let data_type = match name.to_ascii_uppercase().as_str() {
+            "INTEGER" | "INT" | "BIGINT" => DataType::Integer,
+            "REAL" | "FLOAT" | "DOUBLE" => DataType::Real,
+            "TEXT" | "VARCHAR" | "STRING" => DataType::Text,
+            "BOOLEAN" | "BOOL" => DataType::Boolean,
+            _ => return None,
+        };
+        Some(data_type)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataType::Integer => "INTEGER",
+            DataType::Real => "REAL",
+            DataType::Text => "TEXT",
+            DataType::Boolean => "BOOLEAN",
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A single column of a table definition.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub data_type: DataType,
+    pub not_null: bool,
+    /// Rows are stored in order of this column. A primary key is never NULL.
+    pub primary_key: bool,
+    /// The table the column comes from in rows that join several, which tells apart columns of
+    /// the same name. `None` in a table's own schema.
+    pub table: Option<String>,
+}
+
+impl Column {
+    pub fn new(name: &str, data_type: DataType) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type,
+            not_null: false,
+            primary_key: false,
+            table: None,
+        }
+    }
+
+    /// The same column without NOT NULL or PRIMARY KEY, for rows that are only ever read.
+    pub fn unconstrained(&self) -> Column {
+        Column {
+            not_null: false,
+            primary_key: false,
+            ..self.clone()
+        }
+    }
+
+    /// Checks that `value` can be stored in this column, converting it if needed.
+    /// Integers are widened when stored in a REAL column, nothing else is converted.
+    pub fn coerce(&self, value: Value) -> Result<Value, SchemaError> {
+        match (self.data_type, value) {
+            (_, Value::Null) if self.not_null => Err(SchemaError::NotNull(self.name.clone())),
+            (_, Value::Null) => Ok(Value::Null),
+            (DataType::Integer, value @ Value::Integer(_))
+            | (DataType::Real, value @ Value::Real(_))
+            | (DataType::Text, value @ Value::Text(_))
+            | (DataType::Boolean, value @ Value::Boolean(_)) => Ok(value),
+            (DataType::Real, Value::Integer(i)) => Ok(Value::Real(i as f64)),
+            (expected, value) => Err(SchemaError::TypeMismatch {
+                column: self.name.clone(),
+                expected,
+                found: value.type_name(),
+            }),
+        }
+    }
+}
+
+/// The ordered set of columns making up a table.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+}
+
+impl Schema {
+    /// Builds a schema, rejecting definitions that name the same column twice or
+    /// don't have a usable primary key. The primary key is made NOT NULL if it wasn't declared so.
+    pub fn new(mut columns: Vec<Column>) -> Result<Schema, SchemaError> {
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|other| other.name == column.name) {
+                return Err(SchemaError::DuplicateColumn(column.name.clone()));
+            }
+        }
+
+        let mut keys = columns.iter().filter(|column| column.primary_key);
+        if let Some(key) = keys.next() {
+            if keys.next().is_some() {
+                return Err(SchemaError::MultiplePrimaryKeys);
+            }
+            // Rows are kept in a tree ordered by integer keys.
+            if key.data_type != DataType::Integer {
+                return Err(SchemaError::PrimaryKeyType {
+                    column: key.name.clone(),
+                    found: key.data_type,
+                });
+            }
+        }
+        for column in columns.iter_mut().filter(|column| column.primary_key) {
+            column.not_null = true;
+        }
+
+        Ok(Schema { columns })
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+
+    /// Where the column `name` is, the one that comes from `table` if that is given.
+    pub fn find(&self, table: Option<&str>, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| {
+            column.name == name && table.is_none_or(|table| column.table.as_deref() == Some(table))
+        })
+    }
+
+    /// The position of the primary key column, if the table has one.
+    pub fn primary_key(&self) -> Option<usize> {
+        self.columns.iter().position(|column| column.primary_key)
+    }
+
+    /// Checks a full row of values against the schema, returning it with any conversions applied.
+    pub fn check_row(&self, values: Vec<Value>) -> Result<Row, SchemaError> {
+        if values.len() != self.columns.len() {
+            return Err(SchemaError::ColumnCount {
+                expected: self.columns.len(),
+                found: values.len(),
+            });
+        }
+
+        let values = self
+            .columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| column.coerce(value))
+            .collect::<Result<_, _>>()?;
+        Ok(Row { values })
+    }
+}
+
+/// A single row of a table, holding one value per column of its schema.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Row {
+    pub values: Vec<Value>,
+}
+
+impl Row {
+    pub fn new(values: Vec<Value>) -> Row {
+        Row { values }
+    }
+}
+
+/// Why a table definition or a row doesn't fit the table it is meant for.
+#[derive(PartialEq, Debug, Clone)]
+pub enum SchemaError {
+    DuplicateColumn(String),
+    ColumnCount {
+        expected: usize,
+        found: usize,
+    },
+    TypeMismatch {
+        column: String,
+        expected: DataType,
+        found: &'static str,
+    },
+    NotNull(String),
+    TableExists(String),
+    UnknownTable(String),
+    MultiplePrimaryKeys,
+    PrimaryKeyType {
+        column: String,
+        found: DataType,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::DuplicateColumn(name) => write!(f, "column '{}' is defined twice", name),
+            SchemaError::ColumnCount { expected, found } => {
+                write!(f, "expected {} values, got {}", expected, found)
+            }
+            SchemaError::TypeMismatch {
+                column,
+                expected,
+                found,
+            } => write!(f, "column '{}' expects {}, got {}", column, expected, found),
+            SchemaError::NotNull(name) => write!(f, "column '{}' cannot be NULL", name),
+            SchemaError::TableExists(name) => write!(f, "table '{}' already exists", name),
+            SchemaError::UnknownTable(name) => write!(f, "no such table '{}'", name),
+            SchemaError::MultiplePrimaryKeys => write!(f, "a table can only have one primary key"),
+            SchemaError::PrimaryKeyType { column, found } => {
+                write!(f, "primary key '{}' must be INTEGER, not {}", column, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// A change that would break one of a table's constraints.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ConstraintViolation {
+    /// Another row of `table` already has `key` as its primary key.
+    PrimaryKey {
+        table: String,
+        column: String,
+        key: i64,
+    },
+}
+
+impl fmt::Display for ConstraintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstraintViolation::PrimaryKey { table, column, key } => write!(
+                f,
+                "duplicate primary key: {}.{} = {} already exists",
+                table, column, key
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConstraintViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users() -> Schema {
+        let mut id = Column::new("id", DataType::Integer);
+        id.not_null = true;
+        Schema::new(vec![
+            id,
+            Column::new("username", DataType::Text),
+            Column::new("score", DataType::Real),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn schema_rejects_duplicate_columns() {
+        let result = Schema::new(vec![
+            Column::new("id", DataType::Integer),
+            Column::new("id", DataType::Text),
+        ]);
+        assert_eq!(result, Err(SchemaError::DuplicateColumn("id".to_string())));
+    }
+
+    #[test]
+    fn schema_primary_key() {
+        let mut id = Column::new("id", DataType::Integer);
+        id.primary_key = true;
+        let schema = Schema::new(vec![Column::new("name", DataType::Text), id.clone()]).unwrap();
+        assert_eq!(schema.primary_key(), Some(1));
+        // A primary key is never NULL, whether or not it was declared NOT NULL.
+        assert_eq!(
+            schema.check_row(vec![Value::Null, Value::Null]),
+            Err(SchemaError::NotNull("id".to_string()))
+        );
+        assert_eq!(users().primary_key(), None);
+
+        let result = Schema::new(vec![id.clone(), id]);
+        assert_eq!(result, Err(SchemaError::DuplicateColumn("id".to_string())));
+
+        let mut name = Column::new("name", DataType::Text);
+        name.primary_key = true;
+        let result = Schema::new(vec![name.clone()]);
+        assert_eq!(
+            result,
+            Err(SchemaError::PrimaryKeyType {
+                column: "name".to_string(),
+                found: DataType::Text
+            })
+        );
+
+        let mut other = Column::new("other", DataType::Integer);
+        other.primary_key = true;
+        let mut id = Column::new("id", DataType::Integer);
+        id.primary_key = true;
+        let result = Schema::new(vec![id, other]);
+        assert_eq!(result, Err(SchemaError::MultiplePrimaryKeys));
+    }
+
+    #[test]
+    fn find_columns_of_joined_tables() {
+        let joined = |table: &str, name: &str| Column {
+            table: Some(table.to_string()),
+            ..Column::new(name, DataType::Integer)
+        };
+        let schema = Schema {
+            columns: vec![joined("u", "id"), joined("o", "id"), joined("o", "total")],
+        };
+        assert_eq!(schema.find(Some("o"), "id"), Some(1));
+        assert_eq!(schema.find(None, "id"), Some(0));
+        assert_eq!(schema.find(Some("u"), "total"), None);
+        assert_eq!(users().find(Some("users"), "id"), None);
+    }
+
+    #[test]
+    fn check_row_widens_integers() {
+        let row = users()
+            .check_row(vec![Value::Integer(1), Value::Null, Value::Integer(3)])
+            .unwrap();
+        assert_eq!(
+            row.values,
+            vec![Value::Integer(1), Value::Null, Value::Real(3.0)]
+        );
+    }
+
+    #[test]
+    fn check_row_type_mismatch() {
+        let result =
+            users().check_row(vec![Value::Text("1".to_string()), Value::Null, Value::Null]);
+        assert_eq!(
+            result,
+            Err(SchemaError::TypeMismatch {
+                column: "id".to_string(),
+                expected: DataType::Integer,
+                found: "TEXT"
+            })
+        );
+    }
+
+    #[test]
+    fn check_row_not_null() {
+        let result = users().check_row(vec![Value::Null, Value::Null, Value::Null]);
+        assert_eq!(result, Err(SchemaError::NotNull("id".to_string())));
+    }
+
+    #[test]
+    fn check_row_column_count() {
+        let result = users().check_row(vec![Value::Integer(1)]);
+        assert_eq!(
+            result,
+            Err(SchemaError::ColumnCount {
+                expected: 3,
+                found: 1
+            })
+        );
+    }
+}
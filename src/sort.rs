@@ -0,0 +1,483 @@
+use crate::eval::compare;
+use crate::pager::StorageError;
+use crate::record::{deserialize_row, serialize_row};
+use crate::schema::Row;
+use crate::value::Value;
+use log::debug;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicUsize};
+use std::vec;
+
+/// How much memory a sort may take before it starts writing sorted runs out to temporary files,
+/// unless `Database::set_sort_memory` says otherwise.
+pub const DEFAULT_SORT_MEMORY: usize = 64 << 20;
+
+/// How one key of a sort orders its values.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Direction {
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+/// Orders two lists of sort keys, each key going the way its direction says.
+pub fn compare_keys(directions: &[Direction], left: &[Value], right: &[Value]) -> Ordering {
+    for ((direction, left), right) in directions.iter().zip(left).zip(right) {
+        let ordering = match (left.is_null(), right.is_null()) {
+            (true, true) => Ordering::Equal,
+            // NULLs stay where they were asked to be whichever way the key runs.
+            (true, false) if direction.nulls_first => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            (false, true) => match direction.nulls_first {
+                true => Ordering::Greater,
+                false => Ordering::Less,
+            },
+            (false, false) => {
+                let ordering = compare_values(left, right);
+                match direction.descending {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Orders two non-NULL values. Values that `compare` can't order, like a NaN or values of
+/// different types, still need a place, so they fall back on a fixed order between types.
+fn compare_values(left: &Value, right: &Value) -> Ordering {
+    if let Some(ordering) = compare(left, right) {
+        return ordering;
+    }
+    let rank = |value: &Value| match value {
+        Value::Null => 0,
+        Value::Boolean(_) => 1,
+        Value::Integer(_) | Value::Real(_) => 2,
+        Value::Text(_) => 3,
+    };
+    match (left, right) {
+        (Value::Real(l), Value::Real(r)) => l.total_cmp(r),
+        _ => rank(left).cmp(&rank(right)),
+    }
+}
+
+/// A row waiting to be sorted, along with the keys it is sorted on.
+struct Entry {
+    keys: Vec<Value>,
+    row: Row,
+}
+
+impl Entry {
+    /// Roughly how much memory the entry takes up.
+    fn footprint(&self) -> usize {
+        let values = self.keys.iter().chain(self.row.values.iter());
+        values.fold(mem::size_of::<Entry>(), |size, value| {
+            size + mem::size_of::<Value>()
+                + match value {
+                    Value::Text(text) => text.len(),
+                    _ => 0,
+                }
+        })
+    }
+}
+
+/// Sorts rows on keys given alongside them. The sort is stable: rows with equal keys come out in
+/// the order they went in.
+///
+/// Rows are kept in memory up to the budget given, past which they are sorted and written out to
+/// a temporary file as a run. Once every row is in, the runs are merged back together, so sorting
+/// needs memory for one row per run rather than for every row.
+pub struct Sorter {
+    directions: Rc<[Direction]>,
+    memory: usize,
+    buffer: Vec<Entry>,
+    buffered: usize,
+    runs: Vec<Run>,
+    /// How many rows from the start of the sorted order are wanted, when not all of them are.
+    keep: Option<usize>,
+}
+
+impl Sorter {
+    pub fn new(directions: Vec<Direction>, memory: usize) -> Sorter {
+        Sorter {
+            directions: directions.into(),
+            memory,
+            buffer: Vec::new(),
+            buffered: 0,
+            runs: Vec::new(),
+            keep: None,
+        }
+    }
+
+    /// Only the first `rows` rows of the sorted order are going to be read, so the rest can be
+    /// thrown away as soon as it is clear they won't make it.
+    pub fn keep_first(&mut self, rows: usize) {
+        self.keep = Some(rows);
+    }
+
+    /// Adds a row to sort, spilling what has been buffered so far to disk if it outgrows the
+    /// memory budget.
+    pub fn push(&mut self, keys: Vec<Value>, row: Row) -> Result<(), StorageError> {
+        let entry = Entry { keys, row };
+        self.buffered += entry.footprint();
+        self.buffer.push(entry);
+        if let Some(keep) = self
+            .keep
+            .filter(|&keep| self.buffer.len() >= keep.max(1).saturating_mul(2))
+        {
+            self.truncate(keep);
+        }
+        if self.buffered > self.memory {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sorts the buffer and drops everything past its first `rows` rows, which can never come
+    /// before them. The sort is stable, so rows still go on in the order they came in.
+    fn truncate(&mut self, rows: usize) {
+        self.sort_buffer();
+        self.buffer.truncate(rows);
+        self.buffered = self.buffer.iter().map(Entry::footprint).sum();
+    }
+
+    /// How many runs have been written out to temporary files so far.
+    pub fn spilled(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Hands back the rows in order. Rows that never left memory are simply sorted in place.
+    pub fn finish(mut self) -> Result<Sorted, StorageError> {
+        if self.runs.is_empty() {
+            self.sort_buffer();
+            return Ok(Sorted {
+                source: Source::Memory(self.buffer.into_iter()),
+            });
+        }
+
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        debug!("merging {} sorted runs", self.runs.len());
+        let mut heap = BinaryHeap::with_capacity(self.runs.len());
+        let mut readers = Vec::with_capacity(self.runs.len());
+        for (index, run) in self.runs.into_iter().enumerate() {
+            let mut reader = run.reader(self.directions.len())?;
+            if let Some(entry) = reader.next_entry()? {
+                heap.push(Head::new(entry, index, &self.directions));
+            }
+            readers.push(reader);
+        }
+        Ok(Sorted {
+            source: Source::Merge { readers, heap },
+        })
+    }
+
+    fn sort_buffer(&mut self) {
+        let directions = &self.directions;
+        self.buffer
+            .sort_by(|left, right| compare_keys(directions, &left.keys, &right.keys));
+    }
+
+    /// Sorts the buffered rows and writes them out as a new run.
+    fn spill(&mut self) -> Result<(), StorageError> {
+        match self.keep {
+            Some(keep) => self.truncate(keep),
+            None => self.sort_buffer(),
+        }
+        let run = Run::create()?;
+        let mut writer = BufWriter::new(&run.file);
+        for entry in self.buffer.drain(..) {
+            let mut values = entry.keys;
+            values.extend(entry.row.values);
+            let record = serialize_row(&Row::new(values));
+            writer.write_all(&(record.len() as u32).to_le_bytes())?;
+            writer.write_all(&record)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        debug!("spilled {} bytes of rows to {:?}", self.buffered, run.path);
+        self.buffered = 0;
+        self.runs.push(run);
+        Ok(())
+    }
+}
+
+/// Tells temporary files of the same process apart.
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// A sorted run written out to a temporary file, which goes away along with it.
+struct Run {
+    path: PathBuf,
+    file: File,
+}
+
+impl Run {
+    fn create() -> Result<Run, StorageError> {
+        let number = RUNS.fetch_add(1, atomic::Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("sql_engine-sort-{}-{}", process::id(), number));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Run { path, file })
+    }
+
+    /// Reads the run back from the start. Each entry holds `keys` sort keys ahead of its row.
+    fn reader(self, keys: usize) -> Result<RunReader, StorageError> {
+        let file = File::open(&self.path)?;
+        Ok(RunReader {
+            input: BufReader::new(file),
+            keys,
+            _run: self,
+        })
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct RunReader {
+    input: BufReader<File>,
+    keys: usize,
+    _run: Run,
+}
+
+impl RunReader {
+    fn next_entry(&mut self) -> Result<Option<Entry>, StorageError> {
+        let mut len = [0u8; 4];
+        match self.input.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let mut record = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.input.read_exact(&mut record)?;
+
+        let mut keys = deserialize_row(&record)?.values;
+        let values = keys.split_off(self.keys.min(keys.len()));
+        Ok(Some(Entry {
+            keys,
+            row: Row::new(values),
+        }))
+    }
+}
+
+/// The first entry left in a run, as it waits in the merge.
+struct Head {
+    entry: Entry,
+    run: usize,
+    directions: Rc<[Direction]>,
+}
+
+impl Head {
+    fn new(entry: Entry, run: usize, directions: &Rc<[Direction]>) -> Head {
+        Head {
+            entry,
+            run,
+            directions: directions.clone(),
+        }
+    }
+}
+
+impl Ord for Head {
+    /// The heap hands back its greatest element, so the order is turned around to get the entry
+    /// that sorts first. Ties go to the earlier run, which keeps the sort stable.
+    fn cmp(&self, other: &Head) -> Ordering {
+        compare_keys(&self.directions, &self.entry.keys, &other.entry.keys)
+            .then(self.run.cmp(&other.run))
+            .reverse()
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Head) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// The rows of a finished sort, in order.
+pub struct Sorted {
+    source: Source,
+}
+
+enum Source {
+    Memory(vec::IntoIter<Entry>),
+    Merge {
+        readers: Vec<RunReader>,
+        heap: BinaryHeap<Head>,
+    },
+}
+
+impl Iterator for Sorted {
+    type Item = Result<Row, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.source {
+            Source::Memory(entries) => entries.next().map(|entry| Ok(entry.row)),
+            Source::Merge { readers, heap } => {
+                let head = heap.pop()?;
+                match readers[head.run].next_entry() {
+                    Ok(Some(entry)) => heap.push(Head::new(entry, head.run, &head.directions)),
+                    Ok(None) => {}
+                    Err(err) => return Some(Err(err)),
+                }
+                Some(Ok(head.entry.row))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ascending() -> Direction {
+        Direction {
+            descending: false,
+            nulls_first: true,
+        }
+    }
+
+    /// Helper method to sort integer keys, each tagged with the position it went in at.
+    fn sort(keys: &[Option<i64>], direction: Direction, memory: usize) -> (Vec<Row>, usize) {
+        let mut sorter = Sorter::new(vec![direction], memory);
+        for (i, key) in keys.iter().enumerate() {
+            let key = key.map_or(Value::Null, Value::Integer);
+            let row = Row::new(vec![key.clone(), Value::Integer(i as i64)]);
+            sorter.push(vec![key], row).unwrap();
+        }
+        let spilled = sorter.spilled();
+        let rows = sorter.finish().unwrap().collect::<Result<_, _>>().unwrap();
+        (rows, spilled)
+    }
+
+    fn row(key: Option<i64>, position: i64) -> Row {
+        Row::new(vec![
+            key.map_or(Value::Null, Value::Integer),
+            Value::Integer(position),
+        ])
+    }
+
+    #[test]
+    fn compare_keys_directions() {
+        let descending = Direction {
+            descending: true,
+            nulls_first: false,
+        };
+        let one = [Value::Integer(1), Value::Text("b".to_string())];
+        let two = [Value::Integer(1), Value::Text("a".to_string())];
+        let null = [Value::Null, Value::Text("a".to_string())];
+
+        let directions = [ascending(), ascending()];
+        assert_eq!(compare_keys(&directions, &one, &two), Ordering::Greater);
+        assert_eq!(compare_keys(&directions, &null, &one), Ordering::Less);
+
+        let directions = [descending, descending];
+        assert_eq!(compare_keys(&directions, &one, &two), Ordering::Less);
+        assert_eq!(compare_keys(&directions, &null, &one), Ordering::Greater);
+
+        // NULLS FIRST holds for descending keys too.
+        let directions = [
+            Direction {
+                descending: true,
+                nulls_first: true,
+            },
+            ascending(),
+        ];
+        assert_eq!(compare_keys(&directions, &null, &one), Ordering::Less);
+    }
+
+    #[test]
+    fn sorts_in_memory() {
+        let (rows, spilled) = sort(&[Some(3), None, Some(1), Some(3)], ascending(), 1 << 20);
+        assert_eq!(spilled, 0);
+        assert_eq!(
+            rows,
+            vec![
+                row(None, 1),
+                row(Some(1), 2),
+                row(Some(3), 0),
+                row(Some(3), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn spills_and_merges() {
+        let keys: Vec<Option<i64>> = (0..500)
+            .map(|i| match i % 7 {
+                0 => None,
+                _ => Some((i * 37) % 101),
+            })
+            .collect();
+        let (in_memory, _) = sort(&keys, ascending(), 1 << 20);
+        let (merged, spilled) = sort(&keys, ascending(), 2048);
+        assert!(spilled > 1);
+        assert_eq!(merged, in_memory);
+
+        // Every row gets a run of its own when there's no memory to spare at all.
+        let descending = Direction {
+            descending: true,
+            nulls_first: false,
+        };
+        let (in_memory, _) = sort(&keys, descending, 1 << 20);
+        let (merged, spilled) = sort(&keys, descending, 0);
+        assert_eq!(spilled, keys.len());
+        assert_eq!(merged, in_memory);
+    }
+
+    #[test]
+    fn keeps_only_the_first_rows() {
+        let keys: Vec<Option<i64>> = (0..200).map(|i| Some((i * 53) % 17)).collect();
+        let (all, _) = sort(&keys, ascending(), 1 << 20);
+        for memory in [1 << 20, 512] {
+            let mut sorter = Sorter::new(vec![ascending()], memory);
+            sorter.keep_first(25);
+            for (i, key) in keys.iter().enumerate() {
+                let row = row(*key, i as i64);
+                sorter.push(vec![row.values[0].clone()], row).unwrap();
+                assert!(sorter.buffer.len() < 50);
+            }
+            let rows: Vec<Row> = sorter.finish().unwrap().map(Result::unwrap).collect();
+            assert!(rows.len() >= 25);
+            assert_eq!(rows[..25], all[..25]);
+        }
+    }
+
+    #[test]
+    fn runs_are_removed() {
+        let mut sorter = Sorter::new(vec![ascending()], 0);
+        sorter
+            .push(vec![Value::Integer(1)], Row::new(vec![Value::Integer(1)]))
+            .unwrap();
+        let path = sorter.runs[0].path.clone();
+        assert!(path.exists());
+        drop(sorter);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+/// A transaction statement that doesn't make sense in the state the database is in.
+#[derive(PartialEq, Debug, Clone)]
+pub enum TransactionError {
+    /// `BEGIN` while a transaction is already open. Transactions don't nest. Also what a thread
+    /// holding a transaction on a `Connection` gets for writing around it.
+    AlreadyActive,
+    /// `COMMIT`, `ROLLBACK` or a savepoint statement without an open transaction.
+    NotActive,
+    /// `ROLLBACK TO` or `RELEASE` a savepoint the open transaction doesn't have.
+    UnknownSavepoint(String),
+    /// A transaction statement run on a shared `Connection`, which starts transactions itself.
+    Shared,
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::AlreadyActive => write!(f, "a transaction is already open"),
+            TransactionError::NotActive => write!(f, "no transaction is open"),
+            TransactionError::UnknownSavepoint(name) => write!(f, "no such savepoint '{}'", name),
+            TransactionError::Shared => write!(
+                f,
+                "transactions on a shared connection are started with Connection::begin"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// A single SQL value, as produced by literals and expression evaluation.
+#[derive(PartialEq, Debug, Default, Clone)]
+pub enum Value {
+    #[default]
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Boolean(bool),
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// The SQL name of this value's type, used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "NULL",
+            Value::Integer(_) => "INTEGER",
+            Value::Real(_) => "REAL",
+            Value::Text(_) => "TEXT",
+            Value::Boolean(_) => "BOOLEAN",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Real(value) => write!(f, "{:?}", value),
+            Value::Text(value) => write!(f, "{}", value),
+            Value::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
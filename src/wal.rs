@@ -0,0 +1,254 @@
+use crate::pager::{Page, StorageError, PAGE_SIZE};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies the file as one of our logs, and which version of the format it uses.
+const MAGIC: &[u8; 16] = b"sql_engine wal1\0";
+
+/// The log starts with the magic, the page size and the salt every frame of the log carries.
+const HEADER_SIZE: usize = 24;
+
+/// Every frame is a header of the page number, whether the frame ends a commit, the salt and a
+/// checksum of the rest of the frame, followed by the page itself.
+const FRAME_HEADER_SIZE: usize = 16;
+const FRAME_SIZE: usize = FRAME_HEADER_SIZE + PAGE_SIZE;
+
+/// A write-ahead log, kept in a file next to the database file.
+///
+/// Committed pages are appended to the log and synced to disk before the database file is
+/// touched, so a crash can only ever lose work that hadn't finished committing. Opening the
+/// database replays whatever the log holds, and checkpoints fold the log back into the database
+/// file so it doesn't grow forever.
+pub struct Wal {
+    file: File,
+    path: PathBuf,
+    /// Frames written since the log was last reset are only valid with this salt, so leftovers
+    /// of an older log can never be mistaken for new ones.
+    salt: u32,
+    frames: u64,
+}
+
+/// Where the log for the database file at `path` lives.
+pub fn wal_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push("-wal");
+    PathBuf::from(name)
+}
+
+impl Wal {
+    /// Reads every page committed to the log at `path`, the latest version of each. Frames after
+    /// the last complete commit, or that were torn by a crash while being written, are left out.
+    pub fn recover(path: &Path) -> Result<BTreeMap<u32, Box<Page>>, StorageError> {
+        let mut bytes = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => file.read_to_end(&mut bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        // A log cut short before its header made it to disk can't hold any commits.
+        if bytes.len() < HEADER_SIZE {
+            return Ok(BTreeMap::new());
+        }
+        if &bytes[0..16] != MAGIC {
+            return Err(StorageError::Corrupt("not a write-ahead log".to_string()));
+        }
+        if read_u32(&bytes, 16) as usize != PAGE_SIZE {
+            return Err(StorageError::Corrupt(format!(
+                "write-ahead log page size {} is not supported",
+                read_u32(&bytes, 16)
+            )));
+        }
+        let salt = read_u32(&bytes, 20);
+
+        let mut committed = BTreeMap::new();
+        let mut pending = Vec::new();
+        for frame in bytes[HEADER_SIZE..].chunks_exact(FRAME_SIZE) {
+            let number = read_u32(frame, 0);
+            let commit = read_u32(frame, 4) != 0;
+            if read_u32(frame, 8) != salt || read_u32(frame, 12) != checksum(frame) {
+                break;
+            }
+
+            let mut page = Box::new([0; PAGE_SIZE]);
+            page.copy_from_slice(&frame[FRAME_HEADER_SIZE..]);
+            pending.push((number, page));
+            if commit {
+                committed.extend(pending.drain(..));
+            }
+        }
+        Ok(committed)
+    }
+
+    /// Starts an empty log at `path`, throwing away whatever was there.
+    pub fn create(path: &Path) -> Result<Wal, StorageError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let salt = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+
+        let mut wal = Wal {
+            file,
+            path: path.to_path_buf(),
+            salt,
+            frames: 0,
+        };
+        wal.reset()?;
+        Ok(wal)
+    }
+
+    /// How many frames were appended since the log was last reset.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Appends `pages` to the log as a single commit, and waits for the disk to confirm it has
+    /// them. Until then none of them count as committed.
+    pub fn append<'a>(
+        &mut self,
+        pages: impl ExactSizeIterator<Item = (u32, &'a Page)>,
+    ) -> Result<(), StorageError> {
+        let count = pages.len();
+        let mut bytes = Vec::with_capacity(count * FRAME_SIZE);
+        for (index, (number, page)) in pages.enumerate() {
+            let start = bytes.len();
+            bytes.extend_from_slice(&number.to_le_bytes());
+            bytes.extend_from_slice(&u32::from(index + 1 == count).to_le_bytes());
+            bytes.extend_from_slice(&self.salt.to_le_bytes());
+            bytes.extend_from_slice(&[0; 4]);
+            bytes.extend_from_slice(page);
+            let sum = checksum(&bytes[start..]);
+            bytes[start + 12..start + 16].copy_from_slice(&sum.to_le_bytes());
+        }
+
+        let end = HEADER_SIZE as u64 + self.frames * FRAME_SIZE as u64;
+        self.file.seek(SeekFrom::Start(end))?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.frames += count as u64;
+        Ok(())
+    }
+
+    /// Empties the log. Only safe once everything it held is synced to the database file.
+    pub fn reset(&mut self) -> Result<(), StorageError> {
+        self.salt = self.salt.wrapping_add(1);
+        let mut header = [0; HEADER_SIZE];
+        header[0..16].copy_from_slice(MAGIC);
+        header[16..20].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        header[20..24].copy_from_slice(&self.salt.to_le_bytes());
+
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.sync_all()?;
+        self.frames = 0;
+        Ok(())
+    }
+
+    /// Deletes the log file. Only safe once everything it held is synced to the database file.
+    pub fn remove(self) -> Result<(), StorageError> {
+        drop(self.file);
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// FNV-1a over a frame, leaving out the checksum field itself.
+fn checksum(frame: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for (index, byte) in frame.iter().enumerate() {
+        if (12..16).contains(&index) {
+            continue;
+        }
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// Helper method to get a path for a log file that doesn't exist yet.
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("sql_engine_wal_{}_{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// Helper method to make a page filled with `byte`.
+    fn page(byte: u8) -> Box<Page> {
+        Box::new([byte; PAGE_SIZE])
+    }
+
+    #[test]
+    fn recover_committed_pages() {
+        let path = temp_path("committed");
+        let mut wal = Wal::create(&path).unwrap();
+        wal.append([(3, &*page(1)), (5, &*page(2))].into_iter())
+            .unwrap();
+        wal.append([(3, &*page(7))].into_iter()).unwrap();
+        assert_eq!(wal.frames(), 3);
+
+        let pages = Wal::recover(&path).unwrap();
+        assert_eq!(pages.keys().copied().collect::<Vec<_>>(), vec![3, 5]);
+        assert_eq!(pages[&3], page(7));
+        assert_eq!(pages[&5], page(2));
+        wal.remove().unwrap();
+    }
+
+    #[test]
+    fn recover_discards_incomplete_commits() {
+        let path = temp_path("incomplete");
+        let mut wal = Wal::create(&path).unwrap();
+        wal.append([(3, &*page(1))].into_iter()).unwrap();
+        wal.append([(4, &*page(2)), (5, &*page(3))].into_iter())
+            .unwrap();
+
+        // Cut the last commit off part way through its final frame, as a crash would.
+        let length = fs::metadata(&path).unwrap().len();
+        wal.file.set_len(length - 100).unwrap();
+        let pages = Wal::recover(&path).unwrap();
+        assert_eq!(pages.keys().copied().collect::<Vec<_>>(), vec![3]);
+
+        // A frame whose contents don't match its checksum ends the log just the same.
+        wal.reset().unwrap();
+        wal.append([(3, &*page(1))].into_iter()).unwrap();
+        wal.append([(4, &*page(2))].into_iter()).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let pages = Wal::recover(&path).unwrap();
+        assert_eq!(pages.keys().copied().collect::<Vec<_>>(), vec![3]);
+        wal.remove().unwrap();
+    }
+
+    #[test]
+    fn reset_forgets_old_frames() {
+        let path = temp_path("reset");
+        let mut wal = Wal::create(&path).unwrap();
+        wal.append([(3, &*page(1))].into_iter()).unwrap();
+        wal.reset().unwrap();
+        assert_eq!(wal.frames(), 0);
+        assert!(Wal::recover(&path).unwrap().is_empty());
+        wal.remove().unwrap();
+        assert!(Wal::recover(&path).unwrap().is_empty());
+    }
+}
@@ -1,83 +1,109 @@
-use sql_engine::backend::{execute_command, execute_statement, Table};
-use sql_engine::parser::{prepare_statement, MetaCommandResult, Row, Statement};
+use sql_engine::ast::{CreateTable, Statement};
+use sql_engine::backend::{
+    execute_command, execute_read, execute_statement, Database, ExecuteResult,
+};
+use sql_engine::eval::EvalError;
+use sql_engine::limits::{Limit, Limits, Scope, TableFull};
+use sql_engine::mvcc::Snapshot;
+use sql_engine::parser::{prepare, MetaCommandResult};
+use sql_engine::query::ResultSet;
+use sql_engine::schema::{Column, ConstraintViolation, DataType, Row, SchemaError};
+use sql_engine::transaction::TransactionError;
+use sql_engine::value::Value;
+use sql_engine::Error;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-/// Helper method to quickly run SQL commands and mutate a table.
-fn do_sql_cmd(tb: &mut Table, cmd: &str) {
-    let mut statement = Statement::default();
-    prepare_statement(cmd, &mut statement);
-    execute_statement(statement, tb);
+/// Helper method to quickly run SQL commands against a database.
+fn do_sql_cmd(db: &mut Database, cmd: &str) {
+    execute_statement(prepare(cmd).unwrap(), db).unwrap();
+}
+
+/// Helper method to create a database holding the users table most tests work with.
+fn users_db() -> Database {
+    let mut db = Database::new();
+    do_sql_cmd(
+        &mut db,
+        "create table users (id integer primary key, username text, email text)",
+    );
+    db
+}
+
+/// Helper method to get at the rows of the users table.
+fn users_rows(db: &mut Database) -> Vec<Row> {
+    select_rows(db, "select * from users")
+}
+
+/// Helper method to build a row of the users table.
+fn user(id: i64, username: &str, email: &str) -> Row {
+    Row::new(vec![
+        Value::Integer(id),
+        Value::Text(username.to_string()),
+        Value::Text(email.to_string()),
+    ])
 }
 
 #[test]
 fn test_execute_statement_insert() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
 
     assert_eq!(
-        table.data,
-        vec![Row {
-            id: 13,
-            username: "rosh".to_string(),
-            email: "kakapio@gmail.com".to_string()
-        }]
+        users_rows(&mut db),
+        vec![user(13, "rosh", "kakapio@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_statement_insert_fail() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
 
     assert_ne!(
-        table.data,
-        vec![Row {
-            id: 13,
-            username: "alfred".to_string(),
-            email: "alfredddd1@gmail.com".to_string()
-        }]
+        users_rows(&mut db),
+        vec![user(13, "alfred", "alfredddd1@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_multiple_insert() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-    do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+    );
     do_sql_cmd(
-        &mut table,
-        "insert 1699 sniper_penut penutterbutter@yahoo.com",
+        &mut db,
+        "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com')",
     );
 
     assert_eq!(
-        table.data,
+        users_rows(&mut db),
         vec![
-            Row {
-                id: 13,
-                username: "rosh".to_string(),
-                email: "kakapio@gmail.com".to_string()
-            },
-            Row {
-                id: 42,
-                username: "stefan".to_string(),
-                email: "stefp@sigma.com".to_string()
-            },
-            Row {
-                id: 1699,
-                username: "sniper_penut".to_string(),
-                email: "penutterbutter@yahoo.com".to_string()
-            }
+            user(13, "rosh", "kakapio@gmail.com"),
+            user(42, "stefan", "stefp@sigma.com"),
+            user(1699, "sniper_penut", "penutterbutter@yahoo.com")
         ]
     );
 }
 
 #[test]
 fn test_execute_select_empty_table() {
-    let mut table = Table::new();
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let mut db = users_db();
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 0);
     } else {
         panic!("Expected Success with empty vector");
@@ -86,14 +112,16 @@ fn test_execute_select_empty_table() {
 
 #[test]
 fn test_execute_select_nonexistent_id() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
 
-    let mut statement = Statement::default();
-    prepare_statement("select 42", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 42").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 0);
     } else {
         panic!("Expected Success with empty vector");
@@ -102,19 +130,27 @@ fn test_execute_select_nonexistent_id() {
 
 #[test]
 fn test_execute_select_existing_id() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-    do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+    );
 
-    let mut statement = Statement::default();
-    prepare_statement("select 42", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 42").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 1);
-        assert_eq!(rows[0].id, 42);
-        assert_eq!(rows[0].username, "stefan");
-        assert_eq!(rows[0].email, "stefp@sigma.com");
+        assert_eq!(rows[0].values[0], Value::Integer(42));
+        assert_eq!(rows[0].values[1], Value::Text("stefan".to_string()));
+        assert_eq!(
+            rows[0].values[2],
+            Value::Text("stefp@sigma.com".to_string())
+        );
     } else {
         panic!("Expected Success with one row");
     }
@@ -122,19 +158,24 @@ fn test_execute_select_existing_id() {
 
 #[test]
 fn test_execute_select_all_multiple_rows() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-    do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+    );
     do_sql_cmd(
-        &mut table,
-        "insert 1699 sniper_penut penutterbutter@yahoo.com",
+        &mut db,
+        "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com')",
     );
 
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 3);
     } else {
         panic!("Expected Success with three rows");
@@ -143,69 +184,65 @@ fn test_execute_select_all_multiple_rows() {
 
 #[test]
 fn test_execute_insert_duplicate_id() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-    do_sql_cmd(&mut table, "insert 13 stefan stefp@sigma.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
+    let result = run(
+        &mut db,
+        "insert into users values (13, 'stefan', 'stefp@sigma.com')",
+    );
 
+    assert_eq!(result, duplicate_id(13));
     assert_eq!(
-        table.data,
-        vec![
-            Row {
-                id: 13,
-                username: "rosh".to_string(),
-                email: "kakapio@gmail.com".to_string()
-            },
-            Row {
-                id: 13,
-                username: "stefan".to_string(),
-                email: "stefp@sigma.com".to_string()
-            }
-        ]
+        users_rows(&mut db),
+        vec![user(13, "rosh", "kakapio@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_insert_zero_id() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 0 rosh kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (0, 'rosh', 'kakapio@gmail.com')",
+    );
 
     assert_eq!(
-        table.data,
-        vec![Row {
-            id: 0,
-            username: "rosh".to_string(),
-            email: "kakapio@gmail.com".to_string()
-        }]
+        users_rows(&mut db),
+        vec![user(0, "rosh", "kakapio@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_insert_max_id() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 4294967295 rosh kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (4294967295, 'rosh', 'kakapio@gmail.com')",
+    );
 
     assert_eq!(
-        table.data,
-        vec![Row {
-            id: 4294967295,
-            username: "rosh".to_string(),
-            email: "kakapio@gmail.com".to_string()
-        }]
+        users_rows(&mut db),
+        vec![user(4294967295, "rosh", "kakapio@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_select_zero_id() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 0 rosh kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (0, 'rosh', 'kakapio@gmail.com')",
+    );
 
-    let mut statement = Statement::default();
-    prepare_statement("select 0", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 0").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 1);
-        assert_eq!(rows[0].id, 0);
+        assert_eq!(rows[0].values[0], Value::Integer(0));
     } else {
         panic!("Expected Success with one row");
     }
@@ -213,16 +250,18 @@ fn test_execute_select_zero_id() {
 
 #[test]
 fn test_execute_select_max_id() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 4294967295 rosh kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (4294967295, 'rosh', 'kakapio@gmail.com')",
+    );
 
-    let mut statement = Statement::default();
-    prepare_statement("select 4294967295", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 4294967295").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 1);
-        assert_eq!(rows[0].id, 4294967295);
+        assert_eq!(rows[0].values[0], Value::Integer(4294967295));
     } else {
         panic!("Expected Success with one row");
     }
@@ -230,98 +269,95 @@ fn test_execute_select_max_id() {
 
 #[test]
 fn test_execute_insert_special_chars_username() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh!@#$ kakapio@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh!@#$', 'kakapio@gmail.com')",
+    );
 
     assert_eq!(
-        table.data,
-        vec![Row {
-            id: 13,
-            username: "rosh!@#$".to_string(),
-            email: "kakapio@gmail.com".to_string()
-        }]
+        users_rows(&mut db),
+        vec![user(13, "rosh!@#$", "kakapio@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_insert_special_chars_email() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio+special@gmail.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio+special@gmail.com')",
+    );
 
     assert_eq!(
-        table.data,
-        vec![Row {
-            id: 13,
-            username: "rosh".to_string(),
-            email: "kakapio+special@gmail.com".to_string()
-        }]
+        users_rows(&mut db),
+        vec![user(13, "rosh", "kakapio+special@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_insert_long_username() {
-    let mut table = Table::new();
+    let mut db = users_db();
     let long_username = "a".repeat(1000);
     do_sql_cmd(
-        &mut table,
-        &format!("insert 13 {} kakapio@gmail.com", long_username),
+        &mut db,
+        &format!(
+            "insert into users values (13, '{}', 'kakapio@gmail.com')",
+            long_username
+        ),
     );
 
     assert_eq!(
-        table.data,
-        vec![Row {
-            id: 13,
-            username: long_username,
-            email: "kakapio@gmail.com".to_string()
-        }]
+        users_rows(&mut db),
+        vec![user(13, &long_username, "kakapio@gmail.com")]
     );
 }
 
 #[test]
 fn test_execute_insert_long_email() {
-    let mut table = Table::new();
+    let mut db = users_db();
     let long_email = format!("{}@gmail.com", "a".repeat(1000));
-    do_sql_cmd(&mut table, &format!("insert 13 rosh {}", long_email));
-
-    assert_eq!(
-        table.data,
-        vec![Row {
-            id: 13,
-            username: "rosh".to_string(),
-            email: long_email,
-        }]
+    do_sql_cmd(
+        &mut db,
+        &format!("insert into users values (13, 'rosh', '{}')", long_email),
     );
+
+    assert_eq!(users_rows(&mut db), vec![user(13, "rosh", &long_email)]);
 }
 
 #[test]
 fn test_execute_multiple_selects() {
-    let mut table = Table::new();
-    do_sql_cmd(&mut table, "insert 13 rosh kakapio@gmail.com");
-    do_sql_cmd(&mut table, "insert 42 stefan stefp@sigma.com");
+    let mut db = users_db();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+    );
     do_sql_cmd(
-        &mut table,
-        "insert 1699 sniper_penut penutterbutter@yahoo.com",
+        &mut db,
+        "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com')",
     );
 
     // Select all
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 3);
     } else {
         panic!("Expected Success with three rows");
     }
 
     // Select specific ID
-    let mut statement = Statement::default();
-    prepare_statement("select 42", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 42").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 1);
-        assert_eq!(rows[0].id, 42);
+        assert_eq!(rows[0].values[0], Value::Integer(42));
     } else {
         panic!("Expected Success with one row");
     }
@@ -333,3 +369,2009 @@ fn test_execute_command_unrecognized() {
     let out = execute_command(cmd);
     assert_eq!(out, MetaCommandResult::Unrecognized);
 }
+
+/// Helper method to run a select and get the matching rows back.
+fn select_rows(db: &mut Database, cmd: &str) -> Vec<Row> {
+    let statement = prepare(cmd).unwrap();
+    match execute_statement(statement, db) {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => rows,
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+}
+
+fn seed_users(db: &mut Database) {
+    do_sql_cmd(
+        db,
+        "insert into users values (13, 'rosh', 'kakapio@gmail.com')",
+    );
+    do_sql_cmd(
+        db,
+        "insert into users values (42, 'stefan', 'stefp@sigma.com')",
+    );
+    do_sql_cmd(
+        db,
+        "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com')",
+    );
+}
+
+#[test]
+fn test_execute_select_where_text_column() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let rows = select_rows(&mut db, "select * from users where username = 'stefan'");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values[0], Value::Integer(42));
+}
+
+#[test]
+fn test_execute_select_where_boolean_logic() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let rows = select_rows(
+        &mut db,
+        "SELECT * FROM users WHERE (id < 20 OR id > 1000) AND NOT email = 'kakapio@gmail.com'",
+    );
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values[1], Value::Text("sniper_penut".to_string()));
+}
+
+#[test]
+fn test_execute_select_where_arithmetic() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let rows = select_rows(&mut db, "select * from users where id * 2 % 7 = 0");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values[0], Value::Integer(42));
+}
+
+#[test]
+fn test_execute_select_where_no_matches() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let rows = select_rows(&mut db, "select * from users where id > 1699 or null");
+    assert_eq!(rows.len(), 0);
+}
+
+#[test]
+fn test_execute_select_where_unknown_column() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let statement = prepare("select * from users where age > 3").unwrap();
+    assert_eq!(
+        execute_statement(statement, &mut db),
+        Err(Error::Eval(EvalError::UnknownColumn("age".to_string())))
+    );
+}
+
+// Testing that a select returns only the columns and expressions it lists, named and typed.
+#[test]
+fn test_execute_select_projection() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = match run(
+        &mut db,
+        "select username, id * 2 as doubled, id / 2.0, null from users where id < 100",
+    ) {
+        Ok(ExecuteResult::Success(Some(result))) => result,
+        other => panic!("Expected Success with rows, got {:?}", other),
+    };
+    let columns: Vec<(&str, Option<DataType>)> = result
+        .columns
+        .iter()
+        .map(|column| (column.name.as_str(), column.data_type))
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            ("username", Some(DataType::Text)),
+            ("doubled", Some(DataType::Integer)),
+            ("id / 2.0", Some(DataType::Real)),
+            ("NULL", None),
+        ]
+    );
+    assert_eq!(result.column_index("doubled"), Some(1));
+    assert_eq!(
+        result.rows,
+        vec![
+            Row::new(vec![
+                Value::Text("rosh".to_string()),
+                Value::Integer(26),
+                Value::Real(6.5),
+                Value::Null,
+            ]),
+            Row::new(vec![
+                Value::Text("stefan".to_string()),
+                Value::Integer(84),
+                Value::Real(21.0),
+                Value::Null,
+            ]),
+        ]
+    );
+
+    // `*` can sit alongside other columns.
+    let rows = select_rows(&mut db, "select id, * from users where id = 42");
+    assert_eq!(
+        rows,
+        vec![Row::new(vec![
+            Value::Integer(42),
+            Value::Integer(42),
+            Value::Text("stefan".to_string()),
+            Value::Text("stefp@sigma.com".to_string()),
+        ])]
+    );
+}
+
+// Testing that a column list naming an unknown column fails even when no rows match.
+#[test]
+fn test_execute_select_projection_unknown_column() {
+    let mut db = users_db();
+    assert_eq!(
+        run(&mut db, "select id, age from users"),
+        Err(Error::Eval(EvalError::UnknownColumn("age".to_string())))
+    );
+}
+
+/// Helper method to create a table of scores, some of them missing.
+fn scores_db() -> Database {
+    let mut db = Database::new();
+    do_sql_cmd(
+        &mut db,
+        "create table scores (id integer primary key, name text, score integer)",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into scores values (1, 'ann', 30), (2, 'bob', null), (3, 'cat', 10), \
+         (4, 'dan', 30), (5, 'eve', null), (6, 'fay', 20)",
+    );
+    db
+}
+
+/// Helper method to pick out the id each row starts with.
+fn first_ids(rows: &[Row]) -> Vec<i64> {
+    rows.iter()
+        .map(|row| match row.values[0] {
+            Value::Integer(id) => id,
+            ref other => panic!("Expected an integer id, got {:?}", other),
+        })
+        .collect()
+}
+
+// Testing ORDER BY over several keys, in either direction, with NULLs placed as asked.
+#[test]
+fn test_execute_select_order_by() {
+    let mut db = scores_db();
+    let ordered = |db: &mut Database, order: &str| {
+        first_ids(&select_rows(
+            db,
+            &format!("select id from scores order by {}", order),
+        ))
+    };
+
+    // NULLs sort as the smallest value unless told otherwise, and ties keep their key order.
+    assert_eq!(ordered(&mut db, "score"), vec![2, 5, 3, 6, 1, 4]);
+    assert_eq!(ordered(&mut db, "score desc"), vec![1, 4, 6, 3, 2, 5]);
+    assert_eq!(ordered(&mut db, "score nulls last"), vec![3, 6, 1, 4, 2, 5]);
+    assert_eq!(
+        ordered(&mut db, "score desc nulls first"),
+        vec![2, 5, 1, 4, 6, 3]
+    );
+    assert_eq!(
+        ordered(&mut db, "score desc, id desc"),
+        vec![4, 1, 6, 3, 5, 2]
+    );
+    assert_eq!(
+        ordered(&mut db, "score is null, name desc"),
+        vec![6, 4, 3, 1, 5, 2]
+    );
+
+    // Keys can be any expression, including ones over columns the result leaves out.
+    assert_eq!(ordered(&mut db, "id % 3, id desc"), vec![6, 3, 4, 1, 5, 2]);
+    assert_eq!(
+        first_ids(&select_rows(
+            &mut db,
+            "select id, score * -1 as negated from scores where score > 10 order by negated, id"
+        )),
+        vec![1, 4, 6]
+    );
+}
+
+// Testing that ORDER BY a number sorts on that column of the result, and refuses numbers that
+// aren't the position of one.
+#[test]
+fn test_execute_select_order_by_position() {
+    let mut db = scores_db();
+    assert_eq!(
+        first_ids(&select_rows(
+            &mut db,
+            "select id, score from scores order by 2 desc, 1"
+        )),
+        vec![1, 4, 6, 3, 2, 5]
+    );
+    assert_eq!(
+        first_ids(&select_rows(
+            &mut db,
+            "select sum(id) from scores group by id % 2 order by 1"
+        )),
+        vec![9, 12]
+    );
+    assert_eq!(
+        first_ids(&select_rows(
+            &mut db,
+            "select sum(id) from scores group by id % 2 order by 1 desc"
+        )),
+        vec![12, 9]
+    );
+
+    for position in ["0", "3", "-1"] {
+        assert_eq!(
+            run(
+                &mut db,
+                &format!("select id, score from scores order by {}", position)
+            ),
+            Err(Error::Eval(EvalError::InvalidOrderBy(format!(
+                "ORDER BY position {} is not between 1 and 2",
+                position
+            ))))
+        );
+    }
+}
+
+// Testing that ORDER BY refuses keys that refer to columns that don't exist.
+#[test]
+fn test_execute_select_order_by_unknown_column() {
+    let mut db = scores_db();
+    assert_eq!(
+        run(&mut db, "select id from scores where id > 100 order by age"),
+        Err(Error::Eval(EvalError::UnknownColumn("age".to_string())))
+    );
+}
+
+// Testing that sorts bigger than their memory budget spill to disk and still come out in order.
+#[test]
+fn test_execute_select_order_by_spills() {
+    let mut db = Database::new();
+    do_sql_cmd(
+        &mut db,
+        "create table numbers (id integer primary key, label text, bucket integer)",
+    );
+    for id in 0..300 {
+        do_sql_cmd(
+            &mut db,
+            &format!(
+                "insert into numbers values ({}, 'number {}', {})",
+                id,
+                id,
+                (id * 7) % 13
+            ),
+        );
+    }
+
+    let mut expected: Vec<i64> = (0..300).collect();
+    expected.sort_by_key(|id| (-((id * 7) % 13), format!("number {}", id)));
+
+    let query = "select id from numbers order by bucket desc, label";
+    let in_memory = select_rows(&mut db, query);
+    assert_eq!(first_ids(&in_memory), expected);
+    db.set_sort_memory(1024);
+    assert_eq!(select_rows(&mut db, query), in_memory);
+}
+
+// Testing LIMIT and OFFSET, with and without a sort.
+#[test]
+fn test_execute_select_limit_offset() {
+    let mut db = scores_db();
+    let ids = |db: &mut Database, cmd: &str| first_ids(&select_rows(db, cmd));
+
+    assert_eq!(ids(&mut db, "select id from scores limit 2"), vec![1, 2]);
+    assert_eq!(
+        ids(&mut db, "select id from scores limit 2 offset 3"),
+        vec![4, 5]
+    );
+    assert_eq!(
+        ids(&mut db, "select id from scores limit 10 offset 4"),
+        vec![5, 6]
+    );
+    assert_eq!(
+        ids(&mut db, "select id from scores limit 0"),
+        Vec::<i64>::new()
+    );
+    assert_eq!(
+        ids(&mut db, "select id from scores limit 3 offset 9"),
+        Vec::<i64>::new()
+    );
+    assert_eq!(
+        ids(&mut db, "select id from scores limit null offset 5"),
+        vec![6]
+    );
+
+    // The page is taken after the WHERE clause and the sort.
+    assert_eq!(
+        ids(
+            &mut db,
+            "select id from scores where score is not null limit 2 offset 1"
+        ),
+        vec![3, 4]
+    );
+    assert_eq!(
+        ids(
+            &mut db,
+            "select id from scores order by score desc nulls last, id limit 3"
+        ),
+        vec![1, 4, 6]
+    );
+    assert_eq!(
+        ids(
+            &mut db,
+            "select id from scores order by name desc limit 2 offset 2"
+        ),
+        vec![4, 3]
+    );
+}
+
+// Testing that LIMIT and OFFSET have to be numbers of rows.
+#[test]
+fn test_execute_select_invalid_limit() {
+    let mut db = scores_db();
+    for cmd in [
+        "select id from scores limit -1",
+        "select id from scores limit 'ten'",
+        "select id from scores limit 1 offset 1.5",
+    ] {
+        assert!(
+            matches!(
+                run(&mut db, cmd),
+                Err(Error::Eval(EvalError::InvalidLimit(_)))
+            ),
+            "{}",
+            cmd
+        );
+    }
+    assert_eq!(
+        run(&mut db, "select id from scores limit id"),
+        Err(Error::Eval(EvalError::UnknownColumn("id".to_string())))
+    );
+}
+
+// Testing keyset pagination: each page starts after the last key of the one before.
+#[test]
+fn test_execute_select_keyset_pagination() {
+    let mut db = Database::new();
+    do_sql_cmd(
+        &mut db,
+        "create table users (id integer primary key, username text, email text)",
+    );
+    // Keys are inserted out of order so the pages only come out right if they follow the key.
+    for i in 0..100 {
+        let id = (i * 37) % 100 + 1;
+        do_sql_cmd(
+            &mut db,
+            &format!("insert into users values ({}, 'user{}', null)", id, id),
+        );
+    }
+
+    let mut pages = Vec::new();
+    let mut last = 0;
+    loop {
+        let page = first_ids(&select_rows(
+            &mut db,
+            &format!(
+                "select id from users where id > {} order by id limit 30",
+                last
+            ),
+        ));
+        match page.last() {
+            Some(&id) => last = id,
+            None => break,
+        }
+        pages.push(page);
+    }
+    assert_eq!(
+        pages.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![30, 30, 30, 10]
+    );
+    assert_eq!(pages.concat(), (1..=100).collect::<Vec<i64>>());
+
+    // Going the other way round needs a sort, but gives the same pages backwards.
+    let page = first_ids(&select_rows(
+        &mut db,
+        "select id from users where id < 71 order by id desc limit 30",
+    ));
+    assert_eq!(page, (41..=70).rev().collect::<Vec<i64>>());
+}
+
+/// Helper method to create a table of users that know which domain their email is on.
+fn domains_db() -> Database {
+    let mut db = Database::new();
+    do_sql_cmd(
+        &mut db,
+        "create table accounts (id integer primary key, email text, domain text, age integer)",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into accounts values \
+         (1, 'ann@x.com', 'x.com', 30), (2, 'bob@y.org', 'y.org', 25), \
+         (3, 'cat@x.com', 'x.com', null), (4, 'ann@x.com', 'x.com', 41), \
+         (5, 'dan@z.net', 'z.net', 19), (6, 'eve@y.org', 'y.org', 33)",
+    );
+    db
+}
+
+// Testing COUNT, SUM, AVG, MIN and MAX over whole tables and over groups.
+#[test]
+fn test_execute_select_group_by() {
+    let mut db = domains_db();
+
+    let result = match run(
+        &mut db,
+        "select domain, count(*) as users, count(distinct email), sum(age), avg(age), \
+         min(age), max(email) from accounts group by domain",
+    ) {
+        Ok(ExecuteResult::Success(Some(result))) => result,
+        other => panic!("Expected Success with rows, got {:?}", other),
+    };
+    let columns: Vec<(&str, Option<DataType>)> = result
+        .columns
+        .iter()
+        .map(|column| (column.name.as_str(), column.data_type))
+        .collect();
+    assert_eq!(
+        columns,
+        vec![
+            ("domain", Some(DataType::Text)),
+            ("users", Some(DataType::Integer)),
+            ("COUNT(DISTINCT email)", Some(DataType::Integer)),
+            ("SUM(age)", Some(DataType::Integer)),
+            ("AVG(age)", Some(DataType::Real)),
+            ("MIN(age)", Some(DataType::Integer)),
+            ("MAX(email)", Some(DataType::Text)),
+        ]
+    );
+    let text = |text: &str| Value::Text(text.to_string());
+    // Groups come back in the order they were first seen.
+    assert_eq!(
+        result.rows,
+        vec![
+            Row::new(vec![
+                text("x.com"),
+                Value::Integer(3),
+                Value::Integer(2),
+                Value::Integer(71),
+                Value::Real(35.5),
+                Value::Integer(30),
+                text("cat@x.com"),
+            ]),
+            Row::new(vec![
+                text("y.org"),
+                Value::Integer(2),
+                Value::Integer(2),
+                Value::Integer(58),
+                Value::Real(29.0),
+                Value::Integer(25),
+                text("eve@y.org"),
+            ]),
+            Row::new(vec![
+                text("z.net"),
+                Value::Integer(1),
+                Value::Integer(1),
+                Value::Integer(19),
+                Value::Real(19.0),
+                Value::Integer(19),
+                text("dan@z.net"),
+            ]),
+        ]
+    );
+
+    // Without GROUP BY the whole table is one group, even when no rows match.
+    assert_eq!(
+        select_rows(
+            &mut db,
+            "select count(*), max(age) - min(age) from accounts"
+        ),
+        vec![Row::new(vec![Value::Integer(6), Value::Integer(22)])]
+    );
+    assert_eq!(
+        select_rows(
+            &mut db,
+            "select count(*), sum(age) from accounts where id > 100"
+        ),
+        vec![Row::new(vec![Value::Integer(0), Value::Null])]
+    );
+    assert_eq!(
+        select_rows(
+            &mut db,
+            "select domain, count(*) from accounts where id > 100 group by domain"
+        ),
+        Vec::<Row>::new()
+    );
+}
+
+// Testing HAVING, and sorting and paging the groups it lets through.
+#[test]
+fn test_execute_select_having() {
+    let mut db = domains_db();
+    let text = |text: &str| Value::Text(text.to_string());
+
+    assert_eq!(
+        select_rows(
+            &mut db,
+            "select domain, count(*) as users from accounts group by domain \
+             having count(*) > 1 order by users, domain desc"
+        ),
+        vec![
+            Row::new(vec![text("y.org"), Value::Integer(2)]),
+            Row::new(vec![text("x.com"), Value::Integer(3)]),
+        ]
+    );
+    assert_eq!(
+        select_rows(
+            &mut db,
+            "select domain from accounts where age is not null group by domain \
+             having min(age) < 30 order by sum(age) desc limit 1"
+        ),
+        vec![Row::new(vec![text("y.org")])]
+    );
+    assert_eq!(
+        select_rows(
+            &mut db,
+            "select age / 10 as decade, count(*) from accounts where age is not null \
+             group by age / 10 order by decade"
+        ),
+        vec![
+            Row::new(vec![Value::Integer(1), Value::Integer(1)]),
+            Row::new(vec![Value::Integer(2), Value::Integer(1)]),
+            Row::new(vec![Value::Integer(3), Value::Integer(2)]),
+            Row::new(vec![Value::Integer(4), Value::Integer(1)]),
+        ]
+    );
+
+    // Without GROUP BY, HAVING keeps or drops the whole table as one group.
+    assert_eq!(
+        select_rows(&mut db, "select count(*) from accounts having count(*) > 1"),
+        vec![Row::new(vec![Value::Integer(6)])]
+    );
+    assert!(select_rows(&mut db, "select count(*) from accounts having count(*) > 6").is_empty());
+    assert_eq!(
+        select_rows(&mut db, "select id from accounts having id = 1"),
+        vec![Row::new(vec![Value::Integer(1)])]
+    );
+}
+
+// Testing that columns outside of GROUP BY and aggregates read the first row of their group.
+#[test]
+fn test_execute_select_bare_columns() {
+    let mut db = domains_db();
+    let text = |text: &str| Value::Text(text.to_string());
+
+    assert_eq!(
+        select_rows(
+            &mut db,
+            "select domain, id, email, count(*) from accounts group by domain"
+        ),
+        vec![
+            Row::new(vec![
+                text("x.com"),
+                Value::Integer(1),
+                text("ann@x.com"),
+                Value::Integer(3)
+            ]),
+            Row::new(vec![
+                text("y.org"),
+                Value::Integer(2),
+                text("bob@y.org"),
+                Value::Integer(2)
+            ]),
+            Row::new(vec![
+                text("z.net"),
+                Value::Integer(5),
+                text("dan@z.net"),
+                Value::Integer(1)
+            ]),
+        ]
+    );
+    assert_eq!(
+        select_rows(&mut db, "select id, max(age) from accounts where id > 2"),
+        vec![Row::new(vec![Value::Integer(3), Value::Integer(41)])]
+    );
+}
+
+// Testing that aggregates are refused where they can't be worked out.
+#[test]
+fn test_execute_select_misused_aggregate() {
+    let mut db = domains_db();
+    let misused = |aggregate: &str| {
+        Err(Error::Eval(EvalError::MisusedAggregate(
+            aggregate.to_string(),
+        )))
+    };
+
+    assert_eq!(
+        run(&mut db, "select id from accounts where count(*) > 1"),
+        misused("COUNT(*)")
+    );
+    assert_eq!(
+        run(&mut db, "select count(*) from accounts group by max(age)"),
+        misused("MAX(age)")
+    );
+    assert_eq!(
+        run(&mut db, "select avg(sum(age)) from accounts"),
+        misused("AVG(SUM(age))")
+    );
+    assert_eq!(
+        run(&mut db, "update accounts set age = max(age)"),
+        misused("MAX(age)")
+    );
+    assert_eq!(
+        run(
+            &mut db,
+            "select count(*) from accounts group by domain having max(height) > 1"
+        ),
+        Err(Error::Eval(EvalError::UnknownColumn("height".to_string())))
+    );
+    assert!(matches!(
+        run(&mut db, "select sum(email) from accounts"),
+        Err(Error::Eval(EvalError::TypeMismatch(_)))
+    ));
+}
+
+/// Helper method to create a table of users alongside a table of the orders they placed. One
+/// user has no orders and one order has no user.
+fn orders_db() -> Database {
+    let mut db = Database::new();
+    do_sql_cmd(
+        &mut db,
+        "create table users (id integer primary key, name text)",
+    );
+    do_sql_cmd(
+        &mut db,
+        "create table orders (id integer primary key, user_id integer, total real)",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (1, 'ann'), (2, 'bob'), (3, 'cat')",
+    );
+    do_sql_cmd(
+        &mut db,
+        "insert into orders values (10, 1, 5.0), (11, 3, 7.5), (12, 1, 2.5), (13, 9, 1.0)",
+    );
+    db
+}
+
+/// Helper method to run a query and get back the names of its columns and its rows.
+fn query(db: &mut Database, cmd: &str) -> (Vec<String>, Vec<Vec<Value>>) {
+    match run(db, cmd) {
+        Ok(ExecuteResult::Success(Some(ResultSet { columns, rows }))) => (
+            columns.into_iter().map(|column| column.name).collect(),
+            rows.into_iter().map(|row| row.values).collect(),
+        ),
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+}
+
+// Testing every kind of join, with table aliases and qualified column names.
+#[test]
+fn test_execute_select_join() {
+    let mut db = orders_db();
+    let (int, text) = (Value::Integer, |text: &str| Value::Text(text.to_string()));
+
+    let (columns, rows) = query(
+        &mut db,
+        "select u.name, o.id, total from users u join orders o on u.id = o.user_id",
+    );
+    assert_eq!(columns, vec!["name", "id", "total"]);
+    assert_eq!(
+        rows,
+        vec![
+            vec![text("ann"), int(10), Value::Real(5.0)],
+            vec![text("ann"), int(12), Value::Real(2.5)],
+            vec![text("cat"), int(11), Value::Real(7.5)],
+        ]
+    );
+
+    let (_, rows) = query(
+        &mut db,
+        "select users.id, orders.id from users left join orders on users.id = orders.user_id",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec![int(1), int(10)],
+            vec![int(1), int(12)],
+            vec![int(2), Value::Null],
+            vec![int(3), int(11)],
+        ]
+    );
+
+    let (_, rows) = query(
+        &mut db,
+        "select u.id, o.id from users u right outer join orders o on u.id = o.user_id",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec![int(1), int(10)],
+            vec![int(1), int(12)],
+            vec![int(3), int(11)],
+            vec![Value::Null, int(13)],
+        ]
+    );
+
+    // The whole of both tables, in order of the joined columns.
+    let (columns, rows) = query(
+        &mut db,
+        "select * from users u full join orders o on u.id = o.user_id \
+         order by o.id nulls first",
+    );
+    assert_eq!(columns, vec!["id", "name", "id", "user_id", "total"]);
+    assert_eq!(
+        rows.iter()
+            .map(|row| (row[0].clone(), row[2].clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (int(2), Value::Null),
+            (int(1), int(10)),
+            (int(3), int(11)),
+            (int(1), int(12)),
+            (Value::Null, int(13)),
+        ]
+    );
+}
+
+// Testing joins feeding into WHERE, GROUP BY, ORDER BY and LIMIT, and a table joined with itself.
+#[test]
+fn test_execute_select_join_clauses() {
+    let mut db = orders_db();
+    let (int, text) = (Value::Integer, |text: &str| Value::Text(text.to_string()));
+
+    let (columns, rows) = query(
+        &mut db,
+        "select name, count(o.id) as orders, sum(total) from users u \
+         left join orders o on o.user_id = u.id and o.total > 2 where name != 'cat' \
+         group by u.id order by orders desc, name limit 5",
+    );
+    assert_eq!(columns, vec!["name", "orders", "SUM(total)"]);
+    assert_eq!(
+        rows,
+        vec![
+            vec![text("ann"), int(2), Value::Real(7.5)],
+            vec![text("bob"), int(0), Value::Null],
+        ]
+    );
+
+    // ON conditions that aren't equalities pair up rows one by one.
+    let (_, rows) = query(
+        &mut db,
+        "select a.id, b.id from users a join users b on a.id < b.id order by a.id, b.id",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec![int(1), int(2)],
+            vec![int(1), int(3)],
+            vec![int(2), int(3)],
+        ]
+    );
+
+    let (columns, _) = query(
+        &mut db,
+        "select o.*, u.name from users u join orders o on TRUE",
+    );
+    assert_eq!(columns, vec!["id", "user_id", "total", "name"]);
+    let (_, rows) = query(&mut db, "select users.name from users where users.id = 2");
+    assert_eq!(rows, vec![vec![text("bob")]]);
+}
+
+// Testing that columns and tables a join refers to have to name exactly one thing.
+#[test]
+fn test_execute_select_join_errors() {
+    let mut db = orders_db();
+    let eval = |err: EvalError| Err(Error::Eval(err));
+
+    assert_eq!(
+        run(
+            &mut db,
+            "select id from users join orders on users.id = user_id"
+        ),
+        eval(EvalError::AmbiguousColumn("id".to_string()))
+    );
+    assert_eq!(
+        run(&mut db, "select u.id from users join orders on TRUE"),
+        eval(EvalError::UnknownColumn("u.id".to_string()))
+    );
+    assert_eq!(
+        run(&mut db, "select * from users u join orders u on TRUE"),
+        eval(EvalError::DuplicateTable("u".to_string()))
+    );
+    assert_eq!(
+        run(&mut db, "select * from users join users on TRUE"),
+        eval(EvalError::DuplicateTable("users".to_string()))
+    );
+    assert_eq!(
+        run(&mut db, "select * from users join items on TRUE"),
+        Err(Error::Schema(SchemaError::UnknownTable(
+            "items".to_string()
+        )))
+    );
+    assert_eq!(
+        run(&mut db, "select x.* from users"),
+        Err(Error::Schema(SchemaError::UnknownTable("x".to_string())))
+    );
+    // A join can only look at the tables joined before it.
+    assert_eq!(
+        run(
+            &mut db,
+            "select * from users a join users b on a.id = c.id join users c on TRUE"
+        ),
+        eval(EvalError::UnknownColumn("c.id".to_string()))
+    );
+    assert_eq!(
+        run(&mut db, "select * from users join orders on count(*) > 1"),
+        eval(EvalError::MisusedAggregate("COUNT(*)".to_string()))
+    );
+}
+
+#[test]
+fn test_execute_select_where_type_mismatch() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let statement = prepare("select * from users where username > 3").unwrap();
+    assert!(matches!(
+        execute_statement(statement, &mut db),
+        Err(Error::Eval(EvalError::TypeMismatch(_)))
+    ));
+}
+
+/// Helper method to run a statement and get its result back.
+fn run(db: &mut Database, cmd: &str) -> Result<ExecuteResult, Error> {
+    execute_statement(prepare(cmd)?, db)
+}
+
+#[test]
+fn test_execute_update_where() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(
+        &mut db,
+        "update users set email = 'stefan@sigma.com' where id = 42",
+    );
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(1)));
+    assert_eq!(
+        users_rows(&mut db)[1].values[2],
+        Value::Text("stefan@sigma.com".to_string())
+    );
+    assert_eq!(
+        users_rows(&mut db)[0].values[2],
+        Value::Text("kakapio@gmail.com".to_string())
+    );
+    assert_eq!(
+        users_rows(&mut db)[2].values[2],
+        Value::Text("penutterbutter@yahoo.com".to_string())
+    );
+}
+
+#[test]
+fn test_execute_update_all_rows_uses_old_values() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(
+        &mut db,
+        "UPDATE users SET id = id + 1, username = username || '_' || id",
+    );
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(3)));
+    assert_eq!(users_rows(&mut db)[0].values[0], Value::Integer(14));
+    assert_eq!(
+        users_rows(&mut db)[0].values[1],
+        Value::Text("rosh_13".to_string())
+    );
+    assert_eq!(users_rows(&mut db)[2].values[0], Value::Integer(1700));
+}
+
+#[test]
+fn test_execute_update_no_matches() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "update users set username = 'x' where id = 7");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(0)));
+    assert_eq!(
+        users_rows(&mut db)[0].values[1],
+        Value::Text("rosh".to_string())
+    );
+}
+
+#[test]
+fn test_execute_update_type_mismatch_changes_nothing() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "update users set id = 'abc' where id > 20");
+    assert!(matches!(
+        result,
+        Err(Error::Schema(SchemaError::TypeMismatch { .. }))
+    ));
+    assert_eq!(users_rows(&mut db)[1].values[0], Value::Integer(42));
+    assert_eq!(users_rows(&mut db)[2].values[0], Value::Integer(1699));
+}
+
+#[test]
+fn test_execute_update_overflow() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(
+        &mut db,
+        "update users set id = id * 9223372036854775807 where id = 13",
+    );
+    assert_eq!(result, Err(Error::Eval(EvalError::Overflow)));
+    assert_eq!(users_rows(&mut db)[0].values[0], Value::Integer(13));
+}
+
+#[test]
+fn test_execute_update_unknown_column() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "update users set age = 3");
+    assert_eq!(
+        result,
+        Err(Error::Eval(EvalError::UnknownColumn("age".to_string())))
+    );
+}
+
+#[test]
+fn test_execute_delete_where() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "delete from users where id = 42");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(1)));
+    assert_eq!(
+        users_rows(&mut db),
+        vec![
+            user(13, "rosh", "kakapio@gmail.com"),
+            user(1699, "sniper_penut", "penutterbutter@yahoo.com")
+        ]
+    );
+}
+
+#[test]
+fn test_execute_delete_all_rows() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "DELETE FROM users");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(3)));
+    assert!(users_rows(&mut db).is_empty());
+}
+
+#[test]
+fn test_execute_delete_no_matches() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "delete from users where email = 'nobody@x.com'");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(0)));
+    assert_eq!(users_rows(&mut db).len(), 3);
+}
+
+#[test]
+fn test_execute_delete_error_removes_nothing() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    // The last row divides by zero after earlier rows already matched.
+    let result = run(&mut db, "delete from users where 100 / (1699 - id) >= 0");
+    assert_eq!(result, Err(Error::Eval(EvalError::DivisionByZero)));
+    assert_eq!(users_rows(&mut db).len(), 3);
+}
+
+#[test]
+fn test_execute_delete_then_insert() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    run(&mut db, "delete from users where username = 'rosh'").unwrap();
+    do_sql_cmd(
+        &mut db,
+        "insert into users values (13, 'rosh', 'rosh@new.com')",
+    );
+
+    let rows = select_rows(&mut db, "select * from users where id = 13");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].values[2], Value::Text("rosh@new.com".to_string()));
+}
+
+#[test]
+fn test_execute_create_table() {
+    let mut db = Database::new();
+    let result = run(
+        &mut db,
+        "create table posts (id integer not null, title text, score real)",
+    );
+    assert_eq!(result, Ok(ExecuteResult::Success(None)));
+
+    let table = db.table("posts").unwrap();
+    assert_eq!(table.name, "posts");
+    assert_eq!(table.schema.columns.len(), 3);
+    assert!(table.schema.columns[0].not_null);
+}
+
+#[test]
+fn test_execute_create_table_twice() {
+    let mut db = users_db();
+    let result = run(&mut db, "create table users (id integer)");
+    assert_eq!(
+        result,
+        Err(Error::Schema(SchemaError::TableExists("users".to_string())))
+    );
+    // The existing table is left as it was.
+    assert_eq!(db.table("users").unwrap().schema.columns.len(), 3);
+}
+
+#[test]
+fn test_execute_create_table_duplicate_column() {
+    let mut db = Database::new();
+    let result = run(&mut db, "create table posts (id integer, id text)");
+    assert_eq!(
+        result,
+        Err(Error::Schema(SchemaError::DuplicateColumn(
+            "id".to_string()
+        )))
+    );
+}
+
+#[test]
+fn test_execute_insert_column_list() {
+    let mut db = users_db();
+    let result = run(
+        &mut db,
+        "insert into users (email, id) values ('a@x.com', 1), ('b@x.com', 2)",
+    );
+    assert_eq!(result, Ok(ExecuteResult::Success(None)));
+    assert_eq!(
+        users_rows(&mut db)[1],
+        Row::new(vec![
+            Value::Integer(2),
+            Value::Null,
+            Value::Text("b@x.com".to_string())
+        ])
+    );
+}
+
+#[test]
+fn test_execute_insert_expressions() {
+    let mut db = users_db();
+    run(
+        &mut db,
+        "insert into users values (6 * 7, 'ste' || 'fan', 'stefp@sigma.com')",
+    )
+    .unwrap();
+    assert_eq!(
+        users_rows(&mut db),
+        vec![user(42, "stefan", "stefp@sigma.com")]
+    );
+}
+
+#[test]
+fn test_execute_insert_type_mismatch() {
+    let mut db = users_db();
+    let result = run(
+        &mut db,
+        "insert into users values (1, 'a', 'a@x.com'), ('two', 'b', 'b@x.com')",
+    );
+    assert_eq!(
+        result,
+        Err(Error::Schema(SchemaError::TypeMismatch {
+            column: "id".to_string(),
+            expected: DataType::Integer,
+            found: "TEXT"
+        }))
+    );
+    // Neither row goes in when one of them is bad.
+    assert!(users_rows(&mut db).is_empty());
+}
+
+#[test]
+fn test_execute_insert_invalid_id() {
+    let mut db = users_db();
+    let result = run(
+        &mut db,
+        "insert into users values (abc, 'monkeylover', 'ape@gmail.com')",
+    );
+    assert_eq!(
+        result,
+        Err(Error::Eval(EvalError::UnknownColumn("abc".to_string())))
+    );
+}
+
+#[test]
+fn test_execute_insert_wrong_column_count() {
+    let mut db = users_db();
+    let result = run(&mut db, "insert into users values (1, 'a')");
+    assert_eq!(
+        result,
+        Err(Error::Schema(SchemaError::ColumnCount {
+            expected: 3,
+            found: 2
+        }))
+    );
+}
+
+#[test]
+fn test_execute_insert_unknown_column() {
+    let mut db = users_db();
+    let result = run(&mut db, "insert into users (id, age) values (1, 2)");
+    assert_eq!(
+        result,
+        Err(Error::Eval(EvalError::UnknownColumn("age".to_string())))
+    );
+}
+
+#[test]
+fn test_execute_insert_not_null() {
+    let mut db = Database::new();
+    run(
+        &mut db,
+        "create table posts (id integer not null, title text)",
+    )
+    .unwrap();
+
+    let result = run(&mut db, "insert into posts (title) values ('hello')");
+    assert_eq!(
+        result,
+        Err(Error::Schema(SchemaError::NotNull("id".to_string())))
+    );
+
+    let result = run(&mut db, "update posts set id = null");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(0)));
+}
+
+#[test]
+fn test_execute_custom_schema() {
+    let mut db = Database::new();
+    run(
+        &mut db,
+        "create table scores (player text, points real, active boolean)",
+    )
+    .unwrap();
+    run(
+        &mut db,
+        "insert into scores values ('rosh', 10, true), ('stefan', 7.5, false)",
+    )
+    .unwrap();
+
+    let rows = select_rows(&mut db, "select * from scores where active or points > 8");
+    assert_eq!(
+        rows,
+        vec![Row::new(vec![
+            Value::Text("rosh".to_string()),
+            Value::Real(10.0),
+            Value::Boolean(true)
+        ])]
+    );
+}
+
+#[test]
+fn test_execute_multiple_tables() {
+    let mut db = users_db();
+    run(
+        &mut db,
+        "create table posts (id integer, author integer, title text)",
+    )
+    .unwrap();
+    seed_users(&mut db);
+    run(
+        &mut db,
+        "insert into posts values (1, 13, 'hello'), (2, 42, 'world')",
+    )
+    .unwrap();
+
+    assert_eq!(db.table_names(), vec!["posts", "users"]);
+    assert_eq!(users_rows(&mut db).len(), 3);
+
+    let rows = select_rows(&mut db, "select * from posts where author = 42");
+    assert_eq!(
+        rows,
+        vec![Row::new(vec![
+            Value::Integer(2),
+            Value::Integer(42),
+            Value::Text("world".to_string())
+        ])]
+    );
+
+    // Statements only touch the table they name.
+    let result = run(&mut db, "delete from posts");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(2)));
+    assert_eq!(users_rows(&mut db).len(), 3);
+}
+
+#[test]
+fn test_execute_unknown_table() {
+    let mut db = users_db();
+    let unknown = Err(Error::Schema(SchemaError::UnknownTable(
+        "people".to_string(),
+    )));
+
+    assert_eq!(run(&mut db, "select * from people"), unknown);
+    assert_eq!(run(&mut db, "insert into people values (1)"), unknown);
+    assert_eq!(run(&mut db, "update people set id = 1"), unknown);
+    assert_eq!(run(&mut db, "delete from people"), unknown);
+    assert_eq!(run(&mut db, "drop table people"), unknown);
+}
+
+#[test]
+fn test_execute_drop_table() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "drop table users");
+    assert_eq!(result, Ok(ExecuteResult::Success(None)));
+    assert!(db.table("users").is_none());
+    assert!(db.table_names().is_empty());
+
+    // The name is free to be used again, and the new table starts out empty.
+    run(&mut db, "create table users (id integer, name text)").unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert!(rows.is_empty());
+    assert_eq!(db.table("users").unwrap().schema.columns.len(), 2);
+}
+
+/// Helper method to build the error for a second users row with the same id.
+fn duplicate_id(id: i64) -> Result<ExecuteResult, Error> {
+    Err(Error::Constraint(ConstraintViolation::PrimaryKey {
+        table: "users".to_string(),
+        column: "id".to_string(),
+        key: id,
+    }))
+}
+
+/// Helper method to get the ids out of the rows of the users table.
+fn ids(rows: &[Row]) -> Vec<i64> {
+    rows.iter()
+        .map(|row| match row.values[0] {
+            Value::Integer(id) => id,
+            ref other => panic!("Expected an integer id, got {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn test_execute_select_in_key_order() {
+    let mut db = users_db();
+    run(
+        &mut db,
+        "insert into users values (1699, 'sniper_penut', 'penutterbutter@yahoo.com'), (13, 'rosh', 'kakapio@gmail.com'), (-5, 'neg', 'neg@x.com'), (42, 'stefan', 'stefp@sigma.com')",
+    ).unwrap();
+
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![-5, 13, 42, 1699]);
+}
+
+#[test]
+fn test_execute_select_by_primary_key() {
+    let mut db = users_db();
+    for id in (0..3000).rev() {
+        run(
+            &mut db,
+            &format!(
+                "insert into users values ({}, 'user{}', 'user{}@x.com')",
+                id, id, id
+            ),
+        )
+        .unwrap();
+    }
+
+    let rows = select_rows(&mut db, "select * from users where id = 1234");
+    assert_eq!(rows, vec![user(1234, "user1234", "user1234@x.com")]);
+
+    let rows = select_rows(&mut db, "select * from users where id >= 2990");
+    assert_eq!(ids(&rows), (2990..3000).collect::<Vec<_>>());
+
+    let rows = select_rows(
+        &mut db,
+        "select * from users where id > 100 and id < 105 and username != 'user103'",
+    );
+    assert_eq!(ids(&rows), vec![101, 102, 104]);
+
+    let rows = select_rows(&mut db, "select * from users where id = 5000");
+    assert!(rows.is_empty());
+}
+
+#[test]
+fn test_execute_primary_key_not_null() {
+    let mut db = users_db();
+    let result = run(
+        &mut db,
+        "insert into users (username, email) values ('rosh', 'kakapio@gmail.com')",
+    );
+    assert_eq!(
+        result,
+        Err(Error::Schema(SchemaError::NotNull("id".to_string())))
+    );
+
+    // A table built without going through the parser gets the same guarantee.
+    let mut db = Database::new();
+    let id = Column {
+        primary_key: true,
+        ..Column::new("id", DataType::Integer)
+    };
+    let create = CreateTable {
+        name: "p".to_string(),
+        columns: vec![id],
+    };
+    execute_statement(Statement::CreateTable(create), &mut db).unwrap();
+    assert_eq!(
+        run(&mut db, "insert into p values (null)"),
+        Err(Error::Schema(SchemaError::NotNull("id".to_string())))
+    );
+}
+
+#[test]
+fn test_execute_primary_key_must_be_integer() {
+    let mut db = Database::new();
+    let result = run(&mut db, "create table users (name text primary key)");
+    assert_eq!(
+        result,
+        Err(Error::Schema(SchemaError::PrimaryKeyType {
+            column: "name".to_string(),
+            found: DataType::Text
+        }))
+    );
+    assert!(db.table("users").is_none());
+}
+
+#[test]
+fn test_execute_update_primary_key_moves_row() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "update users set id = 7 where id = 1699");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(1)));
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![7, 13, 42]);
+
+    // Shifting every key at once doesn't trip over rows that haven't moved yet.
+    let result = run(&mut db, "update users set id = id + 29");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(3)));
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![36, 42, 71]);
+    assert_eq!(rows[1], user(42, "rosh", "kakapio@gmail.com"));
+}
+
+#[test]
+fn test_execute_delete_by_primary_key_range() {
+    let mut db = users_db();
+    for id in 0..500 {
+        run(
+            &mut db,
+            &format!("insert into users values ({}, 'u', 'u@x.com')", id),
+        )
+        .unwrap();
+    }
+
+    let result = run(&mut db, "delete from users where id >= 100 and id < 400");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(300)));
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), (0..100).chain(400..500).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_execute_insert_duplicate_id_in_one_statement() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    // Neither a clash with an existing row nor one within the statement gets in, and a rejected
+    // statement inserts nothing at all.
+    let result = run(
+        &mut db,
+        "insert into users values (7, 'a', 'a@x.com'), (42, 'b', 'b@x.com')",
+    );
+    assert_eq!(result, duplicate_id(42));
+    let result = run(
+        &mut db,
+        "insert into users values (8, 'a', 'a@x.com'), (8, 'b', 'b@x.com')",
+    );
+    assert_eq!(result, duplicate_id(8));
+
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![13, 42, 1699]);
+}
+
+#[test]
+fn test_execute_update_into_existing_key() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    let result = run(&mut db, "update users set id = 42 where id = 13");
+    assert_eq!(result, duplicate_id(42));
+
+    // Two rows moving onto the same key clash with each other.
+    let result = run(&mut db, "update users set id = 5 where id < 100");
+    assert_eq!(result, duplicate_id(5));
+
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![13, 42, 1699]);
+    assert_eq!(rows[0], user(13, "rosh", "kakapio@gmail.com"));
+
+    // A row may move onto a key that another row is moving away from.
+    let result = run(&mut db, "update users set id = id + 29 where id < 100");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(2)));
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![42, 71, 1699]);
+}
+
+#[test]
+fn test_execute_duplicates_without_primary_key() {
+    let mut db = Database::new();
+    run(&mut db, "create table events (id integer, name text)").unwrap();
+    run(
+        &mut db,
+        "insert into events values (1, 'start'), (1, 'stop')",
+    )
+    .unwrap();
+    run(&mut db, "insert into events values (1, 'restart')").unwrap();
+
+    let rows = select_rows(&mut db, "select * from events where id = 1");
+    assert_eq!(rows.len(), 3);
+}
+
+#[test]
+fn test_execute_insert_table_row_limit() {
+    let mut db = users_db();
+    let limits = Limits {
+        max_rows: Some(3),
+        max_bytes: None,
+    };
+    db.set_table_limits("users", limits).unwrap();
+    run(
+        &mut db,
+        "insert into users values (1, 'a', 'a@x.com'), (2, 'b', 'b@x.com')",
+    )
+    .unwrap();
+
+    // A statement that doesn't fit is turned away whole.
+    let result = run(
+        &mut db,
+        "insert into users values (3, 'c', 'c@x.com'), (4, 'd', 'd@x.com')",
+    );
+    assert_eq!(
+        result,
+        Err(Error::TableFull(TableFull {
+            table: "users".to_string(),
+            scope: Scope::Table,
+            limit: Limit::Rows { max: 3, needed: 4 }
+        }))
+    );
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![1, 2]);
+
+    // Deleting rows makes room again.
+    run(&mut db, "insert into users values (3, 'c', 'c@x.com')").unwrap();
+    run(&mut db, "delete from users where id < 3").unwrap();
+    let result = run(
+        &mut db,
+        "insert into users values (4, 'd', 'd@x.com'), (5, 'e', 'e@x.com')",
+    );
+    assert_eq!(result, Ok(ExecuteResult::Success(None)));
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![3, 4, 5]);
+}
+
+#[test]
+fn test_execute_update_table_byte_limit() {
+    let mut db = users_db();
+    run(&mut db, "insert into users values (1, 'a', 'a@x.com')").unwrap();
+    let limits = Limits {
+        max_rows: None,
+        max_bytes: Some(64),
+    };
+    db.set_table_limits("users", limits).unwrap();
+
+    // Rows that grow past the limit are caught by an update just as by an insert.
+    let result = run(&mut db, "update users set email = email || email || email");
+    assert_eq!(result, Ok(ExecuteResult::RowsAffected(1)));
+    let result = run(&mut db, "update users set email = email || email || email");
+    match result {
+        Err(Error::TableFull(full)) => match full.limit {
+            Limit::Bytes { max, needed } => assert!(max == 64 && needed > 64),
+            limit => panic!("Expected a byte limit, got {:?}", limit),
+        },
+        result => panic!("Expected TableFull, got {:?}", result),
+    }
+    assert_eq!(
+        select_rows(&mut db, "select * from users"),
+        vec![user(1, "a", "a@x.coma@x.coma@x.com")]
+    );
+}
+
+#[test]
+fn test_execute_insert_database_limit() {
+    let mut db = users_db();
+    run(
+        &mut db,
+        "create table posts (id integer primary key, body text)",
+    )
+    .unwrap();
+    run(
+        &mut db,
+        "insert into posts values (1, 'hello'), (2, 'world')",
+    )
+    .unwrap();
+    run(&mut db, "insert into users values (1, 'a', 'a@x.com')").unwrap();
+    db.set_limits(Limits {
+        max_rows: Some(4),
+        max_bytes: None,
+    });
+
+    // Every table counts towards the limit on the database.
+    run(&mut db, "insert into users values (2, 'b', 'b@x.com')").unwrap();
+    let result = run(&mut db, "insert into posts values (3, 'again')");
+    assert_eq!(
+        result,
+        Err(Error::TableFull(TableFull {
+            table: "posts".to_string(),
+            scope: Scope::Database,
+            limit: Limit::Rows { max: 4, needed: 5 }
+        }))
+    );
+
+    // Dropping a table frees up everything it held.
+    run(&mut db, "drop table users").unwrap();
+    let result = run(&mut db, "insert into posts values (3, 'again')");
+    assert_eq!(result, Ok(ExecuteResult::Success(None)));
+}
+
+#[test]
+fn test_set_table_limits_unknown_table() {
+    let mut db = Database::new();
+    assert_eq!(
+        db.set_table_limits("users", Limits::default()),
+        Err(SchemaError::UnknownTable("users".to_string()))
+    );
+}
+
+#[test]
+fn test_transaction_rollback_restores_table() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    assert_eq!(run(&mut db, "begin"), Ok(ExecuteResult::Success(None)));
+    assert!(db.in_transaction());
+    run(&mut db, "insert into users values (7, 'new', 'new@x.com')").unwrap();
+    run(
+        &mut db,
+        "update users set username = 'changed' where id = 13",
+    )
+    .unwrap();
+    run(&mut db, "delete from users where id = 42").unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![7, 13, 1699]);
+
+    assert_eq!(run(&mut db, "rollback"), Ok(ExecuteResult::Success(None)));
+    assert!(!db.in_transaction());
+    assert_eq!(
+        users_rows(&mut db),
+        vec![
+            user(13, "rosh", "kakapio@gmail.com"),
+            user(42, "stefan", "stefp@sigma.com"),
+            user(1699, "sniper_penut", "penutterbutter@yahoo.com")
+        ]
+    );
+}
+
+#[test]
+fn test_transaction_commit_keeps_changes() {
+    let mut db = users_db();
+    run(&mut db, "begin transaction").unwrap();
+    for id in 0..200 {
+        run(
+            &mut db,
+            &format!("insert into users values ({}, 'u', 'u@x.com')", id),
+        )
+        .unwrap();
+    }
+    run(&mut db, "delete from users where id >= 100").unwrap();
+    assert_eq!(run(&mut db, "commit"), Ok(ExecuteResult::Success(None)));
+
+    // A later rollback only undoes what came after the commit.
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "delete from users").unwrap();
+    run(&mut db, "rollback").unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_transaction_rollback_tables() {
+    let mut db = users_db();
+    seed_users(&mut db);
+    run(&mut db, "create table posts (id integer, title text)").unwrap();
+    let limits = Limits {
+        max_rows: Some(3),
+        max_bytes: None,
+    };
+    db.set_table_limits("users", limits).unwrap();
+
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "drop table users").unwrap();
+    run(&mut db, "create table comments (id integer, body text)").unwrap();
+    run(&mut db, "insert into comments values (1, 'hi')").unwrap();
+    assert_eq!(db.table_names(), vec!["comments", "posts"]);
+    run(&mut db, "rollback").unwrap();
+
+    assert_eq!(db.table_names(), vec!["posts", "users"]);
+    assert_eq!(users_rows(&mut db).len(), 3);
+
+    // Limits set on a table outlive the rollback.
+    let result = run(&mut db, "insert into users values (1, 'a', 'a@x.com')");
+    assert!(matches!(result, Err(Error::TableFull(_))));
+}
+
+#[test]
+fn test_transaction_errors() {
+    let mut db = users_db();
+    assert_eq!(
+        run(&mut db, "commit"),
+        Err(Error::Transaction(TransactionError::NotActive))
+    );
+    assert_eq!(
+        run(&mut db, "rollback"),
+        Err(Error::Transaction(TransactionError::NotActive))
+    );
+
+    run(&mut db, "begin").unwrap();
+    assert_eq!(
+        run(&mut db, "begin"),
+        Err(Error::Transaction(TransactionError::AlreadyActive))
+    );
+    assert!(db.in_transaction());
+}
+
+#[test]
+fn test_transaction_library_api() {
+    let mut db = users_db();
+    db.begin().unwrap();
+    assert_eq!(db.begin(), Err(TransactionError::AlreadyActive));
+    seed_users(&mut db);
+    db.rollback();
+    assert!(users_rows(&mut db).is_empty());
+
+    db.begin().unwrap();
+    seed_users(&mut db);
+    db.commit().unwrap();
+    assert!(!db.in_transaction());
+    assert_eq!(users_rows(&mut db).len(), 3);
+}
+
+#[test]
+fn test_savepoint_rollback_to_keeps_earlier_work() {
+    let mut db = users_db();
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "insert into users values (1, 'a', 'a@x.com')").unwrap();
+    assert_eq!(
+        run(&mut db, "savepoint batch"),
+        Ok(ExecuteResult::Success(None))
+    );
+    run(&mut db, "insert into users values (2, 'b', 'b@x.com')").unwrap();
+    run(
+        &mut db,
+        "update users set username = 'changed' where id = 1",
+    )
+    .unwrap();
+
+    assert_eq!(
+        run(&mut db, "rollback to batch"),
+        Ok(ExecuteResult::Success(None))
+    );
+    assert_eq!(users_rows(&mut db), vec![user(1, "a", "a@x.com")]);
+
+    // The savepoint is still there to go back to after more work.
+    run(&mut db, "insert into users values (3, 'c', 'c@x.com')").unwrap();
+    run(&mut db, "rollback to savepoint batch").unwrap();
+    run(&mut db, "insert into users values (4, 'd', 'd@x.com')").unwrap();
+    run(&mut db, "commit").unwrap();
+
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![1, 4]);
+}
+
+#[test]
+fn test_savepoints_nest() {
+    let mut db = users_db();
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "insert into users values (1, 'a', 'a@x.com')").unwrap();
+    run(&mut db, "savepoint first").unwrap();
+    run(&mut db, "create table posts (id integer, title text)").unwrap();
+    run(&mut db, "insert into users values (2, 'b', 'b@x.com')").unwrap();
+    run(&mut db, "savepoint second").unwrap();
+    run(&mut db, "insert into posts values (1, 'hello')").unwrap();
+    run(&mut db, "delete from users where id = 1").unwrap();
+
+    // Rolling back to the inner savepoint leaves the outer one's work alone.
+    run(&mut db, "rollback to second").unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![1, 2]);
+    assert!(select_rows(&mut db, "select * from posts").is_empty());
+
+    // Rolling back to the outer savepoint forgets the inner one, and the table made after it.
+    run(&mut db, "rollback to first").unwrap();
+    assert_eq!(
+        run(&mut db, "rollback to second"),
+        Err(Error::Transaction(TransactionError::UnknownSavepoint(
+            "second".to_string()
+        )))
+    );
+    assert_eq!(db.table_names(), vec!["users"]);
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![1]);
+    run(&mut db, "commit").unwrap();
+    assert_eq!(ids(&select_rows(&mut db, "select * from users")), vec![1]);
+}
+
+#[test]
+fn test_savepoint_release_keeps_changes() {
+    let mut db = users_db();
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "savepoint first").unwrap();
+    run(&mut db, "insert into users values (1, 'a', 'a@x.com')").unwrap();
+    run(&mut db, "savepoint second").unwrap();
+    run(&mut db, "insert into users values (2, 'b', 'b@x.com')").unwrap();
+
+    // Releasing keeps the work, and takes the savepoints made after it along.
+    assert_eq!(
+        run(&mut db, "release first"),
+        Ok(ExecuteResult::Success(None))
+    );
+    assert_eq!(
+        run(&mut db, "release second"),
+        Err(Error::Transaction(TransactionError::UnknownSavepoint(
+            "second".to_string()
+        )))
+    );
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![1, 2]);
+
+    // Released work is still part of the transaction, so rolling that back undoes it.
+    run(&mut db, "rollback").unwrap();
+    assert!(users_rows(&mut db).is_empty());
+}
+
+#[test]
+fn test_savepoint_errors() {
+    let mut db = users_db();
+    assert_eq!(
+        run(&mut db, "savepoint batch"),
+        Err(Error::Transaction(TransactionError::NotActive))
+    );
+    assert_eq!(
+        run(&mut db, "release batch"),
+        Err(Error::Transaction(TransactionError::NotActive))
+    );
+
+    run(&mut db, "begin").unwrap();
+    assert_eq!(
+        run(&mut db, "rollback to batch"),
+        Err(Error::Transaction(TransactionError::UnknownSavepoint(
+            "batch".to_string()
+        )))
+    );
+
+    // A name used twice means the latest savepoint with it.
+    run(&mut db, "savepoint batch").unwrap();
+    run(&mut db, "insert into users values (1, 'a', 'a@x.com')").unwrap();
+    run(&mut db, "savepoint batch").unwrap();
+    run(&mut db, "insert into users values (2, 'b', 'b@x.com')").unwrap();
+    run(&mut db, "rollback to batch").unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(ids(&rows), vec![1]);
+}
+
+/// Helper method to run a SELECT against a snapshot taken earlier.
+fn read_rows(db: &Database, snapshot: &Snapshot, cmd: &str) -> Vec<Row> {
+    let statement = prepare(cmd).unwrap();
+    let select = match statement {
+        Statement::Select(select) => select,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    match execute_read(select, snapshot, db) {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => rows,
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_snapshot_ignores_later_writes() {
+    let mut db = users_db();
+    seed_users(&mut db);
+    let snapshot = db.snapshot();
+
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "update users set username = 'steve' where id = 42").unwrap();
+    run(&mut db, "delete from users where id = 13").unwrap();
+    run(&mut db, "insert into users values (7, 'new', 'new@x.com')").unwrap();
+
+    // The snapshot sees neither the open transaction nor, once it commits, its changes.
+    let before = read_rows(&db, &snapshot, "select * from users");
+    assert_eq!(ids(&before), vec![13, 42, 1699]);
+    assert_eq!(before[1], user(42, "stefan", "stefp@sigma.com"));
+    let fresh = db.snapshot();
+    assert_eq!(
+        ids(&read_rows(&db, &fresh, "select * from users")),
+        vec![13, 42, 1699]
+    );
+
+    run(&mut db, "commit").unwrap();
+    assert_eq!(read_rows(&db, &snapshot, "select * from users"), before);
+    assert_eq!(ids(&users_rows(&mut db)), vec![7, 42, 1699]);
+    let rows = select_rows(&mut db, "select * from users where id = 42");
+    assert_eq!(rows, vec![user(42, "steve", "stefp@sigma.com")]);
+}
+
+#[test]
+fn test_snapshot_after_rollback() {
+    let mut db = users_db();
+    seed_users(&mut db);
+
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "delete from users").unwrap();
+    let snapshot = db.snapshot();
+    run(&mut db, "rollback").unwrap();
+
+    assert_eq!(
+        ids(&read_rows(&db, &snapshot, "select * from users")),
+        vec![13, 42, 1699]
+    );
+    assert_eq!(ids(&users_rows(&mut db)), vec![13, 42, 1699]);
+}
+
+#[test]
+fn test_collect_garbage() {
+    let mut db = users_db();
+    seed_users(&mut db);
+    let snapshot = db.snapshot();
+    run(&mut db, "update users set email = 'hidden'").unwrap();
+    run(&mut db, "delete from users where id = 13").unwrap();
+
+    // The old versions are kept for as long as the snapshot that sees them is.
+    assert_eq!(db.collect_garbage(), Ok(0));
+    assert_eq!(
+        ids(&read_rows(&db, &snapshot, "select * from users")),
+        vec![13, 42, 1699]
+    );
+
+    drop(snapshot);
+    assert_eq!(db.collect_garbage(), Ok(4));
+    assert_eq!(db.collect_garbage(), Ok(0));
+    assert_eq!(
+        users_rows(&mut db),
+        vec![
+            user(42, "stefan", "hidden"),
+            user(1699, "sniper_penut", "hidden")
+        ]
+    );
+}
+
+#[test]
+fn test_readers_alongside_writer() {
+    let db = Arc::new(Mutex::new(Database::new()));
+    {
+        let mut db = db.lock().unwrap();
+        run(
+            &mut db,
+            "create table accounts (id integer primary key, balance integer)",
+        )
+        .unwrap();
+        for id in 0..10 {
+            run(
+                &mut db,
+                &format!("insert into accounts values ({}, 100)", id),
+            )
+            .unwrap();
+        }
+    }
+
+    // Moves money between accounts, one statement at a time so readers get in between.
+    let writer = {
+        let db = Arc::clone(&db);
+        thread::spawn(move || {
+            for transfer in 0..100 {
+                let (from, to) = (transfer % 10, (transfer * 7 + 3) % 10);
+                for cmd in [
+                    "begin".to_string(),
+                    format!(
+                        "update accounts set balance = balance - 5 where id = {}",
+                        from
+                    ),
+                    format!(
+                        "update accounts set balance = balance + 5 where id = {}",
+                        to
+                    ),
+                    "commit".to_string(),
+                ] {
+                    run(&mut db.lock().unwrap(), &cmd).unwrap();
+                }
+            }
+        })
+    };
+
+    // Every snapshot sees all of a transfer or none of it, however often it is read.
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let db = Arc::clone(&db);
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    let snapshot = db.lock().unwrap().snapshot();
+                    let total = || {
+                        let rows =
+                            read_rows(&db.lock().unwrap(), &snapshot, "select * from accounts");
+                        assert_eq!(rows.len(), 10);
+                        rows.iter()
+                            .map(|row| match row.values[1] {
+                                Value::Integer(balance) => balance,
+                                ref other => panic!("Expected a balance, got {:?}", other),
+                            })
+                            .sum::<i64>()
+                    };
+                    assert_eq!(total(), 1000);
+                    assert_eq!(total(), 1000);
+                }
+            })
+        })
+        .collect();
+
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    let mut db = db.lock().unwrap();
+    assert!(db.collect_garbage().is_ok());
+    let rows = select_rows(&mut db, "select * from accounts");
+    assert_eq!(rows.len(), 10);
+}
+
+#[test]
+fn test_database_execute() -> Result<(), Error> {
+    let mut db = Database::new();
+    db.execute("create table users (id integer primary key, username text, email text)")?;
+    db.execute("insert into users values (1, 'a', 'a@x.com')")?;
+    match db.execute("select * from users")? {
+        ExecuteResult::Success(Some(result)) => {
+            assert_eq!(result.rows, vec![user(1, "a", "a@x.com")])
+        }
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+
+    // Every kind of failure comes back as the same error type.
+    assert!(matches!(
+        db.execute("selec * from users"),
+        Err(Error::Parse(_))
+    ));
+    assert_eq!(
+        db.execute("select * from nobody"),
+        Err(Error::Schema(SchemaError::UnknownTable(
+            "nobody".to_string()
+        )))
+    );
+    assert_eq!(
+        db.execute("insert into users values (1, 'b', 'b@x.com')"),
+        duplicate_id(1)
+    );
+    assert_eq!(
+        db.execute("commit").unwrap_err().to_string(),
+        "no transaction is open"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_execute_command_exit() {
+    assert_eq!(execute_command(".exit"), MetaCommandResult::Exit);
+}
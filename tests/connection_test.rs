@@ -0,0 +1,317 @@
+use sql_engine::ast::Statement;
+use sql_engine::backend::ExecuteResult;
+use sql_engine::connection::Connection;
+use sql_engine::parser::prepare;
+use sql_engine::query::ResultSet;
+use sql_engine::schema::{Row, SchemaError};
+use sql_engine::transaction::TransactionError;
+use sql_engine::value::Value;
+use sql_engine::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// Helper method to parse a SQL command.
+fn statement(cmd: &str) -> Statement {
+    prepare(cmd).unwrap()
+}
+
+/// Helper method to run a SQL command on a connection.
+fn run(conn: &Connection, cmd: &str) -> Result<ExecuteResult, Error> {
+    conn.execute(statement(cmd))
+}
+
+/// Helper method to get the rows a SELECT returns.
+fn select_rows(conn: &Connection, cmd: &str) -> Vec<Row> {
+    match run(conn, cmd) {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => rows,
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+}
+
+/// Helper method to set up the accounts table, every account holding 100.
+fn accounts(count: i64) -> Connection {
+    let conn = Connection::memory();
+    run(
+        &conn,
+        "create table accounts (id integer primary key, balance integer)",
+    )
+    .unwrap();
+    for id in 0..count {
+        run(&conn, &format!("insert into accounts values ({}, 100)", id)).unwrap();
+    }
+    conn
+}
+
+/// Helper method to add up the balances of every account.
+fn total(rows: &[Row]) -> i64 {
+    rows.iter()
+        .map(|row| match row.values[1] {
+            Value::Integer(balance) => balance,
+            ref other => panic!("Expected a balance, got {:?}", other),
+        })
+        .sum()
+}
+
+// Testing that statements from many threads all make it into the database.
+#[test]
+fn test_concurrent_inserts() {
+    let conn = Connection::memory();
+    run(
+        &conn,
+        "create table items (id integer primary key, owner integer)",
+    )
+    .unwrap();
+
+    let threads: Vec<_> = (0..8)
+        .map(|owner| {
+            let conn = conn.clone();
+            thread::spawn(move || {
+                for item in 0..25 {
+                    let cmd = format!(
+                        "insert into items values ({}, {})",
+                        owner * 100 + item,
+                        owner
+                    );
+                    assert_eq!(run(&conn, &cmd), Ok(ExecuteResult::Success(None)));
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(select_rows(&conn, "select * from items").len(), 200);
+}
+
+// Testing that readers never see a transaction half done, while writers take turns.
+#[test]
+fn test_concurrent_transactions() {
+    let conn = accounts(10);
+
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let conn = conn.clone();
+            thread::spawn(move || {
+                for transfer in 0..25 {
+                    let (from, to) = ((writer + transfer) % 10, (writer * 3 + 1) % 10);
+                    let txn = conn.begin().unwrap();
+                    txn.execute(statement(&format!(
+                        "update accounts set balance = balance - 1 where id = {}",
+                        from
+                    )))
+                    .unwrap();
+                    txn.execute(statement(&format!(
+                        "update accounts set balance = balance + 1 where id = {}",
+                        to
+                    )))
+                    .unwrap();
+                    txn.commit().unwrap();
+                }
+            })
+        })
+        .collect();
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let conn = conn.clone();
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    assert_eq!(total(&select_rows(&conn, "select * from accounts")), 1000);
+                }
+            })
+        })
+        .collect();
+
+    for thread in writers.into_iter().chain(readers) {
+        thread.join().unwrap();
+    }
+    assert_eq!(total(&select_rows(&conn, "select * from accounts")), 1000);
+}
+
+// Testing that a transaction's changes stay hidden until it commits, and go if it doesn't.
+#[test]
+fn test_transaction_isolation() {
+    let conn = accounts(2);
+
+    let txn = conn.begin().unwrap();
+    txn.execute(statement("update accounts set balance = 0"))
+        .unwrap();
+    let reader = conn.clone();
+    let seen = thread::spawn(move || total(&select_rows(&reader, "select * from accounts")));
+    assert_eq!(seen.join().unwrap(), 200);
+    match txn.execute(statement("select * from accounts")) {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => assert_eq!(total(&rows), 0),
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+    drop(txn);
+    assert_eq!(total(&select_rows(&conn, "select * from accounts")), 200);
+
+    let txn = conn.begin().unwrap();
+    txn.execute(statement("delete from accounts where id = 0"))
+        .unwrap();
+    txn.commit().unwrap();
+    assert_eq!(select_rows(&conn, "select * from accounts").len(), 1);
+}
+
+// Testing that a snapshot keeps its view while the database changes.
+#[test]
+fn test_connection_snapshot() {
+    let conn = accounts(3);
+    let snapshot = conn.snapshot();
+    run(&conn, "delete from accounts").unwrap();
+
+    let select = match statement("select * from accounts") {
+        Statement::Select(select) => select,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    match conn.read(select, &snapshot) {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => assert_eq!(rows.len(), 3),
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+    assert!(select_rows(&conn, "select * from accounts").is_empty());
+
+    drop(snapshot);
+    assert_eq!(conn.collect_garbage(), Ok(3));
+}
+
+// Testing that tables created and dropped in a transaction stay out of sight of other readers
+// until it commits, and that older snapshots still read dropped tables afterwards.
+#[test]
+fn test_transaction_catalog_isolation() {
+    let conn = accounts(2);
+    let snapshot = conn.snapshot();
+
+    let txn = conn.begin().unwrap();
+    txn.execute(statement("drop table accounts")).unwrap();
+    txn.execute(statement("create table audit (id integer primary key)"))
+        .unwrap();
+    let reader = conn.clone();
+    let seen = thread::spawn(move || {
+        (
+            total(&select_rows(&reader, "select * from accounts")),
+            run(&reader, "select * from audit"),
+        )
+    });
+    let (balance, audit) = seen.join().unwrap();
+    assert_eq!(balance, 200);
+    assert_eq!(
+        audit,
+        Err(Error::Schema(SchemaError::UnknownTable(
+            "audit".to_string()
+        )))
+    );
+    assert_eq!(
+        txn.execute(statement("select * from accounts")),
+        Err(Error::Schema(SchemaError::UnknownTable(
+            "accounts".to_string()
+        )))
+    );
+    txn.commit().unwrap();
+
+    assert!(select_rows(&conn, "select * from audit").is_empty());
+    assert_eq!(
+        run(&conn, "select * from accounts"),
+        Err(Error::Schema(SchemaError::UnknownTable(
+            "accounts".to_string()
+        )))
+    );
+    let select = match statement("select * from accounts") {
+        Statement::Select(select) => select,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    match conn.read(select, &snapshot) {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => assert_eq!(total(&rows), 200),
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+}
+
+// Testing that transaction statements are turned away on a shared connection.
+#[test]
+fn test_connection_refuses_transaction_statements() {
+    let conn = accounts(1);
+    for cmd in ["begin", "commit", "rollback", "savepoint a", "release a"] {
+        assert_eq!(
+            run(&conn, cmd),
+            Err(Error::Transaction(TransactionError::Shared))
+        );
+    }
+
+    // Inside a transaction from the connection, savepoints work as usual.
+    let txn = conn.begin().unwrap();
+    txn.execute(statement("savepoint a")).unwrap();
+    txn.execute(statement("delete from accounts")).unwrap();
+    txn.execute(statement("rollback to a")).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(select_rows(&conn, "select * from accounts").len(), 1);
+}
+
+// Testing that reads run side by side while a transaction is open: a read started during a
+// longer one finishes first, and neither sees the transaction's changes.
+#[test]
+fn test_concurrent_reads() {
+    let conn = accounts(400);
+    let txn = conn.begin().unwrap();
+    txn.execute(statement("delete from accounts")).unwrap();
+
+    let (started, running) = mpsc::channel();
+    let done = Arc::new(AtomicBool::new(false));
+    let long = {
+        let (conn, done) = (conn.clone(), done.clone());
+        thread::spawn(move || {
+            started.send(()).unwrap();
+            let rows = select_rows(
+                &conn,
+                "select sum(a.balance) from accounts a join accounts b on a.id <> b.id \
+                 join accounts c on b.id = c.id",
+            );
+            done.store(true, Ordering::SeqCst);
+            rows
+        })
+    };
+    running.recv().unwrap();
+    // Long enough for the other thread to be well into its read.
+    thread::sleep(Duration::from_millis(10));
+    let rows = select_rows(
+        &conn,
+        "select count(*) from accounts a join accounts b on a.id = b.id",
+    );
+    assert!(!done.load(Ordering::SeqCst));
+    assert_eq!(rows[0].values, vec![Value::Integer(400)]);
+    let rows = long.join().unwrap();
+    assert_eq!(rows[0].values, vec![Value::Integer(100 * 400 * 399)]);
+
+    txn.rollback();
+    assert_eq!(select_rows(&conn, "select * from accounts").len(), 400);
+}
+
+// Testing that the thread holding a transaction gets an error for writing around it, where it
+// would otherwise wait for itself forever.
+#[test]
+fn test_transaction_reentry() {
+    let conn = accounts(1);
+    let txn = conn.begin().unwrap();
+    assert_eq!(
+        run(&conn, "insert into accounts values (1, 100)"),
+        Err(Error::Transaction(TransactionError::AlreadyActive))
+    );
+    assert_eq!(
+        conn.collect_garbage(),
+        Err(Error::Transaction(TransactionError::AlreadyActive))
+    );
+    assert_eq!(
+        conn.checkpoint(),
+        Err(Error::Transaction(TransactionError::AlreadyActive))
+    );
+    assert!(matches!(conn.begin(), Err(TransactionError::AlreadyActive)));
+    assert_eq!(select_rows(&conn, "select * from accounts").len(), 1);
+
+    txn.execute(statement("insert into accounts values (1, 100)"))
+        .unwrap();
+    txn.commit().unwrap();
+    assert_eq!(
+        run(&conn, "insert into accounts values (2, 100)"),
+        Ok(ExecuteResult::Success(None))
+    );
+    assert_eq!(select_rows(&conn, "select * from accounts").len(), 3);
+}
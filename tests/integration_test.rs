@@ -1,5 +1,51 @@
-use sql_engine::backend::{execute_command, execute_statement, Table};
-use sql_engine::parser::{prepare_statement, MetaCommandResult, Statement};
+use sql_engine::ast::Statement;
+use sql_engine::backend::{
+    execute_command, execute_read, execute_statement, Database, ExecuteResult,
+};
+use sql_engine::limits::{Limit, Limits};
+use sql_engine::parser::{prepare, MetaCommandResult};
+use sql_engine::query::ResultSet;
+use sql_engine::repl;
+use sql_engine::schema::{ConstraintViolation, Row, SchemaError};
+use sql_engine::value::Value;
+use sql_engine::wal::wal_path;
+use sql_engine::Error;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Helper method to create a database holding the users table most tests work with.
+fn users_db() -> Database {
+    let mut db = Database::new();
+    let statement =
+        prepare("create table users (id integer primary key, username text, email text)").unwrap();
+    execute_statement(statement, &mut db).unwrap();
+    db
+}
+
+/// Helper method to run a statement and get its result back.
+fn run(db: &mut Database, cmd: &str) -> Result<ExecuteResult, Error> {
+    execute_statement(prepare(cmd)?, db)
+}
+
+/// Helper method to run a SELECT and get the rows it found.
+fn select_rows(db: &mut Database, cmd: &str) -> Vec<Row> {
+    match run(db, cmd) {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => rows,
+        other => panic!("Expected Success with rows, got {:?}", other),
+    }
+}
+
+/// Helper method to get a path for a database file that doesn't exist yet.
+fn temp_db_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!(
+        "sql_engine_test_{}_{}.db",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
 
 // Testing whether unrecognized commands are rejected.
 #[test]
@@ -12,24 +58,28 @@ fn test_unrecognized_command() {
 // Testing a complete workflow of insert and select
 #[test]
 fn test_basic_workflow() {
-    let mut table = Table::new();
+    let mut db = users_db();
 
     // Insert a row
-    let mut statement = Statement::default();
-    prepare_statement("insert 42 testuser test@example.com", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement =
+        prepare("insert into users values (42, 'testuser', 'test@example.com')").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(_) = result {
+    if let Ok(sql_engine::backend::ExecuteResult::Success(_)) = result {
         // Select the row
-        let mut statement = Statement::default();
-        prepare_statement("select 42", &mut statement);
-        let result = execute_statement(statement, &mut table);
+        let statement = prepare("select * from users where id = 42").unwrap();
+        let result = execute_statement(statement, &mut db);
 
-        if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+        if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) =
+            result
+        {
             assert_eq!(rows.len(), 1);
-            assert_eq!(rows[0].id, 42);
-            assert_eq!(rows[0].username, "testuser");
-            assert_eq!(rows[0].email, "test@example.com");
+            assert_eq!(rows[0].values[0], Value::Integer(42));
+            assert_eq!(rows[0].values[1], Value::Text("testuser".to_string()));
+            assert_eq!(
+                rows[0].values[2],
+                Value::Text("test@example.com".to_string())
+            );
         } else {
             panic!("Expected Success with one row");
         }
@@ -41,42 +91,40 @@ fn test_basic_workflow() {
 // Testing multiple operations in sequence
 #[test]
 fn test_multiple_operations() {
-    let mut table = Table::new();
+    let mut db = users_db();
 
     // Insert multiple rows
-    let mut statement = Statement::default();
-    prepare_statement("insert 1 user1 user1@example.com", &mut statement);
-    execute_statement(statement, &mut table);
+    let statement = prepare("insert into users values (1, 'user1', 'user1@example.com')").unwrap();
+    execute_statement(statement, &mut db).unwrap();
 
-    let mut statement = Statement::default();
-    prepare_statement("insert 2 user2 user2@example.com", &mut statement);
-    execute_statement(statement, &mut table);
+    let statement = prepare("insert into users values (2, 'user2', 'user2@example.com')").unwrap();
+    execute_statement(statement, &mut db).unwrap();
 
-    let mut statement = Statement::default();
-    prepare_statement("insert 3 user3 user3@example.com", &mut statement);
-    execute_statement(statement, &mut table);
+    let statement = prepare("insert into users values (3, 'user3', 'user3@example.com')").unwrap();
+    execute_statement(statement, &mut db).unwrap();
 
     // Select all rows
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 3);
     } else {
         panic!("Expected Success with three rows");
     }
 
     // Select a specific row
-    let mut statement = Statement::default();
-    prepare_statement("select 2", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 2").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 1);
-        assert_eq!(rows[0].id, 2);
-        assert_eq!(rows[0].username, "user2");
-        assert_eq!(rows[0].email, "user2@example.com");
+        assert_eq!(rows[0].values[0], Value::Integer(2));
+        assert_eq!(rows[0].values[1], Value::Text("user2".to_string()));
+        assert_eq!(
+            rows[0].values[2],
+            Value::Text("user2@example.com".to_string())
+        );
     } else {
         panic!("Expected Success with one row");
     }
@@ -85,29 +133,28 @@ fn test_multiple_operations() {
 // Testing edge cases with special characters and whitespace
 #[test]
 fn test_special_characters() {
-    let mut table = Table::new();
+    let mut db = users_db();
 
     // Insert with special characters in username and email
-    let mut statement = Statement::default();
-    prepare_statement(
-        "insert 1 user@123 test.user+label@example.com",
-        &mut statement,
-    );
-    let result = execute_statement(statement, &mut table);
+    let statement =
+        prepare("insert into users values (1, 'user@123', 'test.user+label@example.com')").unwrap();
+    let result = execute_statement(statement, &mut db);
     assert!(matches!(
         result,
-        sql_engine::backend::ExecuteResult::Success(_)
+        Ok(sql_engine::backend::ExecuteResult::Success(_))
     ));
 
     // Verify the data
-    let mut statement = Statement::default();
-    prepare_statement("select 1", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 1").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 1);
-        assert_eq!(rows[0].username, "user@123");
-        assert_eq!(rows[0].email, "test.user+label@example.com");
+        assert_eq!(rows[0].values[1], Value::Text("user@123".to_string()));
+        assert_eq!(
+            rows[0].values[2],
+            Value::Text("test.user+label@example.com".to_string())
+        );
     } else {
         panic!("Expected Success with one row");
     }
@@ -116,35 +163,33 @@ fn test_special_characters() {
 // Testing boundary values for IDs
 #[test]
 fn test_boundary_values() {
-    let mut table = Table::new();
+    let mut db = users_db();
 
     // Test minimum value (0)
-    let mut statement = Statement::default();
-    prepare_statement("insert 0 user0 zero@example.com", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("insert into users values (0, 'user0', 'zero@example.com')").unwrap();
+    let result = execute_statement(statement, &mut db);
     assert!(matches!(
         result,
-        sql_engine::backend::ExecuteResult::Success(_)
+        Ok(sql_engine::backend::ExecuteResult::Success(_))
     ));
 
     // Test maximum value (u32::MAX)
-    let mut statement = Statement::default();
-    prepare_statement("insert 4294967295 usermax max@example.com", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement =
+        prepare("insert into users values (4294967295, 'usermax', 'max@example.com')").unwrap();
+    let result = execute_statement(statement, &mut db);
     assert!(matches!(
         result,
-        sql_engine::backend::ExecuteResult::Success(_)
+        Ok(sql_engine::backend::ExecuteResult::Success(_))
     ));
 
     // Verify both entries
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 2);
-        assert_eq!(rows[0].id, 0);
-        assert_eq!(rows[1].id, 4294967295);
+        assert_eq!(rows[0].values[0], Value::Integer(0));
+        assert_eq!(rows[1].values[0], Value::Integer(4294967295));
     } else {
         panic!("Expected Success with two rows");
     }
@@ -153,56 +198,56 @@ fn test_boundary_values() {
 // Testing duplicate IDs
 #[test]
 fn test_duplicate_ids() {
-    let mut table = Table::new();
+    let mut db = users_db();
 
     // Insert first row
-    let mut statement = Statement::default();
-    prepare_statement("insert 1 user1 user1@example.com", &mut statement);
-    execute_statement(statement, &mut table);
+    let statement = prepare("insert into users values (1, 'user1', 'user1@example.com')").unwrap();
+    execute_statement(statement, &mut db).unwrap();
 
     // Insert duplicate ID
-    let mut statement = Statement::default();
-    prepare_statement("insert 1 user2 user2@example.com", &mut statement);
-    execute_statement(statement, &mut table);
+    let statement = prepare("insert into users values (1, 'user2', 'user2@example.com')").unwrap();
+    assert_eq!(
+        execute_statement(statement, &mut db),
+        Err(Error::Constraint(ConstraintViolation::PrimaryKey {
+            table: "users".to_string(),
+            column: "id".to_string(),
+            key: 1
+        }))
+    );
 
-    // Verify both entries are present
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    // Verify only the first entry is present
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
-        assert_eq!(rows.len(), 2);
-        assert_eq!(rows[0].id, 1);
-        assert_eq!(rows[0].username, "user1");
-        assert_eq!(rows[1].id, 1);
-        assert_eq!(rows[1].username, "user2");
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].values[0], Value::Integer(1));
+        assert_eq!(rows[0].values[1], Value::Text("user1".to_string()));
     } else {
-        panic!("Expected Success with two rows");
+        panic!("Expected Success with one row");
     }
 }
 
 // Testing select on empty table
 #[test]
 fn test_empty_table_operations() {
-    let mut table = Table::new();
+    let mut db = users_db();
 
     // Select all from empty table
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 0);
     } else {
         panic!("Expected Success with zero rows");
     }
 
     // Select specific ID from empty table
-    let mut statement = Statement::default();
-    prepare_statement("select 1", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users where id = 1").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
         assert_eq!(rows.len(), 0);
     } else {
         panic!("Expected Success with zero rows");
@@ -212,43 +257,441 @@ fn test_empty_table_operations() {
 // Testing complex workflow with mixed operations
 #[test]
 fn test_complex_workflow() {
-    let mut table = Table::new();
+    let mut db = users_db();
 
     // Insert multiple rows
     let test_data = vec![
-        ("insert 1 user1 user1@example.com", 1),
-        ("insert 2 user2 user2@example.com", 2),
-        ("insert 2 user2_dup user2_dup@example.com", 2), // Duplicate ID
-        ("insert 3 user3 user3@example.com", 3),
+        (
+            "insert into users values (1, 'user1', 'user1@example.com')",
+            1,
+        ),
+        (
+            "insert into users values (2, 'user2', 'user2@example.com')",
+            2,
+        ),
+        (
+            "insert into users values (2, 'user2_dup', 'user2_dup@example.com')",
+            2,
+        ), // Duplicate ID, rejected
+        (
+            "insert into users values (3, 'user3', 'user3@example.com')",
+            3,
+        ),
     ];
 
     for (cmd, _) in &test_data {
-        let mut statement = Statement::default();
-        prepare_statement(cmd, &mut statement);
-        execute_statement(statement, &mut table);
+        let statement = prepare(cmd).unwrap();
+        let _ = execute_statement(statement, &mut db);
     }
 
     // Verify total count
-    let mut statement = Statement::default();
-    prepare_statement("select", &mut statement);
-    let result = execute_statement(statement, &mut table);
+    let statement = prepare("select * from users").unwrap();
+    let result = execute_statement(statement, &mut db);
 
-    if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
-        assert_eq!(rows.len(), 4);
+    if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) = result {
+        assert_eq!(rows.len(), 3);
     }
 
     // Verify each ID's count
     for (_, id) in test_data {
-        let mut statement = Statement::default();
-        prepare_statement(&format!("select {}", id), &mut statement);
-        let result = execute_statement(statement, &mut table);
-
-        if let sql_engine::backend::ExecuteResult::Success(Some(rows)) = result {
-            if id == 2 {
-                assert_eq!(rows.len(), 2); // Should have two rows with ID 2
-            } else {
-                assert_eq!(rows.len(), 1);
+        let statement = prepare(&format!("select * from users where id = {}", id)).unwrap();
+        let result = execute_statement(statement, &mut db);
+
+        if let Ok(sql_engine::backend::ExecuteResult::Success(Some(ResultSet { rows, .. }))) =
+            result
+        {
+            assert_eq!(rows.len(), 1);
+        }
+    }
+}
+
+// Testing that rows are still there after the database is closed and opened again.
+#[test]
+fn test_data_persists_across_reopen() {
+    let path = temp_db_path("reopen");
+    {
+        let mut db = Database::open(&path).unwrap();
+        run(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        )
+        .unwrap();
+        run(&mut db, "create table posts (id integer, title text)").unwrap();
+        run(
+            &mut db,
+            "insert into users values (13, 'rosh', 'kakapio@gmail.com'), (42, 'stefan', 'stefp@sigma.com')",
+        ).unwrap();
+        run(&mut db, "insert into posts values (1, 'hello')").unwrap();
+        run(
+            &mut db,
+            "update users set email = 'rosh@new.com' where id = 13",
+        )
+        .unwrap();
+    }
+
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(db.table_names(), vec!["posts", "users"]);
+    assert_eq!(
+        select_rows(&mut db, "select * from users"),
+        vec![
+            Row::new(vec![
+                Value::Integer(13),
+                Value::Text("rosh".to_string()),
+                Value::Text("rosh@new.com".to_string())
+            ]),
+            Row::new(vec![
+                Value::Integer(42),
+                Value::Text("stefan".to_string()),
+                Value::Text("stefp@sigma.com".to_string())
+            ])
+        ]
+    );
+    drop(db);
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that a table spread over many pages comes back whole, and dropping it frees the name.
+#[test]
+fn test_large_table_persists_and_drops() {
+    let path = temp_db_path("large");
+    {
+        let mut db = Database::open(&path).unwrap();
+        run(
+            &mut db,
+            "create table notes (id integer primary key, body text)",
+        )
+        .unwrap();
+        for i in 0..200 {
+            let cmd = format!("insert into notes values ({}, '{}')", i, "x".repeat(500));
+            assert_eq!(run(&mut db, &cmd), Ok(ExecuteResult::Success(None)));
+        }
+    }
+
+    {
+        let mut db = Database::open(&path).unwrap();
+        match run(&mut db, "select * from notes where id >= 195") {
+            Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => {
+                assert_eq!(rows.len(), 5);
+                assert_eq!(rows[0].values[1], Value::Text("x".repeat(500)));
             }
+            other => panic!("Expected Success with rows, got {:?}", other),
+        }
+        assert_eq!(
+            run(&mut db, "drop table notes"),
+            Ok(ExecuteResult::Success(None))
+        );
+    }
+
+    let mut db = Database::open(&path).unwrap();
+    assert!(db.table_names().is_empty());
+    assert_eq!(
+        run(&mut db, "select * from notes"),
+        Err(Error::Schema(SchemaError::UnknownTable(
+            "notes".to_string()
+        )))
+    );
+    drop(db);
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that a table many times the size of the page cache reads and writes correctly.
+#[test]
+fn test_table_larger_than_cache() {
+    let path = temp_db_path("small_cache");
+    let mut db = Database::open(&path).unwrap();
+    db.set_cache_pages(8);
+    run(
+        &mut db,
+        "create table notes (id integer primary key, body text)",
+    )
+    .unwrap();
+    for i in 0..300 {
+        let cmd = format!("insert into notes values ({}, '{}')", i, "x".repeat(500));
+        assert_eq!(run(&mut db, &cmd), Ok(ExecuteResult::Success(None)));
+    }
+    db.checkpoint().unwrap();
+
+    run(&mut db, "begin").unwrap();
+    run(&mut db, "update notes set body = 'y' where id >= 150").unwrap();
+    assert_eq!(
+        select_rows(&mut db, "select * from notes where body = 'y'").len(),
+        150
+    );
+    run(&mut db, "rollback").unwrap();
+
+    let rows = select_rows(&mut db, "select * from notes");
+    assert_eq!(rows.len(), 300);
+    assert!(rows
+        .iter()
+        .all(|row| row.values[1] == Value::Text("x".repeat(500))));
+    drop(db);
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that opening something that isn't a database fails instead of reading garbage.
+#[test]
+fn test_open_rejects_other_files() {
+    let path = temp_db_path("not_a_database");
+    fs::write(&path, "just some text").unwrap();
+    assert!(Database::open(&path).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that the long strings the backend tests use come back from disk unchanged.
+#[test]
+fn test_long_strings_persist() {
+    let path = temp_db_path("long_strings");
+    let long_username = "a".repeat(1000);
+    let long_email = format!("{}@gmail.com", "a".repeat(1000));
+    {
+        let mut db = Database::open(&path).unwrap();
+        run(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        )
+        .unwrap();
+        run(
+            &mut db,
+            &format!(
+                "insert into users values (13, '{}', '{}')",
+                long_username, long_email
+            ),
+        )
+        .unwrap();
+    }
+
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(
+        select_rows(&mut db, "select * from users"),
+        vec![Row::new(vec![
+            Value::Integer(13),
+            Value::Text(long_username),
+            Value::Text(long_email)
+        ])]
+    );
+    drop(db);
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that limits count the rows already in a database file when it is opened again.
+#[test]
+fn test_limits_count_existing_rows() {
+    let path = temp_db_path("limits");
+    {
+        let mut db = Database::open(&path).unwrap();
+        run(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        )
+        .unwrap();
+        for id in 0..50 {
+            run(
+                &mut db,
+                &format!("insert into users values ({}, 'user', 'user@x.com')", id),
+            )
+            .unwrap();
+        }
+    }
+
+    let mut db = Database::open(&path).unwrap();
+    let limits = Limits {
+        max_rows: Some(50),
+        max_bytes: None,
+    };
+    db.set_table_limits("users", limits).unwrap();
+    match run(
+        &mut db,
+        "insert into users values (50, 'user', 'user@x.com')",
+    ) {
+        Err(Error::TableFull(full)) => {
+            assert_eq!(
+                full.limit,
+                Limit::Rows {
+                    max: 50,
+                    needed: 51
+                }
+            )
+        }
+        result => panic!("Expected TableFull, got {:?}", result),
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that a crash keeps every committed statement, and loses one that was cut off part way
+// through being written to the log without corrupting anything.
+#[test]
+fn test_crash_recovery() {
+    let path = temp_db_path("crash");
+    let mut wal_path = path.clone().into_os_string();
+    wal_path.push("-wal");
+    {
+        let mut db = Database::open(&path).unwrap();
+        run(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        )
+        .unwrap();
+        run(
+            &mut db,
+            "insert into users values (1, 'user1', 'user1@x.com')",
+        )
+        .unwrap();
+        run(
+            &mut db,
+            "insert into users values (2, 'user2', 'user2@x.com')",
+        )
+        .unwrap();
+
+        // Crashing skips the checkpoint a clean close would make.
+        std::mem::forget(db);
+    }
+
+    // Tear the last frame of the log, as if the crash happened while the last insert was being
+    // written out.
+    let length = fs::metadata(&wal_path).unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&wal_path)
+        .unwrap()
+        .set_len(length - 10)
+        .unwrap();
+
+    {
+        let mut db = Database::open(&path).unwrap();
+        let rows = select_rows(&mut db, "select * from users");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].values[1], Value::Text("user1".to_string()));
+
+        run(
+            &mut db,
+            "insert into users values (3, 'user3', 'user3@x.com')",
+        )
+        .unwrap();
+        std::mem::forget(db);
+    }
+
+    let mut db = Database::open(&path).unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(rows.len(), 2);
+    drop(db);
+
+    // Closing cleanly folds the log back in and removes it.
+    assert!(!PathBuf::from(&wal_path).exists());
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that only committed transactions make it into the database file.
+#[test]
+fn test_transactions_persist_only_when_committed() {
+    let path = temp_db_path("transactions");
+    {
+        let mut db = Database::open(&path).unwrap();
+        run(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        )
+        .unwrap();
+        run(&mut db, "begin").unwrap();
+        run(
+            &mut db,
+            "insert into users values (1, 'user1', 'user1@x.com')",
+        )
+        .unwrap();
+        run(
+            &mut db,
+            "insert into users values (2, 'user2', 'user2@x.com')",
+        )
+        .unwrap();
+        run(&mut db, "commit").unwrap();
+
+        // Closing with a transaction still open throws it away.
+        run(&mut db, "begin").unwrap();
+        run(&mut db, "delete from users").unwrap();
+        run(
+            &mut db,
+            "insert into users values (3, 'user3', 'user3@x.com')",
+        )
+        .unwrap();
+    }
+
+    let mut db = Database::open(&path).unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].values[0], Value::Integer(2));
+    drop(db);
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that transaction ids carry on where they left off, so versions written before a reopen
+// aren't mistaken for ones a snapshot taken afterwards can't see.
+#[test]
+fn test_snapshots_across_reopen() {
+    let path = temp_db_path("snapshots");
+    {
+        let mut db = Database::open(&path).unwrap();
+        run(
+            &mut db,
+            "create table users (id integer primary key, username text, email text)",
+        )
+        .unwrap();
+        for id in 1..=3 {
+            run(
+                &mut db,
+                &format!("insert into users values ({}, 'user{}', 'x')", id, id),
+            )
+            .unwrap();
+        }
+    }
+
+    let mut db = Database::open(&path).unwrap();
+    let snapshot = db.snapshot();
+    run(&mut db, "update users set email = 'y'").unwrap();
+    run(&mut db, "delete from users where id = 2").unwrap();
+
+    let statement = prepare("select * from users").unwrap();
+    let rows = match statement {
+        Statement::Select(select) => execute_read(select, &snapshot, &db),
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    match rows {
+        Ok(ExecuteResult::Success(Some(ResultSet { rows, .. }))) => {
+            assert_eq!(rows.len(), 3);
+            assert!(rows
+                .iter()
+                .all(|row| row.values[2] == Value::Text("x".to_string())));
         }
+        other => panic!("Expected Success with rows, got {:?}", other),
     }
+    assert_eq!(select_rows(&mut db, "select * from users").len(), 2);
+
+    drop(snapshot);
+    assert_eq!(db.collect_garbage(), Ok(4));
+    drop(db);
+    fs::remove_file(&path).unwrap();
+}
+
+// Testing that the REPL writes everything to the database file when its input runs out, and
+// throws away a transaction that was never committed.
+#[test]
+fn test_repl_shuts_down_cleanly() {
+    let path = temp_db_path("repl");
+    let input = "create table users (id integer primary key, username text, email text)\n\
+                 insert into users values (1, 'user1', 'user1@x.com')\n\
+                 begin\n\
+                 insert into users values (2, 'user2', 'user2@x.com')\n";
+    let mut output = Vec::new();
+    repl::run(
+        Database::open(&path).unwrap(),
+        input.as_bytes(),
+        &mut output,
+    )
+    .unwrap();
+    assert!(!wal_path(&path).exists());
+
+    let mut db = Database::open(&path).unwrap();
+    let rows = select_rows(&mut db, "select * from users");
+    assert_eq!(rows.len(), 1);
+    drop(db);
+    fs::remove_file(&path).unwrap();
 }
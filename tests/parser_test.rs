@@ -1,27 +1,37 @@
-use sql_engine::parser::{
-    prepare_statement, PrepareResult, Row, Statement, StatementType,
+// Most of these tests predate `prepare` and go through `prepare_statement`, which they keep
+// covering for as long as it is around.
+#![allow(deprecated)]
+
+use sql_engine::ast::{
+    AggregateFunction, Assignment, BinaryOp, CreateTable, Delete, DropTable, Expr, Insert, Join,
+    JoinKind, OrderBy, Select, SelectItem, Statement, UnaryOp, Update,
 };
+use sql_engine::lexer::Span;
+use sql_engine::parser::{prepare, prepare_statement, ParseError, PrepareResult};
+use sql_engine::schema::{Column, DataType};
+use sql_engine::value::Value;
+use sql_engine::Error;
 
-// Testing whether the enum is set properly.
+// Testing whether the statement type is set properly.
 #[test]
 fn test_prepare_statement_set_insert() {
     let mut out_statement = Statement::default();
-    let cmd = "insert";
+    let cmd = "insert into users values (10, 'monkeylover', 'ape@gmail.com')";
     prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_statement.cmd, StatementType::Insert);
+    assert!(matches!(out_statement, Statement::Insert(_)));
 }
 
 #[test]
 fn test_prepare_statement_set_select() {
     let mut out_statement = Statement::default();
-    let cmd = "select";
+    let cmd = "select * from users";
     prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_statement.cmd, StatementType::Select);
+    assert!(matches!(out_statement, Statement::Select(_)));
 }
 
 #[test]
 fn test_prepare_statement_success() {
-    let cmd = "insert 10 monkeylover ape@gmail.com";
+    let cmd = "insert into users values (10, 'monkeylover', 'ape@gmail.com')";
     let out_result = prepare_statement(cmd, &mut Statement::default());
     assert_eq!(out_result, PrepareResult::Success);
 }
@@ -38,20 +48,24 @@ fn test_prepare_statement_syntax_error() {
     let mut out_statement = Statement::default();
     let cmd = "insert";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::SyntaxError);
+    assert!(matches!(out_result, PrepareResult::SyntaxError(_)));
 }
 
 #[test]
 fn test_prepare_statement_insert_parse() {
     let mut out_statement = Statement::default();
-    let cmd = "insert 10 monkeylover ape@gmail.com";
+    let cmd = "insert into users values (10, 'monkeylover', 'ape@gmail.com')";
     prepare_statement(cmd, &mut out_statement);
     assert_eq!(
-        out_statement.row_instance,
-        Some(Row {
-            id: 10,
-            username: "monkeylover".to_string(),
-            email: "ape@gmail.com".to_string()
+        out_statement,
+        Statement::Insert(Insert {
+            table: "users".to_string(),
+            columns: None,
+            rows: vec![vec![
+                *integer(10),
+                *text("monkeylover"),
+                *text("ape@gmail.com")
+            ]]
         })
     );
 }
@@ -59,54 +73,85 @@ fn test_prepare_statement_insert_parse() {
 #[test]
 fn test_prepare_statement_insert_parse_fail() {
     let mut out_statement = Statement::default();
-    let cmd = "insert 10 monkeylover ape@gmail.com";
+    let cmd = "insert into users values (10, 'monkeylover', 'ape@gmail.com')";
     prepare_statement(cmd, &mut out_statement);
     assert_ne!(
-        out_statement.row_instance,
-        Some(Row {
-            id: 10,
-            username: "blah".to_string(),
-            email: "blah@gmail.com".to_string()
+        out_statement,
+        Statement::Insert(Insert {
+            table: "users".to_string(),
+            columns: None,
+            rows: vec![vec![*integer(10), *text("blah"), *text("blah@gmail.com")]]
         })
     );
 }
 
+#[test]
+fn test_prepare_statement_insert_multiple_rows() {
+    let mut out_statement = Statement::default();
+    let cmd = "insert into users values (1, 'a', 'a@x.com'), (2, 'b', 'b@x.com')";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+
+    if let Statement::Insert(insert) = out_statement {
+        assert_eq!(insert.rows.len(), 2);
+        assert_eq!(insert.rows[1][0], *integer(2));
+    } else {
+        panic!("Expected an insert statement");
+    }
+}
+
+#[test]
+fn test_prepare_statement_escaped_quote() {
+    let mut out_statement = Statement::default();
+    let cmd = "insert into users values (1, 'o''brien', 'ob@x.com')";
+    prepare_statement(cmd, &mut out_statement);
+
+    if let Statement::Insert(insert) = out_statement {
+        assert_eq!(insert.rows[0][1], *text("o'brien"));
+    } else {
+        panic!("Expected an insert statement");
+    }
+}
+
 #[test]
 fn test_prepare_statement_empty_username() {
     let mut out_statement = Statement::default();
-    let cmd = "insert 10  ape@gmail.com";
+    let cmd = "insert into users values (10, , 'ape@gmail.com')";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::SyntaxError);
+    assert!(matches!(out_result, PrepareResult::SyntaxError(_)));
 }
 
 #[test]
 fn test_prepare_statement_empty_email() {
     let mut out_statement = Statement::default();
-    let cmd = "insert 10 monkeylover ";
+    let cmd = "insert into users values (10, 'monkeylover')";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::SyntaxError);
+    // The number of values is checked against the table's schema when executing.
+    assert_eq!(out_result, PrepareResult::Success);
 }
 
 #[test]
 fn test_prepare_statement_invalid_id() {
     let mut out_statement = Statement::default();
-    let cmd = "insert abc monkeylover ape@gmail.com";
+    let cmd = "insert into users values (10abc, 'monkeylover', 'ape@gmail.com')";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::SyntaxError);
+    assert!(matches!(out_result, PrepareResult::SyntaxError(_)));
 }
 
 #[test]
 fn test_prepare_statement_negative_id() {
     let mut out_statement = Statement::default();
-    let cmd = "insert -10 monkeylover ape@gmail.com";
+    let cmd = "insert into users values (-10, 'monkeylover', 'ape@gmail.com')";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::SyntaxError);
+    assert_eq!(out_result, PrepareResult::Success);
 }
 
 #[test]
 fn test_prepare_statement_large_id() {
     let mut out_statement = Statement::default();
-    let cmd = "insert 4294967295 monkeylover ape@gmail.com";
+    let cmd = "insert into users values (9223372036854775807, 'monkeylover', 'ape@gmail.com')";
     let out_result = prepare_statement(cmd, &mut out_statement);
     assert_eq!(out_result, PrepareResult::Success);
 }
@@ -114,25 +159,762 @@ fn test_prepare_statement_large_id() {
 #[test]
 fn test_prepare_statement_id_overflow() {
     let mut out_statement = Statement::default();
-    let cmd = "insert 4294967296 monkeylover ape@gmail.com";
+    let cmd = "insert into users values (9223372036854775808, 'monkeylover', 'ape@gmail.com')";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::SyntaxError);
+    assert!(matches!(out_result, PrepareResult::SyntaxError(_)));
 }
 
 #[test]
-fn test_prepare_statement_case_sensitivity() {
+fn test_prepare_statement_case_insensitive() {
     let mut out_statement = Statement::default();
-    let cmd = "INSERT 10 monkeylover ape@gmail.com";
+    let cmd = "INSERT INTO users VALUES (10, 'monkeylover', 'ape@gmail.com')";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::Unrecognized);
+    assert_eq!(out_result, PrepareResult::Success);
+
+    let cmd = "SELECT * FROM users WHERE ID = 10";
+    let out_result = prepare_statement(cmd, &mut out_statement);
+    assert_eq!(out_result, PrepareResult::Success);
+    assert_eq!(
+        out_statement,
+        Statement::Select(Select {
+            projection: vec![SelectItem::Wildcard],
+            table: "users".to_string(),
+            alias: None,
+            joins: Vec::new(),
+            selection: Some(Expr::Binary {
+                left: column("id"),
+                op: BinaryOp::Eq,
+                right: Box::new(Expr::Literal(Value::Integer(10)))
+            }),
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None
+        })
+    );
 }
 
 #[test]
 fn test_prepare_statement_whitespace() {
     let mut out_statement = Statement::default();
-    let cmd = "  insert  10  monkeylover  ape@gmail.com  ";
+    let cmd = "  insert  into users values  (10,  'monkeylover',  'ape@gmail.com')  ";
     let out_result = prepare_statement(cmd, &mut out_statement);
-    assert_eq!(out_result, PrepareResult::Unrecognized);
+    assert_eq!(out_result, PrepareResult::Success);
+}
+
+#[test]
+fn test_prepare_statement_trailing_semicolon() {
+    let mut out_statement = Statement::default();
+    let cmd = "select * from users;";
+    let out_result = prepare_statement(cmd, &mut out_statement);
+    assert_eq!(out_result, PrepareResult::Success);
+}
+
+#[test]
+fn test_prepare_statement_trailing_tokens() {
+    let mut out_statement = Statement::default();
+    let cmd = "select * from users u v";
+    let out_result = prepare_statement(cmd, &mut out_statement);
+    assert!(matches!(out_result, PrepareResult::SyntaxError(_)));
+}
+
+#[test]
+fn test_prepare_statement_select_missing_from() {
+    let mut out_statement = Statement::default();
+    let cmd = "select *";
+    let out_result = prepare_statement(cmd, &mut out_statement);
+    assert!(matches!(out_result, PrepareResult::SyntaxError(_)));
+}
+
+// Testing that the column list of a select takes columns, expressions and aliases.
+#[test]
+fn test_prepare_statement_select_projection() {
+    let statement = prepare("select id, id * 2 as double, username name, * from users").unwrap();
+    assert_eq!(
+        statement,
+        Statement::Select(Select {
+            projection: vec![
+                SelectItem::Expr {
+                    expr: *column("id"),
+                    alias: None
+                },
+                SelectItem::Expr {
+                    expr: Expr::Binary {
+                        left: column("id"),
+                        op: BinaryOp::Mul,
+                        right: integer(2)
+                    },
+                    alias: Some("double".to_string())
+                },
+                SelectItem::Expr {
+                    expr: *column("username"),
+                    alias: Some("name".to_string())
+                },
+                SelectItem::Wildcard,
+            ],
+            table: "users".to_string(),
+            alias: None,
+            joins: Vec::new(),
+            selection: None,
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None
+        })
+    );
+
+    // The column list can't be left empty or trail off.
+    for cmd in [
+        "select from users",
+        "select id, from users",
+        "select id as from users",
+    ] {
+        assert!(matches!(prepare(cmd), Err(Error::Parse(_))), "{}", cmd);
+    }
+}
+
+// Testing ORDER BY with several keys, directions and where NULLs go.
+#[test]
+fn test_prepare_statement_order_by() {
+    let statement = prepare(
+        "select * from users where id > 1 order by username desc, id * 2, email asc nulls last, \
+         id desc nulls first",
+    )
+    .unwrap();
+    let order_by = match statement {
+        Statement::Select(select) => select.order_by,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    assert_eq!(
+        order_by,
+        vec![
+            OrderBy {
+                expr: *column("username"),
+                descending: true,
+                nulls_first: false
+            },
+            OrderBy {
+                expr: Expr::Binary {
+                    left: column("id"),
+                    op: BinaryOp::Mul,
+                    right: integer(2)
+                },
+                descending: false,
+                nulls_first: true
+            },
+            OrderBy {
+                expr: *column("email"),
+                descending: false,
+                nulls_first: false
+            },
+            OrderBy {
+                expr: *column("id"),
+                descending: true,
+                nulls_first: true
+            },
+        ]
+    );
+
+    for cmd in [
+        "select * from users order id",
+        "select * from users order by",
+        "select * from users order by id nulls",
+        "select * from users order by id desc asc",
+    ] {
+        assert!(matches!(prepare(cmd), Err(Error::Parse(_))), "{}", cmd);
+    }
+}
+
+// Testing LIMIT with and without OFFSET.
+#[test]
+fn test_prepare_statement_limit_offset() {
+    let select = |cmd: &str| match prepare(cmd).unwrap() {
+        Statement::Select(select) => select,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+
+    let paged = select("select * from users where id > 40 order by id limit 20 offset 2 * 10");
+    assert_eq!(paged.limit, Some(Expr::Literal(Value::Integer(20))));
+    assert_eq!(
+        paged.offset,
+        Some(Expr::Binary {
+            left: integer(2),
+            op: BinaryOp::Mul,
+            right: integer(10)
+        })
+    );
+    assert_eq!(paged.order_by.len(), 1);
+
+    let capped = select("select * from users limit 5");
+    assert_eq!(capped.limit, Some(Expr::Literal(Value::Integer(5))));
+    assert_eq!(capped.offset, None);
+
+    // OFFSET only comes after LIMIT, and LIMIT after ORDER BY.
+    for cmd in [
+        "select * from users offset 5",
+        "select * from users limit",
+        "select * from users limit 5 order by id",
+    ] {
+        assert!(matches!(prepare(cmd), Err(Error::Parse(_))), "{}", cmd);
+    }
+}
+
+// Testing aggregate functions along with GROUP BY and HAVING.
+#[test]
+fn test_prepare_statement_group_by() {
+    let select = match prepare(
+        "select domain, count(*), Count(distinct email), max(id) + 1 from users \
+         where id > 0 group by domain, id % 2 having sum(id) > 10 order by count(*) desc",
+    )
+    .unwrap()
+    {
+        Statement::Select(select) => select,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    let count_all = Expr::Aggregate {
+        function: AggregateFunction::Count,
+        arg: None,
+        distinct: false,
+    };
+    let items: Vec<Expr> = select
+        .projection
+        .into_iter()
+        .map(|item| match item {
+            SelectItem::Expr { expr, .. } => expr,
+            other => panic!("Expected an expression, got {:?}", other),
+        })
+        .collect();
+    assert_eq!(
+        items,
+        vec![
+            *column("domain"),
+            count_all.clone(),
+            Expr::Aggregate {
+                function: AggregateFunction::Count,
+                arg: Some(column("email")),
+                distinct: true,
+            },
+            Expr::Binary {
+                left: Box::new(Expr::Aggregate {
+                    function: AggregateFunction::Max,
+                    arg: Some(column("id")),
+                    distinct: false,
+                }),
+                op: BinaryOp::Add,
+                right: integer(1)
+            },
+        ]
+    );
+    assert_eq!(
+        select.group_by,
+        vec![
+            *column("domain"),
+            Expr::Binary {
+                left: column("id"),
+                op: BinaryOp::Mod,
+                right: integer(2)
+            }
+        ]
+    );
+    assert_eq!(
+        select.having.map(|having| having.to_string()),
+        Some("SUM(id) > 10".to_string())
+    );
+    assert_eq!(select.order_by[0].expr, count_all);
+
+    for cmd in [
+        "select count(distinct *) from users",
+        "select sum(*) from users",
+        "select count(id from users",
+        "select lower(email) from users",
+        "select id from users group id",
+    ] {
+        assert!(matches!(prepare(cmd), Err(Error::Parse(_))), "{}", cmd);
+    }
+
+    // HAVING doesn't need GROUP BY.
+    let select = match prepare("select count(*) from users having count(*) > 1").unwrap() {
+        Statement::Select(select) => select,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    assert!(select.group_by.is_empty());
+    assert_eq!(
+        select.having.map(|having| having.to_string()),
+        Some("COUNT(*) > 1".to_string())
+    );
+}
+
+// Testing that FROM takes table aliases and joins, and that columns can be qualified with a table.
+#[test]
+fn test_prepare_statement_join() {
+    let select = match prepare(
+        "select u.*, o.total, id from users as u join orders o on u.id = o.user_id \
+         left outer join items on items.order_id = o.id full join tags t on TRUE",
+    )
+    .unwrap()
+    {
+        Statement::Select(select) => select,
+        other => panic!("Expected a select, got {:?}", other),
+    };
+    assert_eq!(
+        select.projection[0],
+        SelectItem::TableWildcard("u".to_string())
+    );
+    assert_eq!(
+        select.projection[1],
+        SelectItem::Expr {
+            expr: *qualified("o", "total"),
+            alias: None
+        }
+    );
+    assert_eq!(select.table, "users");
+    assert_eq!(select.alias, Some("u".to_string()));
+    assert_eq!(
+        select.joins,
+        vec![
+            Join {
+                kind: JoinKind::Inner,
+                table: "orders".to_string(),
+                alias: Some("o".to_string()),
+                on: Expr::Binary {
+                    left: qualified("u", "id"),
+                    op: BinaryOp::Eq,
+                    right: qualified("o", "user_id"),
+                },
+            },
+            Join {
+                kind: JoinKind::Left,
+                table: "items".to_string(),
+                alias: None,
+                on: Expr::Binary {
+                    left: qualified("items", "order_id"),
+                    op: BinaryOp::Eq,
+                    right: qualified("o", "id"),
+                },
+            },
+            Join {
+                kind: JoinKind::Full,
+                table: "tags".to_string(),
+                alias: Some("t".to_string()),
+                on: Expr::Literal(Value::Boolean(true)),
+            },
+        ]
+    );
+
+    let select =
+        match prepare("select * from users inner join orders on TRUE right join x on FALSE")
+            .unwrap()
+        {
+            Statement::Select(select) => select,
+            other => panic!("Expected a select, got {:?}", other),
+        };
+    assert_eq!(select.alias, None);
+    let kinds: Vec<JoinKind> = select.joins.iter().map(|join| join.kind).collect();
+    assert_eq!(kinds, vec![JoinKind::Inner, JoinKind::Right]);
+
+    for cmd in [
+        "select * from users join orders",
+        "select * from users left orders on TRUE",
+        "select * from users join on TRUE",
+        "select u. from users u",
+        "select * from users u join orders o on o.",
+        "select * from users outer",
+        "select inner.id from users inner",
+    ] {
+        assert!(matches!(prepare(cmd), Err(Error::Parse(_))), "{}", cmd);
+    }
+}
+
+/// Helper method to parse the WHERE clause of a select on the users table.
+fn parse_where(clause: &str) -> Expr {
+    let mut out_statement = Statement::default();
+    let cmd = format!("select * from users where {}", clause);
+    assert_eq!(
+        prepare_statement(&cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+    match out_statement {
+        Statement::Select(Select {
+            selection: Some(expr),
+            ..
+        }) => expr,
+        other => panic!("Expected a select with a WHERE clause, got {:?}", other),
+    }
+}
+
+fn column(name: &str) -> Box<Expr> {
+    Box::new(Expr::Column {
+        table: None,
+        column: name.to_string(),
+    })
+}
+
+fn qualified(table: &str, name: &str) -> Box<Expr> {
+    Box::new(Expr::Column {
+        table: Some(table.to_string()),
+        column: name.to_string(),
+    })
+}
+
+fn integer(value: i64) -> Box<Expr> {
+    Box::new(Expr::Literal(Value::Integer(value)))
+}
+
+fn text(value: &str) -> Box<Expr> {
+    Box::new(Expr::Literal(Value::Text(value.to_string())))
+}
+
+// AND binds tighter than OR, and multiplication tighter than addition.
+#[test]
+fn test_where_precedence() {
+    assert_eq!(
+        parse_where("id = 1 or id = 2 and id + 2 * 3 > 4"),
+        Expr::Binary {
+            left: Box::new(Expr::Binary {
+                left: column("id"),
+                op: BinaryOp::Eq,
+                right: integer(1)
+            }),
+            op: BinaryOp::Or,
+            right: Box::new(Expr::Binary {
+                left: Box::new(Expr::Binary {
+                    left: column("id"),
+                    op: BinaryOp::Eq,
+                    right: integer(2)
+                }),
+                op: BinaryOp::And,
+                right: Box::new(Expr::Binary {
+                    left: Box::new(Expr::Binary {
+                        left: column("id"),
+                        op: BinaryOp::Add,
+                        right: Box::new(Expr::Binary {
+                            left: integer(2),
+                            op: BinaryOp::Mul,
+                            right: integer(3)
+                        })
+                    }),
+                    op: BinaryOp::Gt,
+                    right: integer(4)
+                })
+            })
+        }
+    );
+}
+
+#[test]
+fn test_where_parentheses() {
+    assert_eq!(
+        parse_where("(id - 1) * 2 = 4"),
+        Expr::Binary {
+            left: Box::new(Expr::Binary {
+                left: Box::new(Expr::Binary {
+                    left: column("id"),
+                    op: BinaryOp::Sub,
+                    right: integer(1)
+                }),
+                op: BinaryOp::Mul,
+                right: integer(2)
+            }),
+            op: BinaryOp::Eq,
+            right: integer(4)
+        }
+    );
+}
+
+#[test]
+fn test_where_not_and_is_null() {
+    assert_eq!(
+        parse_where("not email is not null"),
+        Expr::Unary {
+            op: UnaryOp::Not,
+            expr: Box::new(Expr::IsNull {
+                expr: column("email"),
+                negated: true
+            })
+        }
+    );
+}
+
+#[test]
+fn test_where_literals() {
+    assert_eq!(
+        parse_where("username = 'rosh' or -1.5 < id or true"),
+        Expr::Binary {
+            left: Box::new(Expr::Binary {
+                left: Box::new(Expr::Binary {
+                    left: column("username"),
+                    op: BinaryOp::Eq,
+                    right: Box::new(Expr::Literal(Value::Text("rosh".to_string())))
+                }),
+                op: BinaryOp::Or,
+                right: Box::new(Expr::Binary {
+                    left: Box::new(Expr::Literal(Value::Real(-1.5))),
+                    op: BinaryOp::Lt,
+                    right: column("id")
+                })
+            }),
+            op: BinaryOp::Or,
+            right: Box::new(Expr::Literal(Value::Boolean(true)))
+        }
+    );
+}
+
+#[test]
+fn test_where_unbalanced_parentheses() {
+    let err = parse_error("select * from users where (id = 1");
+    assert_eq!(err.expected.last(), Some(&"')'".to_string()));
+}
+
+#[test]
+fn test_where_missing_operand() {
+    let err = parse_error("select * from users where id =");
+    assert_eq!(err.message, "expected expression, found end of input");
+}
+
+#[test]
+fn test_where_integer_out_of_range() {
+    let err = parse_error("select * from users where id = 99999999999999999999");
+    assert_eq!(err.message, "number 99999999999999999999 is out of range");
+}
+
+// Testing that the smallest integer parses, even though it is only in range with its sign.
+#[test]
+fn test_where_integer_min() {
+    assert_eq!(
+        parse_where("id = -9223372036854775808"),
+        Expr::Binary {
+            left: column("id"),
+            op: BinaryOp::Eq,
+            right: Box::new(Expr::Literal(Value::Integer(i64::MIN)))
+        }
+    );
+    assert_eq!(
+        parse_where("id = -(1)"),
+        Expr::Binary {
+            left: column("id"),
+            op: BinaryOp::Eq,
+            right: Box::new(Expr::Unary {
+                op: UnaryOp::Minus,
+                expr: Box::new(Expr::Literal(Value::Integer(1)))
+            })
+        }
+    );
+    let err = parse_error("select * from users where id = -9223372036854775809");
+    assert_eq!(err.message, "number -9223372036854775809 is out of range");
+}
+
+#[test]
+fn test_prepare_statement_update() {
+    let mut out_statement = Statement::default();
+    let cmd = "UPDATE users SET email = 'new@x.com', id = id + 1 WHERE id = 10";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+    assert_eq!(
+        out_statement,
+        Statement::Update(Update {
+            table: "users".to_string(),
+            assignments: vec![
+                Assignment {
+                    column: "email".to_string(),
+                    value: Expr::Literal(Value::Text("new@x.com".to_string()))
+                },
+                Assignment {
+                    column: "id".to_string(),
+                    value: Expr::Binary {
+                        left: column("id"),
+                        op: BinaryOp::Add,
+                        right: integer(1)
+                    }
+                }
+            ],
+            selection: Some(Expr::Binary {
+                left: column("id"),
+                op: BinaryOp::Eq,
+                right: integer(10)
+            })
+        })
+    );
+}
+
+#[test]
+fn test_prepare_statement_update_without_where() {
+    let mut out_statement = Statement::default();
+    let cmd = "update users set username = 'x'";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+    assert!(matches!(
+        out_statement,
+        Statement::Update(Update {
+            selection: None,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_prepare_statement_update_missing_set() {
+    let err = parse_error("update users username = 'x'");
+    assert_eq!(err.expected, vec!["SET".to_string()]);
+}
+
+#[test]
+fn test_prepare_statement_delete() {
+    let mut out_statement = Statement::default();
+    let cmd = "DELETE FROM users WHERE id = 10";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+    assert_eq!(
+        out_statement,
+        Statement::Delete(Delete {
+            table: "users".to_string(),
+            selection: Some(Expr::Binary {
+                left: column("id"),
+                op: BinaryOp::Eq,
+                right: integer(10)
+            })
+        })
+    );
+}
+
+#[test]
+fn test_prepare_statement_delete_missing_from() {
+    let err = parse_error("delete users");
+    assert_eq!(err.message, "expected FROM, found identifier 'users'");
+}
+
+#[test]
+fn test_prepare_statement_insert_columns() {
+    let mut out_statement = Statement::default();
+    let cmd = "insert into users (email, id) values ('ape@gmail.com', 1 + 1)";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+    assert_eq!(
+        out_statement,
+        Statement::Insert(Insert {
+            table: "users".to_string(),
+            columns: Some(vec!["email".to_string(), "id".to_string()]),
+            rows: vec![vec![
+                *text("ape@gmail.com"),
+                Expr::Binary {
+                    left: integer(1),
+                    op: BinaryOp::Add,
+                    right: integer(1)
+                }
+            ]]
+        })
+    );
+}
+
+#[test]
+fn test_prepare_statement_create_table() {
+    let mut out_statement = Statement::default();
+    let cmd =
+        "CREATE TABLE posts (id INTEGER NOT NULL, title VARCHAR, score real null, draft bool)";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+
+    let mut id = Column::new("id", DataType::Integer);
+    id.not_null = true;
+    assert_eq!(
+        out_statement,
+        Statement::CreateTable(CreateTable {
+            name: "posts".to_string(),
+            columns: vec![
+                id,
+                Column::new("title", DataType::Text),
+                Column::new("score", DataType::Real),
+                Column::new("draft", DataType::Boolean),
+            ]
+        })
+    );
+}
+
+#[test]
+fn test_prepare_statement_create_table_primary_key() {
+    let mut out_statement = Statement::default();
+    let cmd = "create table users (id integer primary key, name text not null)";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+
+    let mut id = Column::new("id", DataType::Integer);
+    id.primary_key = true;
+    id.not_null = true;
+    let mut name = Column::new("name", DataType::Text);
+    name.not_null = true;
+    assert_eq!(
+        out_statement,
+        Statement::CreateTable(CreateTable {
+            name: "users".to_string(),
+            columns: vec![id, name]
+        })
+    );
+}
+
+#[test]
+fn test_prepare_statement_create_table_primary_without_key() {
+    let err = parse_error("create table users (id integer primary, name text)");
+    assert_eq!(err.message, "expected KEY, found ','");
+}
+
+#[test]
+fn test_prepare_statement_create_table_primary_key_constraint() {
+    let mut out_statement = Statement::default();
+    let cmd = "create table users (primary key (id), id integer, name text)";
+    assert_eq!(
+        prepare_statement(cmd, &mut out_statement),
+        PrepareResult::Success
+    );
+
+    let mut id = Column::new("id", DataType::Integer);
+    id.primary_key = true;
+    id.not_null = true;
+    assert_eq!(
+        out_statement,
+        Statement::CreateTable(CreateTable {
+            name: "users".to_string(),
+            columns: vec![id, Column::new("name", DataType::Text)]
+        })
+    );
+}
+
+#[test]
+fn test_prepare_statement_create_table_primary_key_unknown_column() {
+    let err = parse_error("create table users (id integer, primary key (uid))");
+    assert_eq!(err.span, Span { start: 45, end: 48 });
+    assert_eq!(err.message, "unknown column 'uid' in PRIMARY KEY");
+}
+
+#[test]
+fn test_prepare_statement_create_table_unknown_type() {
+    let err = parse_error("create table posts (id blob)");
+    assert_eq!(err.span, Span { start: 23, end: 27 });
+    assert_eq!(err.message, "unknown column type 'blob'");
+}
+
+#[test]
+fn test_prepare_statement_create_table_missing_type() {
+    let err = parse_error("create table posts (id, title text)");
+    assert_eq!(err.message, "expected column type, found ','");
+}
+
+#[test]
+fn test_prepare_statement_create_table_no_columns() {
+    let err = parse_error("create table posts ()");
+    assert_eq!(err.message, "expected PRIMARY or identifier, found ')'");
 }
 
 #[test]
@@ -150,3 +932,192 @@ fn test_prepare_statement_whitespace_only() {
     let out_result = prepare_statement(cmd, &mut out_statement);
     assert_eq!(out_result, PrepareResult::Unrecognized);
 }
+
+/// Helper method to get the error out of a statement we expect to fail.
+fn parse_error(cmd: &str) -> ParseError {
+    match prepare_statement(cmd, &mut Statement::default()) {
+        PrepareResult::SyntaxError(err) => err,
+        other => panic!("Expected a syntax error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_error_span_and_expected() {
+    let err = parse_error("insert into users valuse (1, 'a', 'b')");
+    assert_eq!(err.span, Span { start: 18, end: 24 });
+    assert_eq!(err.expected, vec!["'('".to_string(), "VALUES".to_string()]);
+    assert_eq!(
+        err.message,
+        "expected '(' or VALUES, found identifier 'valuse'"
+    );
+}
+
+#[test]
+fn test_parse_error_lists_every_expected_token() {
+    let err = parse_error("select * from users u v");
+    assert_eq!(
+        err.expected,
+        vec![
+            "INNER".to_string(),
+            "JOIN".to_string(),
+            "LEFT".to_string(),
+            "RIGHT".to_string(),
+            "FULL".to_string(),
+            "WHERE".to_string(),
+            "GROUP".to_string(),
+            "HAVING".to_string(),
+            "ORDER".to_string(),
+            "LIMIT".to_string(),
+            "';'".to_string(),
+            "end of statement".to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_parse_error_at_end_of_input() {
+    let cmd = "insert into users values (1, 'a'";
+    let err = parse_error(cmd);
+    assert_eq!(err.span, Span { start: 32, end: 32 });
+    assert_eq!(
+        err.message,
+        "expected IS, comparison operator, AND, OR, ',' or ')', found end of input"
+    );
+}
+
+#[test]
+fn test_parse_error_from_lexer() {
+    let err = parse_error("select * from users where id = 'oops");
+    assert_eq!(err.span, Span { start: 31, end: 36 });
+    assert_eq!(err.message, "unterminated string literal");
+    assert!(err.expected.is_empty());
+}
+
+#[test]
+fn test_parse_error_line_column() {
+    let cmd = "select *\nfrom users\nwhere id = )";
+    let err = parse_error(cmd);
+    assert_eq!(err.line_column(cmd), (3, 12));
+}
+
+#[test]
+fn test_parse_error_render() {
+    let cmd = "insert into users valuse (1, 'a', 'b')";
+    let err = parse_error(cmd);
+    let expected = [
+        "error: expected '(' or VALUES, found identifier 'valuse'",
+        "  --> line 1, column 19",
+        "  |",
+        "1 | insert into users valuse (1, 'a', 'b')",
+        "  |                   ^^^^^^",
+    ];
+    assert_eq!(err.render(cmd), expected.join("\n"));
+}
+
+#[test]
+fn test_parse_error_render_end_of_input() {
+    let cmd = "select * from";
+    let err = parse_error(cmd);
+    assert!(err
+        .render(cmd)
+        .ends_with("1 | select * from\n  |              ^"));
+}
+
+#[test]
+fn test_prepare_statement_drop_table() {
+    let mut out_statement = Statement::default();
+    let cmd = "DROP TABLE users;";
+    let out_result = prepare_statement(cmd, &mut out_statement);
+    assert_eq!(out_result, PrepareResult::Success);
+    assert_eq!(
+        out_statement,
+        Statement::DropTable(DropTable {
+            name: "users".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_prepare_statement_drop_without_table() {
+    let err = parse_error("drop users");
+    assert_eq!(err.message, "expected TABLE, found identifier 'users'");
+}
+
+#[test]
+fn test_prepare_statement_transactions() {
+    let cases = [
+        ("begin", Statement::Begin),
+        ("BEGIN TRANSACTION;", Statement::Begin),
+        ("commit", Statement::Commit),
+        ("commit transaction", Statement::Commit),
+        ("rollback;", Statement::Rollback),
+        ("ROLLBACK TRANSACTION", Statement::Rollback),
+    ];
+    for (cmd, expected) in cases {
+        let mut out_statement = Statement::default();
+        assert_eq!(
+            prepare_statement(cmd, &mut out_statement),
+            PrepareResult::Success
+        );
+        assert_eq!(out_statement, expected);
+    }
+}
+
+#[test]
+fn test_prepare_statement_transaction_trailing_tokens() {
+    let err = parse_error("begin work");
+    assert_eq!(
+        err.message,
+        "expected TRANSACTION, ';' or end of statement, found identifier 'work'"
+    );
+}
+
+#[test]
+fn test_prepare_statement_savepoints() {
+    let cases = [
+        ("savepoint load", Statement::Savepoint("load".to_string())),
+        ("RELEASE load;", Statement::Release("load".to_string())),
+        (
+            "release savepoint load",
+            Statement::Release("load".to_string()),
+        ),
+        (
+            "rollback to load",
+            Statement::RollbackTo("load".to_string()),
+        ),
+        (
+            "ROLLBACK TRANSACTION TO SAVEPOINT load",
+            Statement::RollbackTo("load".to_string()),
+        ),
+    ];
+    for (cmd, expected) in cases {
+        let mut out_statement = Statement::default();
+        assert_eq!(
+            prepare_statement(cmd, &mut out_statement),
+            PrepareResult::Success
+        );
+        assert_eq!(out_statement, expected);
+    }
+}
+
+#[test]
+fn test_prepare_statement_savepoint_without_name() {
+    let err = parse_error("rollback to");
+    assert_eq!(
+        err.message,
+        "expected SAVEPOINT or identifier, found end of input"
+    );
+    let err = parse_error("savepoint;");
+    assert_eq!(err.message, "expected identifier, found ';'");
+}
+
+#[test]
+fn test_prepare_returns_result() {
+    let statement = prepare("select * from users").unwrap();
+    assert!(matches!(statement, Statement::Select(_)));
+
+    // Input that isn't a statement at all is a syntax error like any other.
+    let err = prepare("dummy").unwrap_err();
+    assert!(matches!(err, Error::Parse(_)));
+    assert!(err.to_string().starts_with("expected INSERT, SELECT"));
+}